@@ -1,6 +1,13 @@
 MEMORY
 {
 BOOT2   : ORIGIN = 0x10000000, LENGTH = 0x100
-FLASH : ORIGIN = 0x10000100, LENGTH = 2048K - 0x100
+FLASH : ORIGIN = 0x10000100, LENGTH = 2048K - 0x100 - 8K
+/* KEYMAP_OFFSET and SETTINGS_OFFSET in main.rs, kept out of the reach of the firmware image */
+KEYMAP : ORIGIN = 0x10000000 + 2048K - 8K, LENGTH = 4K
+SETTINGS : ORIGIN = 0x10000000 + 2048K - 4K, LENGTH = 4K
   RAM : ORIGIN = 0x20000000, LENGTH = 264K
 }
+
+/* the image ends with the veneers, after the initial values of .data */
+ASSERT(__veneer_limit <= ORIGIN(KEYMAP), "
+ERROR: the firmware image runs into the keymap sector saved at runtime");
@@ -0,0 +1,3 @@
+pub mod protocol;
+pub mod via;
+pub mod via_keycodes;
@@ -0,0 +1,331 @@
+//! The configuration channel: a vendor-defined raw HID interface over which the host reads and
+//! changes the keymap of the [KeymapStore] without reflashing the firmware.
+//!
+//! The host sends requests and the firmware answers each one with a response, both reports of
+//! [REPORT_SIZE] bytes. A request is a command byte followed by its arguments; the response
+//! repeats the command byte, then a status byte and the results of the command. Values of two
+//! bytes are little endian, the bytes past the arguments and the results are zero.
+//!
+//! | Command | Arguments | Results |
+//! |---|---|---|
+//! | `0x01` get protocol version | | version (2 bytes) |
+//! | `0x02` get matrix size | | layers, rows, columns, positions |
+//! | `0x03` get keycode | layer, position | action (3 bytes) |
+//! | `0x04` set keycode | layer, position, action (3 bytes) | |
+//! | `0x05` get macro buffer size | | size (2 bytes) |
+//! | `0x06` get macro bytes | offset (2 bytes), size | bytes |
+//! | `0x07` set macro bytes | offset (2 bytes), size, bytes | |
+//! | `0x08` save to flash | | |
+//! | `0x09` reboot to bootloader | | |
+//! | `0x0A` reset the keymap to the one of the profile | | |
+//!
+//! Positions are the [KeyPosition] indices, which also read as a matrix: position
+//! `row * columns + column`. Actions are encoded as in a
+//! [KeymapBlob](crate::profiles_management::keymap_blob::KeymapBlob), a macro action carrying
+//! the offset of its macro in the macro buffer. Up to [MAX_MACRO_CHUNK] bytes of the macro buffer
+//! are read or written a request.
+//!
+//! The changes are live as soon as they are made, and kept across restarts once saved. Until the
+//! first change the keymap is the one of the profile. An action is only set once the macro it
+//! plays is complete in the macro buffer, and the keymap is only saved while all its macros are,
+//! so that the saved keymap always loads back.
+use crate::{
+    io_management::key_position::{KeyPosition, MATRIX_COLUMNS, MATRIX_ROWS, POSITIONS},
+    profiles_management::{
+        keyboard_profile::keyboard_profile::Keymap,
+        keymap_blob::ACTION_SIZE,
+        keymap_store::{KeymapStore, POOL_CAPACITY},
+        layers::LAYER_COUNT,
+    },
+};
+
+/// Number of bytes of a request and of a response.
+pub const REPORT_SIZE: usize = 64;
+pub const PROTOCOL_VERSION: u16 = 1;
+/// Most bytes of the macro buffer read or written by one request.
+pub const MAX_MACRO_CHUNK: usize = REPORT_SIZE - 4;
+
+// commands
+const GET_PROTOCOL_VERSION: u8 = 0x01;
+const GET_MATRIX_SIZE: u8 = 0x02;
+const GET_KEYCODE: u8 = 0x03;
+const SET_KEYCODE: u8 = 0x04;
+const GET_MACRO_BUFFER_SIZE: u8 = 0x05;
+const GET_MACRO_BYTES: u8 = 0x06;
+const SET_MACRO_BYTES: u8 = 0x07;
+const SAVE: u8 = 0x08;
+const REBOOT_TO_BOOTLOADER: u8 = 0x09;
+const RESET_KEYMAP: u8 = 0x0A;
+
+// statuses
+pub const STATUS_OK: u8 = 0x00;
+pub const STATUS_UNKNOWN_COMMAND: u8 = 0x01;
+/// The arguments are out of range, or the action cannot be carried out.
+pub const STATUS_INVALID_ARGUMENTS: u8 = 0x02;
+/// The keymap cannot be saved or loaded into the store.
+pub const STATUS_FAILED: u8 = 0x03;
+
+/// Offset of the status in a response.
+const STATUS: usize = 1;
+/// Offset of the arguments in a request and of the results in a response.
+const ARGUMENTS: usize = 1;
+const RESULTS: usize = 2;
+
+/// The report descriptor of the interface: one vendor-defined input and one output report of
+/// [REPORT_SIZE] (0x40) bytes, without a report id. Usage page 0xFF00 and usage 0x01, the usage
+/// page 0xFF60 being the one of the [VIA interface](super::via), then usage 0x02 for the input
+/// and 0x03 for the output.
+pub const REPORT_DESCRIPTOR: &[u8] = &[
+    0x06, 0x00, 0xFF, 0x09, 0x01, 0xA1, 0x01, 0x09, 0x02, 0x15, 0x00, 0x26, 0xFF, 0x00, 0x95, 0x40,
+    0x75, 0x08, 0x81, 0x02, 0x09, 0x03, 0x15, 0x00, 0x26, 0xFF, 0x00, 0x95, 0x40, 0x75, 0x08, 0x91,
+    0x02, 0xC0,
+];
+
+/// What the firmware has left to do once the response has been sent.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ConfigFollowUp {
+    Nothing,
+    RebootToBootloader,
+}
+
+/// Carries out the request on the store and returns the response, along with what is left to do
+/// once it has been sent. The store starts out as the `defaults` keymap, i.e. the one of the
+/// profile, if nothing has been loaded into it.
+pub fn dispatch(
+    request: &[u8],
+    store: &mut KeymapStore,
+    defaults: &Keymap,
+) -> ([u8; REPORT_SIZE], ConfigFollowUp) {
+    let mut response = [0; REPORT_SIZE];
+    let command = match request.first() {
+        Some(command) => *command,
+        None => {
+            response[STATUS] = STATUS_INVALID_ARGUMENTS;
+            return (response, ConfigFollowUp::Nothing);
+        }
+    };
+    response[0] = command;
+    let arguments = request.get(ARGUMENTS..).unwrap_or(&[]);
+    let results = &mut response[RESULTS..];
+    let (status, follow_up) = match command {
+        GET_PROTOCOL_VERSION => {
+            results[..2].copy_from_slice(&PROTOCOL_VERSION.to_le_bytes());
+            (STATUS_OK, ConfigFollowUp::Nothing)
+        }
+        GET_MATRIX_SIZE => {
+            results[..4].copy_from_slice(&[
+                LAYER_COUNT,
+                MATRIX_ROWS as u8,
+                MATRIX_COLUMNS as u8,
+                POSITIONS as u8,
+            ]);
+            (STATUS_OK, ConfigFollowUp::Nothing)
+        }
+        GET_MACRO_BUFFER_SIZE => {
+            results[..2].copy_from_slice(&(POOL_CAPACITY as u16).to_le_bytes());
+            (STATUS_OK, ConfigFollowUp::Nothing)
+        }
+        REBOOT_TO_BOOTLOADER => (STATUS_OK, ConfigFollowUp::RebootToBootloader),
+        RESET_KEYMAP => match store.reset(defaults) {
+            true => (STATUS_OK, ConfigFollowUp::Nothing),
+            false => (STATUS_FAILED, ConfigFollowUp::Nothing),
+        },
+        GET_KEYCODE | SET_KEYCODE | GET_MACRO_BYTES | SET_MACRO_BYTES | SAVE => {
+            if !store.is_loaded() && !store.reset(defaults) {
+                (STATUS_FAILED, ConfigFollowUp::Nothing)
+            } else {
+                edit_store(command, arguments, results, store)
+            }
+        }
+        _ => (STATUS_UNKNOWN_COMMAND, ConfigFollowUp::Nothing),
+    };
+    response[STATUS] = status;
+    return (response, follow_up);
+}
+
+/// Carries out a command reading or changing the keymap of the store, which is loaded.
+fn edit_store(
+    command: u8,
+    arguments: &[u8],
+    results: &mut [u8],
+    store: &mut KeymapStore,
+) -> (u8, ConfigFollowUp) {
+    let done = match command {
+        GET_KEYCODE => match key(arguments)
+            .and_then(|(layer, position)| store.encoded_action(layer, position))
+        {
+            Some(action) => {
+                results[..ACTION_SIZE].copy_from_slice(&action);
+                true
+            }
+            None => false,
+        },
+        SET_KEYCODE => match (key(arguments), arguments.get(2..2 + ACTION_SIZE)) {
+            (Some((layer, position)), Some(action)) => {
+                let mut encoded = [0; ACTION_SIZE];
+                encoded.copy_from_slice(action);
+                store.set_encoded_action(layer, position, encoded)
+            }
+            _ => false,
+        },
+        GET_MACRO_BYTES => match macro_chunk(arguments) {
+            Some((offset, size)) => match store.pool().get(offset..offset + size) {
+                Some(bytes) => {
+                    results[..size].copy_from_slice(bytes);
+                    true
+                }
+                None => false,
+            },
+            None => false,
+        },
+        SET_MACRO_BYTES => match macro_chunk(arguments) {
+            Some((offset, size)) => match arguments.get(3..3 + size) {
+                Some(bytes) => store.write_pool(offset, bytes),
+                None => false,
+            },
+            None => false,
+        },
+        SAVE => {
+            if !store.request_save() {
+                return (STATUS_FAILED, ConfigFollowUp::Nothing);
+            }
+            return (STATUS_OK, ConfigFollowUp::Nothing);
+        }
+        _ => return (STATUS_UNKNOWN_COMMAND, ConfigFollowUp::Nothing),
+    };
+    match done {
+        true => return (STATUS_OK, ConfigFollowUp::Nothing),
+        false => return (STATUS_INVALID_ARGUMENTS, ConfigFollowUp::Nothing),
+    }
+}
+
+/// Reads the layer and the position starting the arguments.
+fn key(arguments: &[u8]) -> Option<(u8, KeyPosition)> {
+    let layer = *arguments.first()?;
+    if layer >= LAYER_COUNT {
+        return None;
+    }
+    let position = KeyPosition::from_index(*arguments.get(1)? as usize)?;
+    return Some((layer, position));
+}
+
+/// Reads the offset and the size of the bytes of the macro buffer starting the arguments.
+fn macro_chunk(arguments: &[u8]) -> Option<(usize, usize)> {
+    let offset = u16::from_le_bytes([*arguments.first()?, *arguments.get(1)?]) as usize;
+    let size = *arguments.get(2)? as usize;
+    if size > MAX_MACRO_CHUNK {
+        return None;
+    }
+    return Some((offset, size));
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        io_management::left_half_manager::LeftKeyLocation,
+        profiles_management::profiles::profile_1::profile_1::get_profile,
+    };
+
+    use super::*;
+
+    fn request(bytes: &[u8]) -> [u8; REPORT_SIZE] {
+        let mut request = [0; REPORT_SIZE];
+        request[..bytes.len()].copy_from_slice(bytes);
+        return request;
+    }
+
+    #[test]
+    fn describes_the_keyboard() {
+        let keymap = get_profile().keymap;
+        let mut store = KeymapStore::new();
+        let (response, _) = dispatch(&request(&[GET_PROTOCOL_VERSION]), &mut store, &keymap);
+        assert_eq!(response[..4], [GET_PROTOCOL_VERSION, STATUS_OK, 1, 0]);
+        let (response, _) = dispatch(&request(&[GET_MATRIX_SIZE]), &mut store, &keymap);
+        assert_eq!(response[..6], [GET_MATRIX_SIZE, STATUS_OK, 4, 4, 12, 42]);
+        let (response, _) = dispatch(&request(&[0x7F]), &mut store, &keymap);
+        assert_eq!(response[..2], [0x7F, STATUS_UNKNOWN_COMMAND]);
+        // nothing has been asked of the keymap yet
+        assert!(!store.is_loaded());
+    }
+
+    #[test]
+    fn reads_and_changes_the_keymap() {
+        let keymap = get_profile().keymap;
+        let mut store = KeymapStore::new();
+        let position = KeyPosition::left(LeftKeyLocation::C2R1).index() as u8;
+        // the profile binds Q there
+        let (response, _) = dispatch(&request(&[GET_KEYCODE, 0, position]), &mut store, &keymap);
+        assert_eq!(response[..5], [GET_KEYCODE, STATUS_OK, 0x01, 0x14, 0x00]);
+
+        // a macro tapping Z, then the key playing it
+        let offset = (POOL_CAPACITY as u16 - 3).to_le_bytes();
+        let set_macro = [SET_MACRO_BYTES, offset[0], offset[1], 3, 0x03, 0x1D, 0x00];
+        let (response, _) = dispatch(&request(&set_macro), &mut store, &keymap);
+        assert_eq!(response[..2], [SET_MACRO_BYTES, STATUS_OK]);
+        let set_key = [SET_KEYCODE, 0, position, 0x02, offset[0], offset[1]];
+        let (response, _) = dispatch(&request(&set_key), &mut store, &keymap);
+        assert_eq!(response[..2], [SET_KEYCODE, STATUS_OK]);
+
+        let get_macro = [GET_MACRO_BYTES, offset[0], offset[1], 3];
+        let (response, _) = dispatch(&request(&get_macro), &mut store, &keymap);
+        assert_eq!(
+            response[..5],
+            [GET_MACRO_BYTES, STATUS_OK, 0x03, 0x1D, 0x00]
+        );
+        let (response, _) = dispatch(&request(&[GET_KEYCODE, 0, position]), &mut store, &keymap);
+        assert_eq!(
+            response[..5],
+            [GET_KEYCODE, STATUS_OK, 0x02, offset[0], offset[1]]
+        );
+        let (response, _) = dispatch(&request(&[SAVE]), &mut store, &keymap);
+        assert_eq!(response[..2], [SAVE, STATUS_OK]);
+        assert!(store.take_save_request());
+
+        dispatch(&request(&[RESET_KEYMAP]), &mut store, &keymap);
+        let (response, _) = dispatch(&request(&[GET_KEYCODE, 0, position]), &mut store, &keymap);
+        assert_eq!(response[..5], [GET_KEYCODE, STATUS_OK, 0x01, 0x14, 0x00]);
+    }
+
+    #[test]
+    fn saves_only_a_keymap_that_loads_back() {
+        let keymap = get_profile().keymap;
+        let mut store = KeymapStore::new();
+        let offset = (POOL_CAPACITY as u16 - 3).to_le_bytes();
+        let set_macro = [SET_MACRO_BYTES, offset[0], offset[1], 3, 0x03, 0x1D, 0x00];
+        dispatch(&request(&set_macro), &mut store, &keymap);
+        dispatch(
+            &request(&[SET_KEYCODE, 0, 0, 0x02, offset[0], offset[1]]),
+            &mut store,
+            &keymap,
+        );
+        // the end of the macro is overwritten, it now runs past the end of the macro buffer
+        let end = (POOL_CAPACITY as u16 - 1).to_le_bytes();
+        let (response, _) = dispatch(
+            &request(&[SET_MACRO_BYTES, end[0], end[1], 1, 0x03]),
+            &mut store,
+            &keymap,
+        );
+        assert_eq!(response[..2], [SET_MACRO_BYTES, STATUS_OK]);
+        let (response, _) = dispatch(&request(&[SAVE]), &mut store, &keymap);
+        assert_eq!(response[..2], [SAVE, STATUS_FAILED]);
+        assert!(!store.take_save_request());
+    }
+
+    #[test]
+    fn refuses_requests_out_of_range() {
+        let keymap = get_profile().keymap;
+        let mut store = KeymapStore::new();
+        let requests: [&[u8]; 5] = [
+            &[GET_KEYCODE, LAYER_COUNT, 0],
+            &[GET_KEYCODE, 0, POSITIONS as u8],
+            &[SET_KEYCODE, 0, 0, 0xFF, 0, 0],
+            &[GET_MACRO_BYTES, 0, 0, MAX_MACRO_CHUNK as u8 + 1],
+            &[SET_MACRO_BYTES, 0xFF, 0xFF, 2, 0, 0],
+        ];
+        for bytes in requests {
+            let (response, follow_up) = dispatch(&request(bytes), &mut store, &keymap);
+            assert_eq!(response[..2], [bytes[0], STATUS_INVALID_ARGUMENTS]);
+            assert_eq!(follow_up, ConfigFollowUp::Nothing);
+        }
+    }
+}
@@ -0,0 +1,327 @@
+//! The VIA protocol, for the keymap of the [KeymapStore] to be edited from the VIA desktop app
+//! with the keyboard definition of `via/keyboard.json`. It is served on a raw HID interface of
+//! its own, next to the one of the [protocol](super::protocol) of the firmware.
+//!
+//! A request is a report of [VIA_REPORT_SIZE] bytes starting with the command, and the response
+//! is the request with its results written over the arguments, as QMK answers. A request the
+//! firmware does not handle comes back with its command replaced by 0xFF. Values of two bytes are
+//! big endian.
+//!
+//! The matrix of the keymap is the one of [KeyPosition]: 4 rows of 12 keys, the thumb keys
+//! starting the last row, whose other keys read as `KC_NO` and cannot be set. The keycodes are
+//! translated to the actions of the firmware by [via_keycodes](super::via_keycodes), a keycode
+//! the firmware cannot carry out leaves the key as it was. The macros of VIA are kept in the VIA
+//! macro buffer of the store, see [KeymapStore::via_macro_buffer].
+//!
+//! The changes are saved as they are made, the firmware writing them to flash once it takes the
+//! save request of the store. Resetting the keymap restores the one of the profile, along with
+//! the macros.
+use crate::{
+    io_management::key_position::{KeyPosition, MATRIX_COLUMNS, MATRIX_ROWS},
+    macros::via_macro::VIA_MACRO_COUNT,
+    profiles_management::{
+        keyboard_profile::keyboard_profile::Keymap,
+        keymap_store::{KeymapStore, VIA_MACRO_BUFFER_SIZE, VIA_MACRO_BUFFER_START},
+        layers::LAYER_COUNT,
+    },
+};
+
+use super::{
+    protocol::ConfigFollowUp,
+    via_keycodes::{from_keycode, to_keycode},
+};
+
+/// Number of bytes of a request and of a response.
+pub const VIA_REPORT_SIZE: usize = 32;
+pub const VIA_PROTOCOL_VERSION: u16 = 0x000C;
+/// Most bytes of the keymap or of the macro buffer read or written by one request.
+const MAX_BUFFER_CHUNK: usize = VIA_REPORT_SIZE - 4;
+
+// commands
+const GET_PROTOCOL_VERSION: u8 = 0x01;
+const GET_KEYBOARD_VALUE: u8 = 0x02;
+const SET_KEYBOARD_VALUE: u8 = 0x03;
+const DYNAMIC_KEYMAP_GET_KEYCODE: u8 = 0x04;
+const DYNAMIC_KEYMAP_SET_KEYCODE: u8 = 0x05;
+const DYNAMIC_KEYMAP_RESET: u8 = 0x06;
+const BOOTLOADER_JUMP: u8 = 0x0B;
+const DYNAMIC_KEYMAP_MACRO_GET_COUNT: u8 = 0x0C;
+const DYNAMIC_KEYMAP_MACRO_GET_BUFFER_SIZE: u8 = 0x0D;
+const DYNAMIC_KEYMAP_MACRO_GET_BUFFER: u8 = 0x0E;
+const DYNAMIC_KEYMAP_MACRO_SET_BUFFER: u8 = 0x0F;
+const DYNAMIC_KEYMAP_MACRO_RESET: u8 = 0x10;
+const DYNAMIC_KEYMAP_GET_LAYER_COUNT: u8 = 0x11;
+const DYNAMIC_KEYMAP_GET_BUFFER: u8 = 0x12;
+const DYNAMIC_KEYMAP_SET_BUFFER: u8 = 0x13;
+/// Replaces the command of a request the firmware does not handle.
+const UNHANDLED: u8 = 0xFF;
+
+/// The keyboard value of the layout options, the layout has none.
+const LAYOUT_OPTIONS: u8 = 0x02;
+
+/// Number of bytes of the keycodes of a layer, the keys past the last position included.
+const LAYER_SIZE: usize = MATRIX_ROWS * MATRIX_COLUMNS * 2;
+
+/// The report descriptor of the interface: one vendor-defined input and one output report of
+/// [VIA_REPORT_SIZE] (0x20) bytes, without a report id, on usage page 0xFF60 and usage 0x61 the
+/// VIA app looks for.
+pub const VIA_REPORT_DESCRIPTOR: &[u8] = &[
+    0x06, 0x60, 0xFF, 0x09, 0x61, 0xA1, 0x01, 0x09, 0x62, 0x15, 0x00, 0x26, 0xFF, 0x00, 0x95, 0x20,
+    0x75, 0x08, 0x81, 0x02, 0x09, 0x63, 0x15, 0x00, 0x26, 0xFF, 0x00, 0x95, 0x20, 0x75, 0x08, 0x91,
+    0x02, 0xC0,
+];
+
+/// Carries out the request on the store and returns the response, along with what is left to do
+/// once it has been sent. The store starts out as the `defaults` keymap, i.e. the one of the
+/// profile, if nothing has been loaded into it.
+pub fn dispatch(
+    request: &[u8],
+    store: &mut KeymapStore,
+    defaults: &Keymap,
+) -> ([u8; VIA_REPORT_SIZE], ConfigFollowUp) {
+    let mut response = [0; VIA_REPORT_SIZE];
+    let size = request.len().min(VIA_REPORT_SIZE);
+    response[..size].copy_from_slice(&request[..size]);
+    if !store.is_loaded() && !store.reset(defaults) {
+        response[0] = UNHANDLED;
+        return (response, ConfigFollowUp::Nothing);
+    }
+    let (command, data) = response.split_at_mut(1);
+    match command[0] {
+        GET_PROTOCOL_VERSION => data[..2].copy_from_slice(&VIA_PROTOCOL_VERSION.to_be_bytes()),
+        GET_KEYBOARD_VALUE if data[0] == LAYOUT_OPTIONS => data[1..5].fill(0),
+        SET_KEYBOARD_VALUE if data[0] == LAYOUT_OPTIONS => {}
+        DYNAMIC_KEYMAP_GET_KEYCODE => {
+            let keycode = keycode(store, data[0], data[1] as usize, data[2] as usize);
+            data[3..5].copy_from_slice(&keycode.to_be_bytes());
+        }
+        DYNAMIC_KEYMAP_SET_KEYCODE => {
+            let keycode = u16::from_be_bytes([data[3], data[4]]);
+            set_keycode(store, data[0], data[1] as usize, data[2] as usize, keycode);
+            store.request_save();
+        }
+        DYNAMIC_KEYMAP_RESET => {
+            store.reset(defaults);
+            store.request_save();
+        }
+        BOOTLOADER_JUMP => return (response, ConfigFollowUp::RebootToBootloader),
+        DYNAMIC_KEYMAP_MACRO_GET_COUNT => data[0] = VIA_MACRO_COUNT,
+        DYNAMIC_KEYMAP_MACRO_GET_BUFFER_SIZE => {
+            data[..2].copy_from_slice(&(VIA_MACRO_BUFFER_SIZE as u16).to_be_bytes());
+        }
+        DYNAMIC_KEYMAP_MACRO_GET_BUFFER => match chunk(data, VIA_MACRO_BUFFER_SIZE) {
+            Some((offset, size)) => {
+                let buffer = &store.via_macro_buffer()[offset..offset + size];
+                data[3..3 + size].copy_from_slice(buffer);
+            }
+            None => command[0] = UNHANDLED,
+        },
+        DYNAMIC_KEYMAP_MACRO_SET_BUFFER => match chunk(data, VIA_MACRO_BUFFER_SIZE) {
+            Some((offset, size)) => {
+                store.write_pool(VIA_MACRO_BUFFER_START + offset, &data[3..3 + size]);
+                store.request_save();
+            }
+            None => command[0] = UNHANDLED,
+        },
+        DYNAMIC_KEYMAP_MACRO_RESET => {
+            store.write_pool(VIA_MACRO_BUFFER_START, &[0; VIA_MACRO_BUFFER_SIZE]);
+            store.request_save();
+        }
+        DYNAMIC_KEYMAP_GET_LAYER_COUNT => data[0] = LAYER_COUNT,
+        DYNAMIC_KEYMAP_GET_BUFFER => match chunk(data, LAYER_COUNT as usize * LAYER_SIZE) {
+            Some((offset, size)) if offset % 2 == 0 && size % 2 == 0 => {
+                for index in 0..size / 2 {
+                    let (layer, row, column) = matrix_key(offset / 2 + index);
+                    let keycode = keycode(store, layer, row, column);
+                    data[3 + 2 * index..5 + 2 * index].copy_from_slice(&keycode.to_be_bytes());
+                }
+            }
+            _ => command[0] = UNHANDLED,
+        },
+        DYNAMIC_KEYMAP_SET_BUFFER => match chunk(data, LAYER_COUNT as usize * LAYER_SIZE) {
+            Some((offset, size)) if offset % 2 == 0 && size % 2 == 0 => {
+                for index in 0..size / 2 {
+                    let (layer, row, column) = matrix_key(offset / 2 + index);
+                    let keycode = u16::from_be_bytes([data[3 + 2 * index], data[4 + 2 * index]]);
+                    set_keycode(store, layer, row, column, keycode);
+                }
+                store.request_save();
+            }
+            _ => command[0] = UNHANDLED,
+        },
+        _ => command[0] = UNHANDLED,
+    }
+    return (response, ConfigFollowUp::Nothing);
+}
+
+/// Returns the keycode of the key of the matrix on the layer, `KC_NO` past the last position.
+fn keycode(store: &KeymapStore, layer: u8, row: usize, column: usize) -> u16 {
+    match matrix_position(layer, row, column).and_then(|position| store.action(layer, position)) {
+        Some(action) => return to_keycode(action),
+        None => return 0,
+    }
+}
+
+/// Replaces the action of the key of the matrix on the layer, if the firmware can carry the
+/// keycode out.
+fn set_keycode(store: &mut KeymapStore, layer: u8, row: usize, column: usize, keycode: u16) {
+    if let (Some(position), Some(action)) =
+        (matrix_position(layer, row, column), from_keycode(keycode))
+    {
+        store.set_action(layer, position, action);
+    }
+}
+
+fn matrix_position(layer: u8, row: usize, column: usize) -> Option<KeyPosition> {
+    if layer >= LAYER_COUNT || row >= MATRIX_ROWS || column >= MATRIX_COLUMNS {
+        return None;
+    }
+    return KeyPosition::from_index(row * MATRIX_COLUMNS + column);
+}
+
+/// Returns the layer, the row and the column of the keycode at the index of the keymap, which
+/// holds the layers one after the other, each one row by row.
+fn matrix_key(index: usize) -> (u8, usize, usize) {
+    let keys = MATRIX_ROWS * MATRIX_COLUMNS;
+    let key = index % keys;
+    return (
+        (index / keys) as u8,
+        key / MATRIX_COLUMNS,
+        key % MATRIX_COLUMNS,
+    );
+}
+
+/// Reads the offset and the size of the bytes of a buffer of `buffer_size` bytes starting the
+/// arguments, [None] if they do not fit in the buffer or in a report.
+fn chunk(arguments: &[u8], buffer_size: usize) -> Option<(usize, usize)> {
+    let offset = u16::from_be_bytes([arguments[0], arguments[1]]) as usize;
+    let size = arguments[2] as usize;
+    if size > MAX_BUFFER_CHUNK || offset + size > buffer_size {
+        return None;
+    }
+    return Some((offset, size));
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        io_management::left_half_manager::LeftKeyLocation,
+        profiles_management::profiles::profile_1::profile_1::get_profile,
+    };
+
+    use super::*;
+
+    fn request(bytes: &[u8]) -> [u8; VIA_REPORT_SIZE] {
+        let mut request = [0; VIA_REPORT_SIZE];
+        request[..bytes.len()].copy_from_slice(bytes);
+        return request;
+    }
+
+    #[test]
+    fn describes_the_keyboard() {
+        let keymap = get_profile().keymap;
+        let mut store = KeymapStore::new();
+        let (response, _) = dispatch(&request(&[GET_PROTOCOL_VERSION]), &mut store, &keymap);
+        assert_eq!(response[..3], [GET_PROTOCOL_VERSION, 0x00, 0x0C]);
+        let (response, _) = dispatch(
+            &request(&[DYNAMIC_KEYMAP_GET_LAYER_COUNT]),
+            &mut store,
+            &keymap,
+        );
+        assert_eq!(response[..2], [DYNAMIC_KEYMAP_GET_LAYER_COUNT, 4]);
+        let (response, _) = dispatch(
+            &request(&[DYNAMIC_KEYMAP_MACRO_GET_COUNT]),
+            &mut store,
+            &keymap,
+        );
+        assert_eq!(response[..2], [DYNAMIC_KEYMAP_MACRO_GET_COUNT, 16]);
+        let (response, _) = dispatch(&request(&[0x08, 0x01, 0x02]), &mut store, &keymap);
+        assert_eq!(response[..3], [UNHANDLED, 0x01, 0x02]);
+        let (_, follow_up) = dispatch(&request(&[BOOTLOADER_JUMP]), &mut store, &keymap);
+        assert_eq!(follow_up, ConfigFollowUp::RebootToBootloader);
+        assert!(!store.take_save_request());
+    }
+
+    #[test]
+    fn reads_and_changes_the_keymap() {
+        let keymap = get_profile().keymap;
+        let mut store = KeymapStore::new();
+        // the profile binds Q at row 0, column 1
+        let get = [DYNAMIC_KEYMAP_GET_KEYCODE, 0, 0, 1];
+        let (response, _) = dispatch(&request(&get), &mut store, &keymap);
+        assert_eq!(
+            response[..6],
+            [DYNAMIC_KEYMAP_GET_KEYCODE, 0, 0, 1, 0x00, 0x14]
+        );
+
+        // Caps Word there, then KC_TRANSPARENT the firmware does not have
+        let set = [DYNAMIC_KEYMAP_SET_KEYCODE, 0, 0, 1, 0x7C, 0x73];
+        dispatch(&request(&set), &mut store, &keymap);
+        let set = [DYNAMIC_KEYMAP_SET_KEYCODE, 0, 0, 1, 0x00, 0x01];
+        dispatch(&request(&set), &mut store, &keymap);
+        let (response, _) = dispatch(&request(&get), &mut store, &keymap);
+        assert_eq!(response[4..6], [0x7C, 0x73]);
+        assert!(store.take_save_request());
+
+        // the second key of the second layer, through the buffer
+        let offset = (LAYER_SIZE as u16 + 2).to_be_bytes();
+        let set = [
+            DYNAMIC_KEYMAP_SET_BUFFER,
+            offset[0],
+            offset[1],
+            2,
+            0x00,
+            0x1D,
+        ];
+        dispatch(&request(&set), &mut store, &keymap);
+        let position = KeyPosition::left(LeftKeyLocation::C2R1);
+        assert_eq!(to_keycode(store.action(1, position).unwrap()), 0x001D);
+        let get = [DYNAMIC_KEYMAP_GET_BUFFER, offset[0], offset[1], 4];
+        let (response, _) = dispatch(&request(&get), &mut store, &keymap);
+        assert_eq!(response[4..6], [0x00, 0x1D]);
+
+        // the keys past the thumb keys
+        let get = [DYNAMIC_KEYMAP_GET_KEYCODE, 0, 3, 6];
+        let (response, _) = dispatch(&request(&get), &mut store, &keymap);
+        assert_eq!(response[4..6], [0x00, 0x00]);
+        let set = [DYNAMIC_KEYMAP_SET_KEYCODE, 0, 3, 6, 0x00, 0x04];
+        dispatch(&request(&set), &mut store, &keymap);
+        let (response, _) = dispatch(&request(&get), &mut store, &keymap);
+        assert_eq!(response[4..6], [0x00, 0x00]);
+
+        dispatch(&request(&[DYNAMIC_KEYMAP_RESET]), &mut store, &keymap);
+        assert_eq!(to_keycode(store.action(1, position).unwrap()), 0x0000);
+    }
+
+    #[test]
+    fn reads_and_changes_the_macros() {
+        let keymap = get_profile().keymap;
+        let mut store = KeymapStore::new();
+        let size = [DYNAMIC_KEYMAP_MACRO_GET_BUFFER_SIZE];
+        let (response, _) = dispatch(&request(&size), &mut store, &keymap);
+        assert_eq!(
+            response[..3],
+            [DYNAMIC_KEYMAP_MACRO_GET_BUFFER_SIZE, 0x04, 0x00]
+        );
+
+        let set = [DYNAMIC_KEYMAP_MACRO_SET_BUFFER, 0, 1, 3, b'h', b'i', 0];
+        dispatch(&request(&set), &mut store, &keymap);
+        let get = [DYNAMIC_KEYMAP_MACRO_GET_BUFFER, 0, 0, 5];
+        let (response, _) = dispatch(&request(&get), &mut store, &keymap);
+        assert_eq!(response[4..9], [0, b'h', b'i', 0, 0]);
+        assert!(store.take_save_request());
+
+        let past_the_end = (VIA_MACRO_BUFFER_SIZE as u16 - 1).to_be_bytes();
+        let get = [
+            DYNAMIC_KEYMAP_MACRO_GET_BUFFER,
+            past_the_end[0],
+            past_the_end[1],
+            2,
+        ];
+        let (response, _) = dispatch(&request(&get), &mut store, &keymap);
+        assert_eq!(response[0], UNHANDLED);
+
+        dispatch(&request(&[DYNAMIC_KEYMAP_MACRO_RESET]), &mut store, &keymap);
+        assert!(store.via_macro_buffer().iter().all(|byte| *byte == 0));
+    }
+}
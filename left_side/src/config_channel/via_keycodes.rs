@@ -0,0 +1,218 @@
+//! Translation between the actions of the firmware and the 16-bit keycodes VIA edits keymaps
+//! with, those of QMK as of VIA protocol version 12. The actions without a QMK keycode take the
+//! custom keycodes of the keyboard definition, see `via/keyboard.json`.
+use usbd_hid::descriptor::KeyboardUsage;
+
+use crate::{
+    hid_helper::{host_layout::HostLayout, modifiers::Modifiers, unicode_input::UnicodeInput},
+    key_features::swap_hands::SwapHandsMode,
+    macros::{macro_recorder::DynamicMacroAction, via_macro::VIA_MACRO_COUNT},
+    profiles_management::{keyboard_profile::keyboard_profile::KeyAction, layers::LAYER_COUNT},
+};
+
+const KC_NO: u16 = 0x0000;
+/// The keys of the keyboard page up to here share their HID usage, QMK takes the usages past it
+/// for system, media and mouse keys.
+const LAST_BASIC_KEY: u16 = 0x00A4;
+const FIRST_MODIFIER: u16 = 0x00E0;
+const LAST_MODIFIER: u16 = 0x00E7;
+/// Followed by the layer.
+const QK_DEF_LAYER: u16 = 0x5240;
+/// Followed by the left hand modifiers, the right hand ones having [ONE_SHOT_RIGHT_HAND] set.
+const QK_ONE_SHOT_MOD: u16 = 0x52A0;
+const ONE_SHOT_RIGHT_HAND: u16 = 0x10;
+const QK_SWAP_HANDS_TOGGLE: u16 = 0x56F0;
+const QK_SWAP_HANDS_MOMENTARY_ON: u16 = 0x56F2;
+/// Followed by the index of the macro in the VIA macro buffer.
+const QK_MACRO: u16 = 0x7700;
+const QK_UNICODE_MODE_MACOS: u16 = 0x7C32;
+const QK_UNICODE_MODE_LINUX: u16 = 0x7C33;
+const QK_UNICODE_MODE_WINDOWS: u16 = 0x7C34;
+const QK_UNICODE_MODE_WINCOMPOSE: u16 = 0x7C36;
+/// Followed by the slot, [QK_DYNAMIC_MACRO_PLAY_1] likewise.
+const QK_DYNAMIC_MACRO_RECORD_START_1: u16 = 0x7C53;
+const QK_DYNAMIC_MACRO_RECORD_STOP: u16 = 0x7C55;
+const QK_DYNAMIC_MACRO_PLAY_1: u16 = 0x7C56;
+/// Number of slots of the QMK dynamic macros.
+const DYNAMIC_MACRO_SLOTS: u8 = 2;
+const QK_LEADER: u16 = 0x7C58;
+const QK_CAPS_WORD_TOGGLE: u16 = 0x7C73;
+/// The custom keycodes of the keyboard definition, in order: a key switching to each host layout
+/// in [HostLayout] order, then [FIRMWARE_ACTION].
+const QK_KB: u16 = 0x7E00;
+/// Number of the host layouts, the custom keycodes switching to them come first.
+const HOST_LAYOUTS: u16 = 6;
+/// Stands for the actions VIA cannot set, e.g. the macros of the profile, which are kept as they
+/// are unless the key is set to another keycode.
+pub const FIRMWARE_ACTION: u16 = QK_KB + HOST_LAYOUTS;
+/// Followed by a character of the first 0x8000 code points.
+const QK_UNICODE: u16 = 0x8000;
+
+/// Returns the keycode of the action, [FIRMWARE_ACTION] if it has none.
+pub fn to_keycode(action: KeyAction) -> u16 {
+    match action {
+        KeyAction::DeadKey => return KC_NO,
+        KeyAction::HidKey(key) => match key as u16 {
+            keycode @ (0x04..=LAST_BASIC_KEY | FIRST_MODIFIER..=LAST_MODIFIER) => return keycode,
+            _ => return FIRMWARE_ACTION,
+        },
+        KeyAction::ViaMacro(index) => return QK_MACRO + index as u16,
+        KeyAction::DynamicMacro(DynamicMacroAction::Record(slot)) if slot < DYNAMIC_MACRO_SLOTS => {
+            return QK_DYNAMIC_MACRO_RECORD_START_1 + slot as u16;
+        }
+        KeyAction::DynamicMacro(DynamicMacroAction::Stop) => return QK_DYNAMIC_MACRO_RECORD_STOP,
+        KeyAction::DynamicMacro(DynamicMacroAction::Play(slot)) if slot < DYNAMIC_MACRO_SLOTS => {
+            return QK_DYNAMIC_MACRO_PLAY_1 + slot as u16;
+        }
+        KeyAction::CapsWord => return QK_CAPS_WORD_TOGGLE,
+        KeyAction::OneShotMod(modifiers) => {
+            let bits = modifiers.bits() as u16;
+            match (bits & 0x0F, bits >> 4) {
+                (0, 0) => return FIRMWARE_ACTION,
+                (left, 0) => return QK_ONE_SHOT_MOD | left,
+                (0, right) => return QK_ONE_SHOT_MOD | ONE_SHOT_RIGHT_HAND | right,
+                _ => return FIRMWARE_ACTION,
+            }
+        }
+        KeyAction::Unicode(character) if (character as u32) < 0x8000 => {
+            return QK_UNICODE | character as u16;
+        }
+        KeyAction::SetUnicodeInput(UnicodeInput::Linux) => return QK_UNICODE_MODE_LINUX,
+        KeyAction::SetUnicodeInput(UnicodeInput::WinCompose) => return QK_UNICODE_MODE_WINCOMPOSE,
+        KeyAction::SetUnicodeInput(UnicodeInput::WindowsAltCodes) => {
+            return QK_UNICODE_MODE_WINDOWS;
+        }
+        KeyAction::SetUnicodeInput(UnicodeInput::MacOs) => return QK_UNICODE_MODE_MACOS,
+        KeyAction::SetHostLayout(layout) => return QK_KB + layout.to_byte() as u16,
+        KeyAction::SetDefaultLayer(layer) => return QK_DEF_LAYER | layer as u16,
+        KeyAction::Leader => return QK_LEADER,
+        KeyAction::SwapHands(SwapHandsMode::Momentary) => return QK_SWAP_HANDS_MOMENTARY_ON,
+        KeyAction::SwapHands(SwapHandsMode::Toggle) => return QK_SWAP_HANDS_TOGGLE,
+        KeyAction::Macro(_)
+        | KeyAction::EncodedMacro(_)
+        | KeyAction::StoredMacro(_)
+        | KeyAction::DynamicMacro(_)
+        | KeyAction::Unicode(_)
+        | KeyAction::BoardAction => return FIRMWARE_ACTION,
+    }
+}
+
+/// Returns the action of the keycode, [None] if the firmware cannot carry it out.
+pub fn from_keycode(keycode: u16) -> Option<KeyAction> {
+    match keycode {
+        KC_NO => return Some(KeyAction::DeadKey),
+        0x04..=LAST_BASIC_KEY | FIRST_MODIFIER..=LAST_MODIFIER => {
+            return Some(KeyAction::HidKey(KeyboardUsage::from(keycode as u8)));
+        }
+        QK_DEF_LAYER..=0x525F if keycode - QK_DEF_LAYER < LAYER_COUNT as u16 => {
+            return Some(KeyAction::SetDefaultLayer((keycode - QK_DEF_LAYER) as u8));
+        }
+        0x52A1..=0x52BF => {
+            let modifiers = keycode & 0x0F;
+            if modifiers == 0 {
+                return None;
+            }
+            if keycode & ONE_SHOT_RIGHT_HAND != 0 {
+                return Some(KeyAction::OneShotMod(Modifiers::from_bits(
+                    (modifiers << 4) as u8,
+                )));
+            }
+            return Some(KeyAction::OneShotMod(Modifiers::from_bits(modifiers as u8)));
+        }
+        QK_SWAP_HANDS_TOGGLE => return Some(KeyAction::SwapHands(SwapHandsMode::Toggle)),
+        QK_SWAP_HANDS_MOMENTARY_ON => return Some(KeyAction::SwapHands(SwapHandsMode::Momentary)),
+        QK_MACRO..=0x777F if keycode - QK_MACRO < VIA_MACRO_COUNT as u16 => {
+            return Some(KeyAction::ViaMacro((keycode - QK_MACRO) as u8));
+        }
+        QK_UNICODE_MODE_MACOS => return Some(KeyAction::SetUnicodeInput(UnicodeInput::MacOs)),
+        QK_UNICODE_MODE_LINUX => return Some(KeyAction::SetUnicodeInput(UnicodeInput::Linux)),
+        QK_UNICODE_MODE_WINDOWS => {
+            return Some(KeyAction::SetUnicodeInput(UnicodeInput::WindowsAltCodes));
+        }
+        QK_UNICODE_MODE_WINCOMPOSE => {
+            return Some(KeyAction::SetUnicodeInput(UnicodeInput::WinCompose));
+        }
+        0x7C53..=0x7C54 => {
+            let slot = (keycode - QK_DYNAMIC_MACRO_RECORD_START_1) as u8;
+            return Some(KeyAction::DynamicMacro(DynamicMacroAction::Record(slot)));
+        }
+        QK_DYNAMIC_MACRO_RECORD_STOP => {
+            return Some(KeyAction::DynamicMacro(DynamicMacroAction::Stop));
+        }
+        0x7C56..=0x7C57 => {
+            let slot = (keycode - QK_DYNAMIC_MACRO_PLAY_1) as u8;
+            return Some(KeyAction::DynamicMacro(DynamicMacroAction::Play(slot)));
+        }
+        QK_LEADER => return Some(KeyAction::Leader),
+        QK_CAPS_WORD_TOGGLE => return Some(KeyAction::CapsWord),
+        QK_KB..=0x7E3F => {
+            let layout = HostLayout::from_byte((keycode - QK_KB) as u8)?;
+            return Some(KeyAction::SetHostLayout(layout));
+        }
+        QK_UNICODE..=0xFFFF => {
+            return Some(KeyAction::Unicode(char::from_u32(
+                (keycode - QK_UNICODE) as u32,
+            )?));
+        }
+        _ => return None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use usbd_hid::descriptor::KeyboardUsage::*;
+
+    use super::*;
+
+    #[test]
+    fn translates_the_actions_back_and_forth() {
+        let actions = [
+            KeyAction::DeadKey,
+            KeyAction::HidKey(KeyboardAa),
+            KeyAction::HidKey(KeyboardRightGUI),
+            KeyAction::ViaMacro(VIA_MACRO_COUNT - 1),
+            KeyAction::DynamicMacro(DynamicMacroAction::Record(1)),
+            KeyAction::DynamicMacro(DynamicMacroAction::Stop),
+            KeyAction::DynamicMacro(DynamicMacroAction::Play(0)),
+            KeyAction::CapsWord,
+            KeyAction::OneShotMod(Modifiers::LEFT_CTRL.union(Modifiers::LEFT_SHIFT)),
+            KeyAction::OneShotMod(Modifiers::RIGHT_ALT),
+            KeyAction::Unicode('é'),
+            KeyAction::SetUnicodeInput(UnicodeInput::WindowsAltCodes),
+            KeyAction::SetHostLayout(HostLayout::Dvorak),
+            KeyAction::SetDefaultLayer(LAYER_COUNT - 1),
+            KeyAction::Leader,
+            KeyAction::SwapHands(SwapHandsMode::Momentary),
+        ];
+        for action in actions {
+            let keycode = to_keycode(action);
+            assert_ne!(keycode, FIRMWARE_ACTION, "{:?}", action);
+            let back = from_keycode(keycode).unwrap();
+            assert_eq!(format!("{:?}", back), format!("{:?}", action));
+        }
+        assert_eq!(to_keycode(KeyAction::HidKey(KeyboardAa)), 0x0004);
+        assert_eq!(
+            to_keycode(KeyAction::OneShotMod(Modifiers::RIGHT_ALT)),
+            0x52B4
+        );
+        assert_eq!(to_keycode(KeyAction::ViaMacro(2)), 0x7702);
+    }
+
+    #[test]
+    fn keeps_what_via_cannot_carry() {
+        assert_eq!(to_keycode(KeyAction::Unicode('😀')), FIRMWARE_ACTION);
+        assert_eq!(to_keycode(KeyAction::StoredMacro(0)), FIRMWARE_ACTION);
+        assert_eq!(
+            to_keycode(KeyAction::OneShotMod(Modifiers::CTRL)),
+            FIRMWARE_ACTION
+        );
+        // the custom keycodes of the keyboard definition end with it
+        assert!(HostLayout::from_byte(HOST_LAYOUTS as u8).is_none());
+        assert!(from_keycode(FIRMWARE_ACTION).is_none());
+        // KC_TRANSPARENT, a layer key and a key held with Ctrl
+        assert!(from_keycode(0x0001).is_none());
+        assert!(from_keycode(0x5221).is_none());
+        assert!(from_keycode(0x0104).is_none());
+        assert!(from_keycode(QK_DEF_LAYER + LAYER_COUNT as u16).is_none());
+    }
+}
@@ -0,0 +1,599 @@
+use usbd_hid::descriptor::KeyboardUsage::{self, *};
+
+use super::modifiers::Modifiers;
+
+/// The keyboard layout the host is set to. Usages name the keys of a US keyboard, the host
+/// layout decides which character each of them types.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum HostLayout {
+    Us,
+    /// British.
+    Uk,
+    /// German QWERTZ.
+    German,
+    /// French AZERTY.
+    French,
+    /// Swedish and Finnish.
+    Nordic,
+    /// US Dvorak set on the host, the keyboard itself sending QWERTY.
+    Dvorak,
+}
+
+/// How a character is typed on a [HostLayout].
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct LayoutKey {
+    pub key: KeyboardUsage,
+    pub modifiers: Modifiers,
+    /// The key is a dead key, a space has to follow for the character to come out on its own.
+    pub dead: bool,
+}
+
+type LayoutTable = &'static [(char, LayoutKey)];
+
+impl HostLayout {
+    pub fn from_byte(byte: u8) -> Option<HostLayout> {
+        match byte {
+            0 => return Some(HostLayout::Us),
+            1 => return Some(HostLayout::Uk),
+            2 => return Some(HostLayout::German),
+            3 => return Some(HostLayout::French),
+            4 => return Some(HostLayout::Nordic),
+            5 => return Some(HostLayout::Dvorak),
+            _ => return None,
+        }
+    }
+
+    pub fn to_byte(self) -> u8 {
+        return self as u8;
+    }
+
+    /// Returns how the character is typed on the layout, [None] if the layout cannot type it.
+    pub fn layout_key(self, character: char) -> Option<LayoutKey> {
+        if character.is_ascii_uppercase() {
+            let key = self.find(character.to_ascii_lowercase())?;
+            return Some(LayoutKey {
+                modifiers: key.modifiers.union(Modifiers::LEFT_SHIFT),
+                ..key
+            });
+        }
+        return self.find(character);
+    }
+
+    /// Returns the first entry of the tables of the layout for the character, the earlier
+    /// tables overriding the later ones.
+    fn find(self, character: char) -> Option<LayoutKey> {
+        for table in self.tables() {
+            if let Some((_, key)) = table.iter().find(|(entry, _)| *entry == character) {
+                return Some(*key);
+            }
+        }
+        return None;
+    }
+
+    fn tables(self) -> &'static [LayoutTable] {
+        match self {
+            HostLayout::Us => return &[US_NUMBER_ROW, US_SYMBOLS, QWERTY_LETTERS, WHITESPACE],
+            HostLayout::Uk => {
+                return &[
+                    UK_SYMBOLS,
+                    US_NUMBER_ROW,
+                    US_SYMBOLS,
+                    QWERTY_LETTERS,
+                    WHITESPACE,
+                ]
+            }
+            HostLayout::German => return &[GERMAN_SYMBOLS, QWERTZ_LETTERS, WHITESPACE],
+            HostLayout::French => return &[FRENCH_SYMBOLS, AZERTY_LETTERS, WHITESPACE],
+            HostLayout::Nordic => return &[NORDIC_SYMBOLS, QWERTY_LETTERS, WHITESPACE],
+            HostLayout::Dvorak => return &[US_NUMBER_ROW, DVORAK_KEYS, WHITESPACE],
+        }
+    }
+}
+
+const fn plain(character: char, key: KeyboardUsage) -> (char, LayoutKey) {
+    return with(character, key, Modifiers::NONE);
+}
+
+const fn shifted(character: char, key: KeyboardUsage) -> (char, LayoutKey) {
+    return with(character, key, Modifiers::LEFT_SHIFT);
+}
+
+const fn alt_gr(character: char, key: KeyboardUsage) -> (char, LayoutKey) {
+    return with(character, key, Modifiers::RIGHT_ALT);
+}
+
+const fn with(character: char, key: KeyboardUsage, modifiers: Modifiers) -> (char, LayoutKey) {
+    let dead = false;
+    return (
+        character,
+        LayoutKey {
+            key,
+            modifiers,
+            dead,
+        },
+    );
+}
+
+const fn dead((character, key): (char, LayoutKey)) -> (char, LayoutKey) {
+    let dead = true;
+    return (character, LayoutKey { dead, ..key });
+}
+
+const WHITESPACE: LayoutTable = &[
+    plain('\n', KeyboardEnter),
+    plain('\t', KeyboardTab),
+    plain(' ', KeyboardSpacebar),
+];
+
+const QWERTY_LETTERS: LayoutTable = &[
+    plain('a', KeyboardAa),
+    plain('b', KeyboardBb),
+    plain('c', KeyboardCc),
+    plain('d', KeyboardDd),
+    plain('e', KeyboardEe),
+    plain('f', KeyboardFf),
+    plain('g', KeyboardGg),
+    plain('h', KeyboardHh),
+    plain('i', KeyboardIi),
+    plain('j', KeyboardJj),
+    plain('k', KeyboardKk),
+    plain('l', KeyboardLl),
+    plain('m', KeyboardMm),
+    plain('n', KeyboardNn),
+    plain('o', KeyboardOo),
+    plain('p', KeyboardPp),
+    plain('q', KeyboardQq),
+    plain('r', KeyboardRr),
+    plain('s', KeyboardSs),
+    plain('t', KeyboardTt),
+    plain('u', KeyboardUu),
+    plain('v', KeyboardVv),
+    plain('w', KeyboardWw),
+    plain('x', KeyboardXx),
+    plain('y', KeyboardYy),
+    plain('z', KeyboardZz),
+];
+
+const QWERTZ_LETTERS: LayoutTable = &[
+    plain('a', KeyboardAa),
+    plain('b', KeyboardBb),
+    plain('c', KeyboardCc),
+    plain('d', KeyboardDd),
+    plain('e', KeyboardEe),
+    plain('f', KeyboardFf),
+    plain('g', KeyboardGg),
+    plain('h', KeyboardHh),
+    plain('i', KeyboardIi),
+    plain('j', KeyboardJj),
+    plain('k', KeyboardKk),
+    plain('l', KeyboardLl),
+    plain('m', KeyboardMm),
+    plain('n', KeyboardNn),
+    plain('o', KeyboardOo),
+    plain('p', KeyboardPp),
+    plain('q', KeyboardQq),
+    plain('r', KeyboardRr),
+    plain('s', KeyboardSs),
+    plain('t', KeyboardTt),
+    plain('u', KeyboardUu),
+    plain('v', KeyboardVv),
+    plain('w', KeyboardWw),
+    plain('x', KeyboardXx),
+    plain('y', KeyboardZz),
+    plain('z', KeyboardYy),
+];
+
+const AZERTY_LETTERS: LayoutTable = &[
+    plain('a', KeyboardQq),
+    plain('b', KeyboardBb),
+    plain('c', KeyboardCc),
+    plain('d', KeyboardDd),
+    plain('e', KeyboardEe),
+    plain('f', KeyboardFf),
+    plain('g', KeyboardGg),
+    plain('h', KeyboardHh),
+    plain('i', KeyboardIi),
+    plain('j', KeyboardJj),
+    plain('k', KeyboardKk),
+    plain('l', KeyboardLl),
+    plain('m', KeyboardSemiColon),
+    plain('n', KeyboardNn),
+    plain('o', KeyboardOo),
+    plain('p', KeyboardPp),
+    plain('q', KeyboardAa),
+    plain('r', KeyboardRr),
+    plain('s', KeyboardSs),
+    plain('t', KeyboardTt),
+    plain('u', KeyboardUu),
+    plain('v', KeyboardVv),
+    plain('w', KeyboardZz),
+    plain('x', KeyboardXx),
+    plain('y', KeyboardYy),
+    plain('z', KeyboardWw),
+];
+
+const US_NUMBER_ROW: LayoutTable = &[
+    plain('`', KeyboardBacktickTilde),
+    shifted('~', KeyboardBacktickTilde),
+    plain('1', Keyboard1Exclamation),
+    shifted('!', Keyboard1Exclamation),
+    plain('2', Keyboard2At),
+    shifted('@', Keyboard2At),
+    plain('3', Keyboard3Hash),
+    shifted('#', Keyboard3Hash),
+    plain('4', Keyboard4Dollar),
+    shifted('$', Keyboard4Dollar),
+    plain('5', Keyboard5Percent),
+    shifted('%', Keyboard5Percent),
+    plain('6', Keyboard6Caret),
+    shifted('^', Keyboard6Caret),
+    plain('7', Keyboard7Ampersand),
+    shifted('&', Keyboard7Ampersand),
+    plain('8', Keyboard8Asterisk),
+    shifted('*', Keyboard8Asterisk),
+    plain('9', Keyboard9OpenParens),
+    shifted('(', Keyboard9OpenParens),
+    plain('0', Keyboard0CloseParens),
+    shifted(')', Keyboard0CloseParens),
+];
+
+const US_SYMBOLS: LayoutTable = &[
+    plain('-', KeyboardDashUnderscore),
+    shifted('_', KeyboardDashUnderscore),
+    plain('=', KeyboardEqualPlus),
+    shifted('+', KeyboardEqualPlus),
+    plain('[', KeyboardOpenBracketBrace),
+    shifted('{', KeyboardOpenBracketBrace),
+    plain(']', KeyboardCloseBracketBrace),
+    shifted('}', KeyboardCloseBracketBrace),
+    plain('\\', KeyboardBackslashBar),
+    shifted('|', KeyboardBackslashBar),
+    plain(';', KeyboardSemiColon),
+    shifted(':', KeyboardSemiColon),
+    plain('\'', KeyboardSingleDoubleQuote),
+    shifted('"', KeyboardSingleDoubleQuote),
+    plain(',', KeyboardCommaLess),
+    shifted('<', KeyboardCommaLess),
+    plain('.', KeyboardPeriodGreater),
+    shifted('>', KeyboardPeriodGreater),
+    plain('/', KeyboardSlashQuestion),
+    shifted('?', KeyboardSlashQuestion),
+];
+
+/// Where the British layout differs from the US one.
+const UK_SYMBOLS: LayoutTable = &[
+    shifted('¬', KeyboardBacktickTilde),
+    shifted('"', Keyboard2At),
+    shifted('£', Keyboard3Hash),
+    shifted('@', KeyboardSingleDoubleQuote),
+    plain('#', KeyboardNonUSHash),
+    shifted('~', KeyboardNonUSHash),
+    plain('\\', KeyboardNonUSSlash),
+    shifted('|', KeyboardNonUSSlash),
+];
+
+const GERMAN_SYMBOLS: LayoutTable = &[
+    dead(plain('^', KeyboardBacktickTilde)),
+    shifted('°', KeyboardBacktickTilde),
+    plain('1', Keyboard1Exclamation),
+    shifted('!', Keyboard1Exclamation),
+    plain('2', Keyboard2At),
+    shifted('"', Keyboard2At),
+    alt_gr('²', Keyboard2At),
+    plain('3', Keyboard3Hash),
+    shifted('§', Keyboard3Hash),
+    alt_gr('³', Keyboard3Hash),
+    plain('4', Keyboard4Dollar),
+    shifted('$', Keyboard4Dollar),
+    plain('5', Keyboard5Percent),
+    shifted('%', Keyboard5Percent),
+    plain('6', Keyboard6Caret),
+    shifted('&', Keyboard6Caret),
+    plain('7', Keyboard7Ampersand),
+    shifted('/', Keyboard7Ampersand),
+    alt_gr('{', Keyboard7Ampersand),
+    plain('8', Keyboard8Asterisk),
+    shifted('(', Keyboard8Asterisk),
+    alt_gr('[', Keyboard8Asterisk),
+    plain('9', Keyboard9OpenParens),
+    shifted(')', Keyboard9OpenParens),
+    alt_gr(']', Keyboard9OpenParens),
+    plain('0', Keyboard0CloseParens),
+    shifted('=', Keyboard0CloseParens),
+    alt_gr('}', Keyboard0CloseParens),
+    plain('ß', KeyboardDashUnderscore),
+    shifted('?', KeyboardDashUnderscore),
+    alt_gr('\\', KeyboardDashUnderscore),
+    dead(plain('´', KeyboardEqualPlus)),
+    dead(shifted('`', KeyboardEqualPlus)),
+    alt_gr('@', KeyboardQq),
+    alt_gr('€', KeyboardEe),
+    plain('ü', KeyboardOpenBracketBrace),
+    shifted('Ü', KeyboardOpenBracketBrace),
+    plain('+', KeyboardCloseBracketBrace),
+    shifted('*', KeyboardCloseBracketBrace),
+    alt_gr('~', KeyboardCloseBracketBrace),
+    plain('ö', KeyboardSemiColon),
+    shifted('Ö', KeyboardSemiColon),
+    plain('ä', KeyboardSingleDoubleQuote),
+    shifted('Ä', KeyboardSingleDoubleQuote),
+    plain('#', KeyboardNonUSHash),
+    shifted('\'', KeyboardNonUSHash),
+    plain('<', KeyboardNonUSSlash),
+    shifted('>', KeyboardNonUSSlash),
+    alt_gr('|', KeyboardNonUSSlash),
+    alt_gr('µ', KeyboardMm),
+    plain(',', KeyboardCommaLess),
+    shifted(';', KeyboardCommaLess),
+    plain('.', KeyboardPeriodGreater),
+    shifted(':', KeyboardPeriodGreater),
+    plain('-', KeyboardSlashQuestion),
+    shifted('_', KeyboardSlashQuestion),
+];
+
+const FRENCH_SYMBOLS: LayoutTable = &[
+    plain('²', KeyboardBacktickTilde),
+    plain('&', Keyboard1Exclamation),
+    shifted('1', Keyboard1Exclamation),
+    plain('é', Keyboard2At),
+    shifted('2', Keyboard2At),
+    dead(alt_gr('~', Keyboard2At)),
+    plain('"', Keyboard3Hash),
+    shifted('3', Keyboard3Hash),
+    alt_gr('#', Keyboard3Hash),
+    plain('\'', Keyboard4Dollar),
+    shifted('4', Keyboard4Dollar),
+    alt_gr('{', Keyboard4Dollar),
+    plain('(', Keyboard5Percent),
+    shifted('5', Keyboard5Percent),
+    alt_gr('[', Keyboard5Percent),
+    plain('-', Keyboard6Caret),
+    shifted('6', Keyboard6Caret),
+    alt_gr('|', Keyboard6Caret),
+    plain('è', Keyboard7Ampersand),
+    shifted('7', Keyboard7Ampersand),
+    dead(alt_gr('`', Keyboard7Ampersand)),
+    plain('_', Keyboard8Asterisk),
+    shifted('8', Keyboard8Asterisk),
+    alt_gr('\\', Keyboard8Asterisk),
+    plain('ç', Keyboard9OpenParens),
+    shifted('9', Keyboard9OpenParens),
+    alt_gr('^', Keyboard9OpenParens),
+    plain('à', Keyboard0CloseParens),
+    shifted('0', Keyboard0CloseParens),
+    alt_gr('@', Keyboard0CloseParens),
+    plain(')', KeyboardDashUnderscore),
+    shifted('°', KeyboardDashUnderscore),
+    alt_gr(']', KeyboardDashUnderscore),
+    plain('=', KeyboardEqualPlus),
+    shifted('+', KeyboardEqualPlus),
+    alt_gr('}', KeyboardEqualPlus),
+    alt_gr('€', KeyboardEe),
+    plain('$', KeyboardCloseBracketBrace),
+    shifted('£', KeyboardCloseBracketBrace),
+    alt_gr('¤', KeyboardCloseBracketBrace),
+    plain('ù', KeyboardSingleDoubleQuote),
+    shifted('%', KeyboardSingleDoubleQuote),
+    plain('*', KeyboardNonUSHash),
+    shifted('µ', KeyboardNonUSHash),
+    plain('<', KeyboardNonUSSlash),
+    shifted('>', KeyboardNonUSSlash),
+    plain(',', KeyboardMm),
+    shifted('?', KeyboardMm),
+    plain(';', KeyboardCommaLess),
+    shifted('.', KeyboardCommaLess),
+    plain(':', KeyboardPeriodGreater),
+    shifted('/', KeyboardPeriodGreater),
+    plain('!', KeyboardSlashQuestion),
+    shifted('§', KeyboardSlashQuestion),
+];
+
+const NORDIC_SYMBOLS: LayoutTable = &[
+    plain('§', KeyboardBacktickTilde),
+    shifted('½', KeyboardBacktickTilde),
+    plain('1', Keyboard1Exclamation),
+    shifted('!', Keyboard1Exclamation),
+    plain('2', Keyboard2At),
+    shifted('"', Keyboard2At),
+    alt_gr('@', Keyboard2At),
+    plain('3', Keyboard3Hash),
+    shifted('#', Keyboard3Hash),
+    alt_gr('£', Keyboard3Hash),
+    plain('4', Keyboard4Dollar),
+    shifted('¤', Keyboard4Dollar),
+    alt_gr('$', Keyboard4Dollar),
+    plain('5', Keyboard5Percent),
+    shifted('%', Keyboard5Percent),
+    alt_gr('€', Keyboard5Percent),
+    plain('6', Keyboard6Caret),
+    shifted('&', Keyboard6Caret),
+    plain('7', Keyboard7Ampersand),
+    shifted('/', Keyboard7Ampersand),
+    alt_gr('{', Keyboard7Ampersand),
+    plain('8', Keyboard8Asterisk),
+    shifted('(', Keyboard8Asterisk),
+    alt_gr('[', Keyboard8Asterisk),
+    plain('9', Keyboard9OpenParens),
+    shifted(')', Keyboard9OpenParens),
+    alt_gr(']', Keyboard9OpenParens),
+    plain('0', Keyboard0CloseParens),
+    shifted('=', Keyboard0CloseParens),
+    alt_gr('}', Keyboard0CloseParens),
+    plain('+', KeyboardDashUnderscore),
+    shifted('?', KeyboardDashUnderscore),
+    alt_gr('\\', KeyboardDashUnderscore),
+    dead(plain('´', KeyboardEqualPlus)),
+    dead(shifted('`', KeyboardEqualPlus)),
+    plain('å', KeyboardOpenBracketBrace),
+    shifted('Å', KeyboardOpenBracketBrace),
+    dead(plain('¨', KeyboardCloseBracketBrace)),
+    dead(shifted('^', KeyboardCloseBracketBrace)),
+    dead(alt_gr('~', KeyboardCloseBracketBrace)),
+    plain('ö', KeyboardSemiColon),
+    shifted('Ö', KeyboardSemiColon),
+    plain('ä', KeyboardSingleDoubleQuote),
+    shifted('Ä', KeyboardSingleDoubleQuote),
+    plain('\'', KeyboardNonUSHash),
+    shifted('*', KeyboardNonUSHash),
+    plain('<', KeyboardNonUSSlash),
+    shifted('>', KeyboardNonUSSlash),
+    alt_gr('|', KeyboardNonUSSlash),
+    alt_gr('µ', KeyboardMm),
+    plain(',', KeyboardCommaLess),
+    shifted(';', KeyboardCommaLess),
+    plain('.', KeyboardPeriodGreater),
+    shifted(':', KeyboardPeriodGreater),
+    plain('-', KeyboardSlashQuestion),
+    shifted('_', KeyboardSlashQuestion),
+];
+
+/// Everything but the number row, which Dvorak leaves as it is.
+const DVORAK_KEYS: LayoutTable = &[
+    plain('[', KeyboardDashUnderscore),
+    shifted('{', KeyboardDashUnderscore),
+    plain(']', KeyboardEqualPlus),
+    shifted('}', KeyboardEqualPlus),
+    plain('\'', KeyboardQq),
+    shifted('"', KeyboardQq),
+    plain(',', KeyboardWw),
+    shifted('<', KeyboardWw),
+    plain('.', KeyboardEe),
+    shifted('>', KeyboardEe),
+    plain('p', KeyboardRr),
+    plain('y', KeyboardTt),
+    plain('f', KeyboardYy),
+    plain('g', KeyboardUu),
+    plain('c', KeyboardIi),
+    plain('r', KeyboardOo),
+    plain('l', KeyboardPp),
+    plain('/', KeyboardOpenBracketBrace),
+    shifted('?', KeyboardOpenBracketBrace),
+    plain('=', KeyboardCloseBracketBrace),
+    shifted('+', KeyboardCloseBracketBrace),
+    plain('\\', KeyboardBackslashBar),
+    shifted('|', KeyboardBackslashBar),
+    plain('a', KeyboardAa),
+    plain('o', KeyboardSs),
+    plain('e', KeyboardDd),
+    plain('u', KeyboardFf),
+    plain('i', KeyboardGg),
+    plain('d', KeyboardHh),
+    plain('h', KeyboardJj),
+    plain('t', KeyboardKk),
+    plain('n', KeyboardLl),
+    plain('s', KeyboardSemiColon),
+    plain('-', KeyboardSingleDoubleQuote),
+    shifted('_', KeyboardSingleDoubleQuote),
+    plain(';', KeyboardZz),
+    shifted(':', KeyboardZz),
+    plain('q', KeyboardXx),
+    plain('j', KeyboardCc),
+    plain('k', KeyboardVv),
+    plain('x', KeyboardBb),
+    plain('b', KeyboardNn),
+    plain('m', KeyboardMm),
+    plain('w', KeyboardCommaLess),
+    plain('v', KeyboardPeriodGreater),
+    plain('z', KeyboardSlashQuestion),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LAYOUTS: [HostLayout; 6] = [
+        HostLayout::Us,
+        HostLayout::Uk,
+        HostLayout::German,
+        HostLayout::French,
+        HostLayout::Nordic,
+        HostLayout::Dvorak,
+    ];
+
+    fn key(key: KeyboardUsage, modifiers: Modifiers) -> Option<LayoutKey> {
+        let dead = false;
+        return Some(LayoutKey {
+            key,
+            modifiers,
+            dead,
+        });
+    }
+
+    #[test]
+    fn types_ascii_on_every_layout() {
+        for layout in LAYOUTS {
+            for character in (' '..='~').chain(['\n', '\t']) {
+                assert!(
+                    layout.layout_key(character).is_some(),
+                    "{:?} cannot type {:?}",
+                    layout,
+                    character
+                );
+            }
+            assert_eq!(HostLayout::from_byte(layout.to_byte()), Some(layout));
+        }
+    }
+
+    #[test]
+    fn gives_every_character_its_own_key() {
+        for layout in LAYOUTS {
+            let mut keys: Vec<(char, LayoutKey)> = Vec::new();
+            for table in layout.tables() {
+                for (character, _) in table.iter() {
+                    let key = layout.layout_key(*character).unwrap();
+                    let same_key = keys.iter().find(|(other, other_key)| {
+                        *other != *character
+                            && other_key.key == key.key
+                            && other_key.modifiers == key.modifiers
+                    });
+                    assert_eq!(same_key, None, "{:?} types {:?}", layout, character);
+                    keys.push((*character, key));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn moves_keys_around_on_the_host_layouts() {
+        assert_eq!(
+            HostLayout::Us.layout_key('Q'),
+            key(KeyboardQq, Modifiers::LEFT_SHIFT)
+        );
+        assert_eq!(
+            HostLayout::Uk.layout_key('"'),
+            key(Keyboard2At, Modifiers::LEFT_SHIFT)
+        );
+        assert_eq!(
+            HostLayout::German.layout_key('Z'),
+            key(KeyboardYy, Modifiers::LEFT_SHIFT)
+        );
+        assert_eq!(
+            HostLayout::German.layout_key('@'),
+            key(KeyboardQq, Modifiers::RIGHT_ALT)
+        );
+        assert_eq!(
+            HostLayout::French.layout_key('1'),
+            key(Keyboard1Exclamation, Modifiers::LEFT_SHIFT)
+        );
+        assert_eq!(
+            HostLayout::French.layout_key('a'),
+            key(KeyboardQq, Modifiers::NONE)
+        );
+        assert_eq!(
+            HostLayout::Nordic.layout_key('Ö'),
+            key(KeyboardSemiColon, Modifiers::LEFT_SHIFT)
+        );
+        assert_eq!(
+            HostLayout::Dvorak.layout_key('s'),
+            key(KeyboardSemiColon, Modifiers::NONE)
+        );
+        assert_eq!(HostLayout::Us.layout_key('é'), None);
+    }
+
+    #[test]
+    fn marks_dead_keys() {
+        assert!(HostLayout::German.layout_key('^').unwrap().dead);
+        assert!(HostLayout::Nordic.layout_key('~').unwrap().dead);
+        assert!(!HostLayout::Uk.layout_key('~').unwrap().dead);
+    }
+}
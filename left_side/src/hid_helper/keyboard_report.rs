@@ -1,7 +1,9 @@
 use serde::Serialize;
 use usbd_hid::descriptor::{KeyboardReport, KeyboardUsage};
 
-#[derive(Serialize, Clone, Copy, PartialEq, Eq)]
+use super::modifiers::Modifiers;
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
 pub struct KeyboardReportHelper {
     report: KeyboardReport,
     pos: usize,
@@ -15,41 +17,26 @@ impl KeyboardReportHelper {
         };
     }
 
-    pub fn from_values(
-        modifier: u8,
-        key_1: u8,
-        key_2: u8,
-        key_3: u8,
-        key_4: u8,
-        key_5: u8,
-        key_6: u8,
-    ) -> KeyboardReportHelper {
-        return KeyboardReportHelper {
-            report: KeyboardReport {
-                keycodes: [key_1, key_2, key_3, key_4, key_5, key_6],
-                modifier: modifier,
-                leds: 0,
-                reserved: 0,
-            },
-            pos: 6,
-        };
+    /// Returns true if the report holds neither a key nor a modifier, i.e. releases everything.
+    pub fn is_empty(&self) -> bool {
+        return self.pos == 0 && self.report.modifier == 0;
     }
 
-    pub fn is_empty(&self) -> bool {
-        return self.pos == 0;
+    /// Returns true if a key or modifier held in `previous` is no longer held in this report.
+    pub fn releases_keys_of(&self, previous: &KeyboardReportHelper) -> bool {
+        if previous.report.modifier & !self.report.modifier != 0 {
+            return true;
+        }
+        let held_keys = &self.report.keycodes[..self.pos];
+        return previous.report.keycodes[..previous.pos]
+            .iter()
+            .any(|key| !held_keys.contains(key));
     }
 
     pub fn add_keycode(&mut self, key: KeyboardUsage) {
-        match key {
-            KeyboardUsage::KeyboardLeftShift => self.add_modifier(Modifiers::LeftShift),
-            KeyboardUsage::KeyboardRightShift => self.add_modifier(Modifiers::RightShift),
-            KeyboardUsage::KeyboardLeftGUI => self.add_modifier(Modifiers::LeftGui),
-            KeyboardUsage::KeyboardRightGUI => self.add_modifier(Modifiers::RightGui),
-            KeyboardUsage::KeyboardLeftControl => self.add_modifier(Modifiers::LeftCtrl),
-            KeyboardUsage::KeyboardRightControl => self.add_modifier(Modifiers::RightCtrl),
-            KeyboardUsage::KeyboardLeftAlt => self.add_modifier(Modifiers::LeftAlt),
-            KeyboardUsage::KeyboardRightAlt => self.add_modifier(Modifiers::RightAlt),
-            key => {
+        match Modifiers::from_key(key) {
+            Some(modifier) => self.add_modifiers(modifier),
+            None => {
                 if self.pos == 6 {
                     return;
                 }
@@ -59,22 +46,63 @@ impl KeyboardReportHelper {
         }
     }
 
-    pub fn get_report(&self) -> KeyboardReport {
-        return self.report;
+    /// Returns true if the key has been added by [KeyboardReportHelper::add_keycode], modifiers
+    /// aside.
+    pub fn contains_keycode(&self, key: KeyboardUsage) -> bool {
+        return self.report.keycodes[..self.pos].contains(&(key as u8));
     }
 
-    fn add_modifier(&mut self, modifier: Modifiers) {
-        self.report.modifier = self.report.modifier | (modifier as u8);
+    /// Puts `replacement` in the place of `key`, modifiers aside.
+    pub fn replace_keycode(&mut self, key: KeyboardUsage, replacement: KeyboardUsage) {
+        if let Some(keycode) = self.report.keycodes[..self.pos]
+            .iter_mut()
+            .find(|keycode| **keycode == key as u8)
+        {
+            *keycode = replacement as u8;
+        }
+    }
+
+    /// Adds the keys and modifiers of `other` this report does not hold yet, after its own keys.
+    pub fn add_report(&mut self, other: &KeyboardReportHelper) {
+        self.add_modifiers(other.modifiers());
+        for keycode in other.report.keycodes[..other.pos].iter() {
+            if self.pos == 6 {
+                return;
+            }
+            if !self.report.keycodes[..self.pos].contains(keycode) {
+                self.report.keycodes[self.pos] = *keycode;
+                self.pos += 1;
+            }
+        }
+    }
+
+    pub fn modifiers(&self) -> Modifiers {
+        return Modifiers::from_bits(self.report.modifier);
+    }
+
+    pub fn add_modifiers(&mut self, modifiers: Modifiers) {
+        self.report.modifier = self.modifiers().union(modifiers).bits();
+    }
+
+    pub fn remove_modifiers(&mut self, modifiers: Modifiers) {
+        self.report.modifier = self.modifiers().difference(modifiers).bits();
+    }
+
+    /// Removes a key added by [KeyboardReportHelper::add_keycode], modifiers aside.
+    pub fn remove_keycode(&mut self, key: KeyboardUsage) {
+        let pos = match self.report.keycodes[..self.pos]
+            .iter()
+            .position(|keycode| *keycode == key as u8)
+        {
+            Some(pos) => pos,
+            None => return,
+        };
+        self.report.keycodes.copy_within(pos + 1..self.pos, pos);
+        self.pos -= 1;
+        self.report.keycodes[self.pos] = 0;
     }
-}
 
-enum Modifiers {
-    LeftCtrl = 0b00000001,
-    LeftShift = 0b00000010,
-    LeftAlt = 0b00000100,
-    LeftGui = 0b00001000,
-    RightCtrl = 0b00010000,
-    RightShift = 0b00100000,
-    RightAlt = 0b01000000,
-    RightGui = 0b10000000,
+    pub fn get_report(&self) -> KeyboardReport {
+        return self.report;
+    }
 }
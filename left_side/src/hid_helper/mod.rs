@@ -1 +1,4 @@
+pub mod host_layout;
 pub mod keyboard_report;
+pub mod modifiers;
+pub mod unicode_input;
@@ -0,0 +1,79 @@
+use usbd_hid::descriptor::KeyboardUsage;
+
+/// A set of modifiers, in the bit layout of the modifier byte of a keyboard report.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Modifiers(u8);
+
+impl Modifiers {
+    pub const NONE: Modifiers = Modifiers(0);
+    pub const LEFT_CTRL: Modifiers = Modifiers(0b00000001);
+    pub const LEFT_SHIFT: Modifiers = Modifiers(0b00000010);
+    pub const LEFT_ALT: Modifiers = Modifiers(0b00000100);
+    pub const LEFT_GUI: Modifiers = Modifiers(0b00001000);
+    pub const RIGHT_CTRL: Modifiers = Modifiers(0b00010000);
+    pub const RIGHT_SHIFT: Modifiers = Modifiers(0b00100000);
+    pub const RIGHT_ALT: Modifiers = Modifiers(0b01000000);
+    pub const RIGHT_GUI: Modifiers = Modifiers(0b10000000);
+    /// Either control key.
+    pub const CTRL: Modifiers = Modifiers::LEFT_CTRL.union(Modifiers::RIGHT_CTRL);
+    /// Either shift key.
+    pub const SHIFT: Modifiers = Modifiers::LEFT_SHIFT.union(Modifiers::RIGHT_SHIFT);
+    /// Either alt key.
+    pub const ALT: Modifiers = Modifiers::LEFT_ALT.union(Modifiers::RIGHT_ALT);
+    /// Either GUI key.
+    pub const GUI: Modifiers = Modifiers::LEFT_GUI.union(Modifiers::RIGHT_GUI);
+
+    pub const fn from_bits(bits: u8) -> Modifiers {
+        return Modifiers(bits);
+    }
+
+    pub const fn bits(self) -> u8 {
+        return self.0;
+    }
+
+    pub const fn union(self, other: Modifiers) -> Modifiers {
+        return Modifiers(self.0 | other.0);
+    }
+
+    pub const fn difference(self, other: Modifiers) -> Modifiers {
+        return Modifiers(self.0 & !other.0);
+    }
+
+    pub const fn is_empty(self) -> bool {
+        return self.0 == 0;
+    }
+
+    pub const fn contains(self, other: Modifiers) -> bool {
+        return self.0 & other.0 == other.0;
+    }
+
+    /// Returns the modifiers on both sides of the keyboard for every one in the set, e.g. both
+    /// shift keys for the left one.
+    pub const fn either_side(self) -> Modifiers {
+        let sides = (self.0 | self.0 >> 4) & 0b00001111;
+        return Modifiers(sides | sides << 4);
+    }
+
+    /// Returns true if every modifier of `required` is in the set, on either side of the
+    /// keyboard.
+    pub const fn matches(self, required: Modifiers) -> bool {
+        let held = (self.0 | self.0 >> 4) & 0b00001111;
+        let required = (required.0 | required.0 >> 4) & 0b00001111;
+        return held & required == required;
+    }
+
+    /// Returns the modifier the key stands for, [None] if the key is not a modifier.
+    pub fn from_key(key: KeyboardUsage) -> Option<Modifiers> {
+        match key {
+            KeyboardUsage::KeyboardLeftControl => return Some(Modifiers::LEFT_CTRL),
+            KeyboardUsage::KeyboardLeftShift => return Some(Modifiers::LEFT_SHIFT),
+            KeyboardUsage::KeyboardLeftAlt => return Some(Modifiers::LEFT_ALT),
+            KeyboardUsage::KeyboardLeftGUI => return Some(Modifiers::LEFT_GUI),
+            KeyboardUsage::KeyboardRightControl => return Some(Modifiers::RIGHT_CTRL),
+            KeyboardUsage::KeyboardRightShift => return Some(Modifiers::RIGHT_SHIFT),
+            KeyboardUsage::KeyboardRightAlt => return Some(Modifiers::RIGHT_ALT),
+            KeyboardUsage::KeyboardRightGUI => return Some(Modifiers::RIGHT_GUI),
+            _ => return None,
+        }
+    }
+}
@@ -0,0 +1,281 @@
+use heapless::Vec;
+use usbd_hid::descriptor::KeyboardUsage;
+
+use super::{keyboard_report::KeyboardReportHelper, modifiers::Modifiers};
+
+/// Number of reports the longest input of a character takes.
+pub const MAX_UNICODE_REPORTS: usize = 18;
+
+/// The ways of typing a character by its code point, each of them needs the host to be set up
+/// for it.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum UnicodeInput {
+    /// Ctrl+Shift+U, the code point in hex and space, as IBus takes it on Linux.
+    Linux,
+    /// The compose key (right alt), `u`, the code point in hex and enter, as WinCompose takes it
+    /// on Windows.
+    WinCompose,
+    /// Alt held over keypad `+` and the code point in hex, the hex numpad input of Windows. It
+    /// has to be turned on with `EnableHexNumpad` in the registry, and stops at U+FFFF.
+    WindowsAltCodes,
+    /// Option held over the UTF-16 code units in hex, as the Unicode Hex Input source takes it
+    /// on macOS.
+    MacOs,
+}
+
+impl UnicodeInput {
+    pub fn from_byte(byte: u8) -> Option<UnicodeInput> {
+        match byte {
+            0 => return Some(UnicodeInput::Linux),
+            1 => return Some(UnicodeInput::WinCompose),
+            2 => return Some(UnicodeInput::WindowsAltCodes),
+            3 => return Some(UnicodeInput::MacOs),
+            _ => return None,
+        }
+    }
+
+    pub fn to_byte(self) -> u8 {
+        return self as u8;
+    }
+}
+
+/// Returns the reports typing the character with the input method, starting and ending with
+/// all the keys up. Returns no report for characters the method cannot type.
+pub fn unicode_reports(
+    input: UnicodeInput,
+    character: char,
+) -> Vec<KeyboardReportHelper, MAX_UNICODE_REPORTS> {
+    let mut reports = Vec::new();
+    let code_point = character as u32;
+    match input {
+        UnicodeInput::Linux => {
+            push(
+                &mut reports,
+                Modifiers::LEFT_CTRL.union(Modifiers::LEFT_SHIFT),
+                Some(KeyboardUsage::KeyboardUu),
+            );
+            push(&mut reports, Modifiers::NONE, None);
+            tap_hex(&mut reports, Modifiers::NONE, code_point, false);
+            tap(
+                &mut reports,
+                Modifiers::NONE,
+                KeyboardUsage::KeyboardSpacebar,
+            );
+        }
+        UnicodeInput::WinCompose => {
+            tap(
+                &mut reports,
+                Modifiers::NONE,
+                KeyboardUsage::KeyboardRightAlt,
+            );
+            tap(&mut reports, Modifiers::NONE, KeyboardUsage::KeyboardUu);
+            tap_hex(&mut reports, Modifiers::NONE, code_point, false);
+            tap(&mut reports, Modifiers::NONE, KeyboardUsage::KeyboardEnter);
+        }
+        UnicodeInput::WindowsAltCodes => {
+            if code_point > 0xFFFF {
+                return reports;
+            }
+            push(&mut reports, Modifiers::LEFT_ALT, None);
+            tap(&mut reports, Modifiers::LEFT_ALT, KeyboardUsage::KeypadPlus);
+            tap_hex(&mut reports, Modifiers::LEFT_ALT, code_point, true);
+            push(&mut reports, Modifiers::NONE, None);
+        }
+        UnicodeInput::MacOs => {
+            push(&mut reports, Modifiers::LEFT_ALT, None);
+            for unit in character.encode_utf16(&mut [0; 2]) {
+                tap_hex(&mut reports, Modifiers::LEFT_ALT, *unit as u32, false);
+            }
+            push(&mut reports, Modifiers::NONE, None);
+        }
+    }
+    return reports;
+}
+
+/// Taps the hex digits of the value, at least four of them. Windows only takes the digits from
+/// the keypad.
+fn tap_hex(
+    reports: &mut Vec<KeyboardReportHelper, MAX_UNICODE_REPORTS>,
+    modifiers: Modifiers,
+    value: u32,
+    keypad: bool,
+) {
+    let mut digits = 4;
+    while digits < 8 && value >> (digits * 4) != 0 {
+        digits += 1;
+    }
+    for position in (0..digits).rev() {
+        let digit = (value >> (position * 4) & 0xF) as u8;
+        tap(reports, modifiers, hex_digit_key(digit, keypad));
+    }
+}
+
+fn hex_digit_key(digit: u8, keypad: bool) -> KeyboardUsage {
+    match (digit, keypad) {
+        (0, false) => return KeyboardUsage::Keyboard0CloseParens,
+        (0, true) => return KeyboardUsage::Keypad0Insert,
+        (1..=9, false) => {
+            return KeyboardUsage::from(KeyboardUsage::Keyboard1Exclamation as u8 + digit - 1)
+        }
+        (1..=9, true) => return KeyboardUsage::from(KeyboardUsage::Keypad1End as u8 + digit - 1),
+        _ => return KeyboardUsage::from(KeyboardUsage::KeyboardAa as u8 + digit - 10),
+    }
+}
+
+/// Presses the key on top of the modifiers, then releases it.
+fn tap(
+    reports: &mut Vec<KeyboardReportHelper, MAX_UNICODE_REPORTS>,
+    modifiers: Modifiers,
+    key: KeyboardUsage,
+) {
+    push(reports, modifiers, Some(key));
+    push(reports, modifiers, None);
+}
+
+fn push(
+    reports: &mut Vec<KeyboardReportHelper, MAX_UNICODE_REPORTS>,
+    modifiers: Modifiers,
+    key: Option<KeyboardUsage>,
+) {
+    let mut report = KeyboardReportHelper::new();
+    report.add_modifiers(modifiers);
+    if let Some(key) = key {
+        report.add_keycode(key);
+    }
+    let _ = reports.push(report);
+}
+
+#[cfg(test)]
+mod tests {
+    use usbd_hid::descriptor::KeyboardUsage::*;
+
+    use super::*;
+
+    fn report(modifiers: Modifiers, keys: &[KeyboardUsage]) -> KeyboardReportHelper {
+        let mut report = KeyboardReportHelper::new();
+        report.add_modifiers(modifiers);
+        for key in keys {
+            report.add_keycode(*key);
+        }
+        return report;
+    }
+
+    /// Returns the reports tapping the keys one after the other under the modifiers.
+    fn taps(modifiers: Modifiers, keys: &[KeyboardUsage]) -> std::vec::Vec<KeyboardReportHelper> {
+        let mut reports = std::vec::Vec::new();
+        for key in keys {
+            reports.push(report(modifiers, &[*key]));
+            reports.push(report(modifiers, &[]));
+        }
+        return reports;
+    }
+
+    #[test]
+    fn types_on_linux_through_ctrl_shift_u() {
+        let mut expected = std::vec![
+            report(
+                Modifiers::LEFT_CTRL.union(Modifiers::LEFT_SHIFT),
+                &[KeyboardUu]
+            ),
+            report(Modifiers::NONE, &[]),
+        ];
+        expected.extend(taps(
+            Modifiers::NONE,
+            &[
+                Keyboard0CloseParens,
+                Keyboard0CloseParens,
+                KeyboardEe,
+                Keyboard9OpenParens,
+            ],
+        ));
+        expected.extend(taps(Modifiers::NONE, &[KeyboardSpacebar]));
+        assert_eq!(
+            unicode_reports(UnicodeInput::Linux, 'é'),
+            expected.as_slice()
+        );
+    }
+
+    #[test]
+    fn types_on_windows_through_wincompose() {
+        let mut expected = taps(Modifiers::NONE, &[KeyboardRightAlt, KeyboardUu]);
+        expected.extend(taps(
+            Modifiers::NONE,
+            &[
+                Keyboard1Exclamation,
+                KeyboardFf,
+                Keyboard6Caret,
+                Keyboard0CloseParens,
+                Keyboard0CloseParens,
+                KeyboardEnter,
+            ],
+        ));
+        assert_eq!(
+            unicode_reports(UnicodeInput::WinCompose, '😀'),
+            expected.as_slice()
+        );
+    }
+
+    #[test]
+    fn types_on_windows_through_the_hex_keypad() {
+        let mut expected = std::vec![report(Modifiers::LEFT_ALT, &[])];
+        expected.extend(taps(
+            Modifiers::LEFT_ALT,
+            &[
+                KeypadPlus,
+                Keypad2DownArrow,
+                Keypad0Insert,
+                KeyboardAa,
+                KeyboardCc,
+            ],
+        ));
+        expected.push(report(Modifiers::NONE, &[]));
+        assert_eq!(
+            unicode_reports(UnicodeInput::WindowsAltCodes, '€'),
+            expected.as_slice()
+        );
+        // beyond the reach of the hex keypad
+        assert!(unicode_reports(UnicodeInput::WindowsAltCodes, '😀').is_empty());
+    }
+
+    #[test]
+    fn types_on_macos_through_utf16_code_units() {
+        let mut expected = std::vec![report(Modifiers::LEFT_ALT, &[])];
+        expected.extend(taps(
+            Modifiers::LEFT_ALT,
+            &[
+                KeyboardDd,
+                Keyboard8Asterisk,
+                Keyboard3Hash,
+                KeyboardDd,
+                KeyboardDd,
+                KeyboardEe,
+                Keyboard0CloseParens,
+                Keyboard0CloseParens,
+            ],
+        ));
+        expected.push(report(Modifiers::NONE, &[]));
+        assert_eq!(
+            unicode_reports(UnicodeInput::MacOs, '😀'),
+            expected.as_slice()
+        );
+    }
+
+    #[test]
+    fn fits_the_longest_inputs() {
+        for input in [
+            UnicodeInput::Linux,
+            UnicodeInput::WinCompose,
+            UnicodeInput::WindowsAltCodes,
+            UnicodeInput::MacOs,
+        ] {
+            for character in ['\u{FFFF}', '\u{10FFFF}'] {
+                // nothing has been cut off the end, which releases all the keys
+                match unicode_reports(input, character).last() {
+                    Some(last) => assert_eq!(*last, KeyboardReportHelper::new()),
+                    None => assert_eq!(input, UnicodeInput::WindowsAltCodes),
+                }
+            }
+            assert_eq!(UnicodeInput::from_byte(input.to_byte()), Some(input));
+        }
+    }
+}
@@ -0,0 +1,193 @@
+/// The strategy used by a [Debouncer] to filter chatter out of the raw key matrix.
+#[derive(Clone, Copy, PartialEq)]
+pub enum DebounceAlgorithm {
+    /// A key change is reported as soon as it is seen, after which the key ignores any further
+    /// change for the debounce time. Lowest latency, but sensitive to noise.
+    EagerPerKey,
+    /// A key change is reported once that key alone has been stable for the debounce time.
+    DeferredPerKey,
+    /// All key changes are reported together once the whole matrix has been stable for the
+    /// debounce time.
+    SymmetricDeferred,
+}
+
+/// Debounces a raw key matrix bitmap of `N` bytes (8 keys per byte).
+///
+/// The debouncer is a pure function over timestamped scans: feed every raw scan into
+/// [Debouncer::debounce] together with the time it was taken and use the returned bitmap in
+/// place of the raw one.
+pub struct Debouncer<const N: usize> {
+    algorithm: DebounceAlgorithm,
+    debounce_ms: u64,
+    debounced: [u8; N],
+    last_raw: [u8; N],
+    /// [DebounceAlgorithm::EagerPerKey]: time until which the key ignores changes.
+    /// [DebounceAlgorithm::DeferredPerKey]: time at which the key last changed.
+    key_times: [[u64; 8]; N],
+    /// [DebounceAlgorithm::SymmetricDeferred]: time at which the matrix last changed.
+    last_change: u64,
+}
+
+impl<const N: usize> Debouncer<N> {
+    pub const fn new(algorithm: DebounceAlgorithm, debounce_ms: u64) -> Debouncer<N> {
+        return Debouncer {
+            algorithm,
+            debounce_ms,
+            debounced: [0; N],
+            last_raw: [0; N],
+            key_times: [[0; 8]; N],
+            last_change: 0,
+        };
+    }
+
+    /// Takes a raw scan taken at `now_ms` and returns the debounced state of the matrix.
+    pub fn debounce(&mut self, now_ms: u64, raw: [u8; N]) -> [u8; N] {
+        match self.algorithm {
+            DebounceAlgorithm::EagerPerKey => self.eager_per_key(now_ms, &raw),
+            DebounceAlgorithm::DeferredPerKey => self.deferred_per_key(now_ms, &raw),
+            DebounceAlgorithm::SymmetricDeferred => self.symmetric_deferred(now_ms, &raw),
+        }
+        self.last_raw = raw;
+        return self.debounced;
+    }
+
+    /// Returns true if the debounced state has caught up with the last raw scan, i.e. feeding
+    /// the same scan again would not change anything.
+    pub fn is_settled(&self) -> bool {
+        return self.debounced == self.last_raw;
+    }
+
+    fn eager_per_key(&mut self, now_ms: u64, raw: &[u8; N]) {
+        let keys = self.debounced.iter_mut().zip(self.key_times.iter_mut());
+        for ((debounced, times), raw) in keys.zip(raw) {
+            let changed = raw ^ *debounced;
+            for (bit, time) in times.iter_mut().enumerate() {
+                let mask = 1 << bit;
+                if changed & mask == 0 || now_ms < *time {
+                    continue;
+                }
+                *debounced ^= mask;
+                *time = now_ms + self.debounce_ms;
+            }
+        }
+    }
+
+    fn deferred_per_key(&mut self, now_ms: u64, raw: &[u8; N]) {
+        let keys = self.debounced.iter_mut().zip(self.key_times.iter_mut());
+        for ((debounced, times), (raw, last_raw)) in keys.zip(raw.iter().zip(&self.last_raw)) {
+            let flickered = raw ^ last_raw;
+            for (bit, time) in times.iter_mut().enumerate() {
+                let mask = 1 << bit;
+                if flickered & mask != 0 {
+                    *time = now_ms;
+                }
+                if (raw ^ *debounced) & mask != 0 && now_ms - *time >= self.debounce_ms {
+                    *debounced ^= mask;
+                }
+            }
+        }
+    }
+
+    fn symmetric_deferred(&mut self, now_ms: u64, raw: &[u8; N]) {
+        if *raw != self.last_raw {
+            self.last_change = now_ms;
+        }
+        if *raw != self.debounced && now_ms - self.last_change >= self.debounce_ms {
+            self.debounced = *raw;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEBOUNCE_MS: u64 = 5;
+
+    /// Feeds the scans, one every millisecond from 100 ms on, and returns the debounced states.
+    fn debounce_scans(algorithm: DebounceAlgorithm, scans: &[u8]) -> Vec<u8> {
+        let mut debouncer: Debouncer<1> = Debouncer::new(algorithm, DEBOUNCE_MS);
+        let mut debounced = Vec::new();
+        for (time, scan) in scans.iter().enumerate() {
+            debounced.push(debouncer.debounce(100 + time as u64, [*scan])[0]);
+        }
+        return debounced;
+    }
+
+    #[test]
+    fn eager_per_key_reports_changes_right_away_and_ignores_their_chatter() {
+        let scans = [0, 1, 0, 1, 0, 1, 1, 1, 1, 0, 1, 0, 0, 0, 0, 0];
+        let debounced = [0, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0];
+        assert_eq!(
+            debounce_scans(DebounceAlgorithm::EagerPerKey, &scans),
+            debounced
+        );
+    }
+
+    #[test]
+    fn deferred_per_key_reports_changes_once_stable() {
+        let scans = [0, 1, 0, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0];
+        let debounced = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 0];
+        assert_eq!(
+            debounce_scans(DebounceAlgorithm::DeferredPerKey, &scans),
+            debounced
+        );
+    }
+
+    #[test]
+    fn per_key_algorithms_debounce_each_key_on_its_own() {
+        // the first key is pressed cleanly while the second one chatters
+        let scans = [0b01, 0b11, 0b01, 0b11, 0b01, 0b11, 0b01, 0b01];
+        let debounced = [0b01, 0b11, 0b11, 0b11, 0b11, 0b11, 0b01, 0b01];
+        assert_eq!(
+            debounce_scans(DebounceAlgorithm::EagerPerKey, &scans),
+            debounced
+        );
+        let debounced = [0, 0, 0, 0, 0, 0b01, 0b01, 0b01];
+        assert_eq!(
+            debounce_scans(DebounceAlgorithm::DeferredPerKey, &scans),
+            debounced
+        );
+    }
+
+    #[test]
+    fn symmetric_deferred_waits_for_the_whole_matrix() {
+        let scans = [0b01, 0b11, 0b01, 0b11, 0b01, 0b01, 0b01, 0b01, 0b01, 0b01];
+        let debounced = [0, 0, 0, 0, 0, 0, 0, 0, 0, 0b01];
+        assert_eq!(
+            debounce_scans(DebounceAlgorithm::SymmetricDeferred, &scans),
+            debounced
+        );
+        // changes seen together are reported together
+        let scans = [
+            0b11, 0b11, 0b11, 0b11, 0b11, 0b11, 0b00, 0b00, 0b00, 0b00, 0b00, 0b00,
+        ];
+        let debounced = [0, 0, 0, 0, 0, 0b11, 0b11, 0b11, 0b11, 0b11, 0b11, 0];
+        assert_eq!(
+            debounce_scans(DebounceAlgorithm::SymmetricDeferred, &scans),
+            debounced
+        );
+    }
+
+    #[test]
+    fn settles_once_the_debounced_state_catches_up() {
+        let mut eager: Debouncer<2> = Debouncer::new(DebounceAlgorithm::EagerPerKey, DEBOUNCE_MS);
+        assert!(eager.is_settled());
+        eager.debounce(100, [0, 1]);
+        assert!(eager.is_settled());
+        // a bounce within the debounce time is held back
+        eager.debounce(101, [0, 0]);
+        assert!(!eager.is_settled());
+        eager.debounce(102, [0, 1]);
+        assert!(eager.is_settled());
+
+        let mut deferred: Debouncer<2> =
+            Debouncer::new(DebounceAlgorithm::DeferredPerKey, DEBOUNCE_MS);
+        deferred.debounce(100, [1, 0]);
+        assert!(!deferred.is_settled());
+        deferred.debounce(104, [1, 0]);
+        assert!(!deferred.is_settled());
+        assert_eq!(deferred.debounce(105, [1, 0]), [1, 0]);
+        assert!(deferred.is_settled());
+    }
+}
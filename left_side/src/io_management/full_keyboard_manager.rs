@@ -1,41 +1,392 @@
+use usbd_hid::descriptor::KeyboardUsage;
+
 use crate::{
-    hid_helper::keyboard_report::KeyboardReportHelper,
-    profiles_management::keyboard_profile::keyboard_profile::KeyboardProfile,
-    report_buffer::buffer::KeyboardRingBuffer,
+    hid_helper::{keyboard_report::KeyboardReportHelper, modifiers::Modifiers},
+    key_features::key_state::KeyState,
+    macros::macro_player::MacroOutput,
+    profiles_management::{
+        keyboard_profile::keyboard_profile::KeyboardProfile, keymap_store::KeymapStore,
+    },
+    report_buffer::{buffer::ReportProducer, report_tracker::ReportTracker},
+    settings::keyboard_settings::KeyboardSettings,
+};
+
+use super::{
+    left_half_manager::LeftReadout,
+    right_half_manager::RightReadout,
+    rotary_encoder::{EncoderDirection, EncoderLocation, EncoderStepper},
 };
 
-use super::{left_half_manager::LeftReadout, right_half_manager::RightReadout};
+/// Number of pending reports above which the macro playback waits for the buffer to drain, so
+/// that a long macro cannot crowd out the reports of the keys.
+const MACRO_BUFFER_LIMIT: usize = 10;
 
-pub struct FullKeyboardManager {
+/// What the macro runner has to do after [FullKeyboardManager::play_macro].
+pub enum MacroPlayback {
+    /// All the queued macros have been played.
+    Finished,
+    /// The macro asks for a pause of the given number of milliseconds.
+    Delay(u16),
+    /// The buffer is too full, playback resumes once it has drained.
+    BufferFull,
+}
+
+pub struct FullKeyboardManager<'a> {
     right_readout: RightReadout,
     left_readout: LeftReadout,
-    buffer: KeyboardRingBuffer,
+    buffer: ReportProducer<'a>,
+    tracker: ReportTracker<KeyboardReportHelper>,
+    state: KeyState,
+    /// Report of the keys held, as last built from the readouts.
+    held_report: KeyboardReportHelper,
+    layer: u8,
+    /// The halves were swapped for the last key changes, see
+    /// [SwapHands](crate::key_features::swap_hands::SwapHands).
+    swapped: bool,
+    left_encoder: EncoderStepper,
+    right_encoder: EncoderStepper,
 }
 
-impl FullKeyboardManager {
-    pub fn new(buffer: KeyboardRingBuffer) -> Self {
+impl<'a> FullKeyboardManager<'a> {
+    pub fn new(buffer: ReportProducer<'a>) -> Self {
         return FullKeyboardManager {
             buffer,
             left_readout: LeftReadout::default(),
             right_readout: RightReadout::default(),
+            tracker: ReportTracker::new(KeyboardReportHelper::new()),
+            state: KeyState::new(),
+            held_report: KeyboardReportHelper::new(),
+            layer: 0,
+            swapped: false,
+            left_encoder: EncoderStepper::new(),
+            right_encoder: EncoderStepper::new(),
         };
     }
 
-    pub fn update_right_readout(&mut self, readout: RightReadout, profile: &KeyboardProfile) {
+    pub fn update_right_readout(
+        &mut self,
+        readout: RightReadout,
+        profile: &KeyboardProfile,
+        now_us: u64,
+    ) {
         self.right_readout = readout;
-        self.process_readouts(profile);
+        self.process_readouts(profile, now_us);
     }
 
-    pub fn update_left_readout(&mut self, readout: LeftReadout, profile: &KeyboardProfile) {
+    pub fn update_left_readout(
+        &mut self,
+        readout: LeftReadout,
+        profile: &KeyboardProfile,
+        now_us: u64,
+    ) {
         self.left_readout = readout;
-        self.process_readouts(profile);
+        self.process_readouts(profile, now_us);
+    }
+
+    /// Taps the encoder action once for every step the counts complete, at the resolution of
+    /// the encoder in the profile.
+    pub fn process_encoder(
+        &mut self,
+        encoder: EncoderLocation,
+        direction: EncoderDirection,
+        counts: u8,
+        profile: &KeyboardProfile,
+        now_us: u64,
+    ) {
+        let stepper = match encoder {
+            EncoderLocation::Left => &mut self.left_encoder,
+            EncoderLocation::Right => &mut self.right_encoder,
+        };
+        let counts_per_step = profile.encoder_actions(encoder).counts_per_step;
+        let steps = stepper.count(direction, counts, counts_per_step);
+        for _ in 0..steps {
+            self.state.begin_report(now_us, profile);
+            let (mut report, mut held_report) = profile.process_encoder(
+                encoder,
+                direction,
+                &self.left_readout,
+                &self.right_readout,
+                &mut self.state,
+            );
+            if let Some(key) = self.state.finish_report() {
+                self.tap_held_back_key(profile, report, key, now_us);
+            }
+            self.state.apply(&mut report, profile);
+            self.state.apply(&mut held_report, profile);
+            self.held_report = held_report;
+            self.put_report(report, now_us);
+            self.put_report(held_report, now_us);
+        }
+    }
+
+    /// To be called when the key changes go through another profile from now on. Releases all
+    /// the keys, the keys held across the switch may stand for something else in the new
+    /// profile. They are pressed again with the next key change.
+    pub fn switch_profile(&mut self, now_us: u64) {
+        self.held_report = KeyboardReportHelper::new();
+        self.put_report(KeyboardReportHelper::new(), now_us);
+    }
+
+    /// Moves the report that did not fit into the buffer in, once the buffer has drained.
+    pub fn flush_reports(&mut self) {
+        self.buffer.flush();
+    }
+
+    /// Returns true if a macro has been triggered and still has to be played.
+    pub fn is_macro_pending(&self) -> bool {
+        return self.state.macros.is_pending();
+    }
+
+    /// Returns true while Caps Word is on.
+    pub fn is_caps_word_active(&self) -> bool {
+        return self.state.caps_word.is_active();
+    }
+
+    /// Returns true while a leader sequence is being typed.
+    pub fn is_leader_active(&self) -> bool {
+        return self.state.leader.is_active();
+    }
+
+    /// Returns the modifiers one-shot keys have been tapped or locked for.
+    pub fn one_shot_modifiers(&self) -> Modifiers {
+        return self.state.one_shot.waiting_modifiers();
+    }
+
+    pub fn settings(&self) -> KeyboardSettings {
+        return self.state.settings();
+    }
+
+    /// Replaces the settings with the ones saved before a restart.
+    pub fn load_settings(&mut self, settings: KeyboardSettings) {
+        self.state.load_settings(settings);
+    }
+
+    /// Returns the settings if key actions changed them since the last call, for them to be
+    /// saved.
+    pub fn take_changed_settings(&mut self) -> Option<KeyboardSettings> {
+        return self.state.take_changed_settings();
+    }
+
+    /// Returns the keymap edited at runtime, empty until a keymap is loaded into it.
+    pub fn keymap_store(&self) -> &KeymapStore {
+        return &self.state.keymap_store;
+    }
+
+    /// Gives access to the keymap edited at runtime, the keys changing from the next key change
+    /// on.
+    pub fn keymap_store_mut(&mut self) -> &mut KeymapStore {
+        return &mut self.state.keymap_store;
+    }
+
+    /// Lets the timed modes expire at `now_us`, releasing or changing the keys they held.
+    pub fn tick(&mut self, profile: &KeyboardProfile, now_us: u64) {
+        if self.state.tick(now_us) {
+            self.process_readouts(profile, now_us);
+        }
+    }
+
+    /// Returns the next time [FullKeyboardManager::tick] has to be called at, if any.
+    pub fn next_deadline_us(&self) -> Option<u64> {
+        return self.state.next_deadline_us();
+    }
+
+    /// Plays the pending macros into the buffer until they are over, ask for a delay or the
+    /// buffer fills up.
+    pub fn play_macro(&mut self, now_us: u64) -> MacroPlayback {
+        loop {
+            if self.buffer.len() >= MACRO_BUFFER_LIMIT {
+                return MacroPlayback::BufferFull;
+            }
+            let settings = self.state.settings();
+            let store = &self.state.keymap_store;
+            match self.state.macros.next_output(&settings, store) {
+                Some(MacroOutput::Report(report)) => {
+                    // the keys held stay down under the macro
+                    let mut merged = self.held_report;
+                    merged.add_report(&report);
+                    self.put_report(merged, now_us);
+                }
+                Some(MacroOutput::Delay(delay_ms)) => return MacroPlayback::Delay(delay_ms),
+                None => {
+                    // whatever the macros did, the host is left with the keys held
+                    self.put_report(self.held_report, now_us);
+                    return MacroPlayback::Finished;
+                }
+            }
+        }
+    }
+
+    fn process_readouts(&mut self, profile: &KeyboardProfile, now_us: u64) {
+        let layer = profile.active_layer(&self.left_readout, &self.right_readout, &self.state);
+        let swapped = self.state.swap_hands.is_active();
+        if layer != self.layer || swapped != self.swapped {
+            // the keys held across the change may stand for something else on the new layer
+            self.layer = layer;
+            self.swapped = swapped;
+            self.put_report(KeyboardReportHelper::new(), now_us);
+        }
+        self.state.begin_report(now_us, profile);
+        let mut report =
+            profile.process_readout(&self.left_readout, &self.right_readout, &mut self.state);
+        if let Some(key) = self.state.finish_report() {
+            self.tap_held_back_key(profile, report, key, now_us);
+        }
+        if self.state.swap_hands.is_active() != swapped {
+            // a swap-hands key changed the halves the keys have just been looked up on
+            self.process_readouts(profile, now_us);
+            return;
+        }
+        self.state.apply(&mut report, profile);
+        self.held_report = report;
+        self.put_report(report, now_us);
+    }
+
+    /// Taps a key auto-shift held back until its release, on top of the keys still held.
+    fn tap_held_back_key(
+        &mut self,
+        profile: &KeyboardProfile,
+        report: KeyboardReportHelper,
+        key: KeyboardUsage,
+        now_us: u64,
+    ) {
+        let mut tap_report = report;
+        tap_report.add_keycode(key);
+        self.state.apply(&mut tap_report, profile);
+        self.put_report(tap_report, now_us);
+    }
+
+    /// Every report sent to the host goes through here, which is also where macros get recorded.
+    /// Reports not changing the state of the host are dropped. `now_us` is the time the key
+    /// change behind the report has been seen.
+    fn put_report(&mut self, report: KeyboardReportHelper, now_us: u64) {
+        if !self.tracker.track(report) {
+            return;
+        }
+        self.state.macros.record(now_us / 1000, report);
+        self.buffer.put_report(report, now_us);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use usbd_hid::descriptor::KeyboardUsage::*;
+
+    use crate::{
+        io_management::{
+            key_position::KeyPosition, left_half_manager::LeftKeyLocation::*,
+            right_half_manager::RightKeyLocation::*,
+        },
+        key_features::{auto_shift::AutoShiftConfig, swap_hands::SwapHandsMode},
+        macros::macro_step::MacroStep,
+        profiles_management::{
+            keyboard_profile::keyboard_profile::{
+                KeyAction, Layer,
+                UniversalKey::{LeftKey, RightKey},
+            },
+            keymap_tester::{
+                base_layer_keymap, report, test_profile, KeymapTester, Step::*, TAP_MS,
+            },
+            layers::LAYER_COUNT,
+        },
+    };
+
+    const KEYMAP: [Layer; LAYER_COUNT as usize] = base_layer_keymap(&[
+        (KeyPosition::left(C2R1), KeyAction::HidKey(KeyboardQq)),
+        (KeyPosition::left(C3R1), KeyAction::HidKey(KeyboardWw)),
+        (KeyPosition::left(C6R2), KeyAction::HidKey(KeyboardGg)),
+        (
+            KeyPosition::left(LT2),
+            KeyAction::SwapHands(SwapHandsMode::Momentary),
+        ),
+        (KeyPosition::right(C11R1), KeyAction::HidKey(KeyboardPp)),
+        (KeyPosition::right(C7R2), KeyAction::HidKey(KeyboardHh)),
+        // macros on both ends of the order the keys are looked at in
+        (
+            KeyPosition::left(C1R1),
+            KeyAction::Macro(&[MacroStep::Tap(KeyboardF1)]),
+        ),
+        (
+            KeyPosition::right(RT3),
+            KeyAction::Macro(&[MacroStep::Tap(KeyboardF2)]),
+        ),
+    ]);
+
+    #[test]
+    fn taps_the_keys_auto_shift_held_back() {
+        let mut profile = test_profile(&KEYMAP);
+        profile.auto_shift = AutoShiftConfig {
+            timeout_ms: 175,
+            letters: true,
+            numbers: false,
+            symbols: false,
+        };
+        let mut keyboard = KeymapTester::new(profile);
+        keyboard.run(&[Tap(LeftKey(C2R1))]);
+        let sent = keyboard.take_reports();
+        assert_eq!(sent.len(), 2);
+        assert_eq!(sent[0].report, report(&[KeyboardQq]));
+        assert_eq!(sent[1].report, report(&[]));
+        assert!(sent.iter().all(|sent| sent.time_ms == TAP_MS));
+        // shifted by the timer of the timed modes once held past the timeout
+        keyboard.run(&[Press(LeftKey(C3R1)), Wait(174)]);
+        keyboard.expect_reports(&[]);
+        keyboard.run(&[Wait(1), Release(LeftKey(C3R1))]);
+        keyboard.expect_reports(&[&[KeyboardLeftShift, KeyboardWw], &[]]);
+    }
+
+    #[test]
+    fn macros_leave_the_held_keys_in_the_report() {
+        let mut keyboard = KeymapTester::new(test_profile(&KEYMAP));
+        keyboard.run(&[
+            Press(LeftKey(C2R1)),
+            Tap(LeftKey(C1R1)),
+            Tap(RightKey(RT3)),
+            Release(LeftKey(C2R1)),
+        ]);
+        keyboard.expect_reports(&[
+            &[KeyboardQq],
+            &[KeyboardQq, KeyboardF1],
+            &[KeyboardQq],
+            &[KeyboardQq, KeyboardF2],
+            &[KeyboardQq],
+            &[],
+        ]);
+    }
+
+    #[test]
+    fn held_macro_keys_play_their_macro_once() {
+        let mut keyboard = KeymapTester::new(test_profile(&KEYMAP));
+        keyboard.run(&[
+            Press(RightKey(RT3)),
+            Tap(LeftKey(C2R1)),
+            Tap(LeftKey(C2R1)),
+            Release(RightKey(RT3)),
+        ]);
+        keyboard.expect_reports(&[&[KeyboardF2], &[], &[KeyboardQq], &[], &[KeyboardQq], &[]]);
     }
 
-    pub fn get_report_helper(&mut self) -> Option<KeyboardReportHelper> {
-        self.buffer.get_report_helper()
+    #[test]
+    fn swaps_hands_while_the_key_is_held() {
+        let mut keyboard = KeymapTester::new(test_profile(&KEYMAP));
+        keyboard.run(&[
+            Press(LeftKey(LT2)),
+            Tap(LeftKey(C2R1)),
+            Tap(RightKey(C7R2)),
+            Release(LeftKey(LT2)),
+            Tap(LeftKey(C2R1)),
+        ]);
+        keyboard.expect_reports(&[&[KeyboardPp], &[], &[KeyboardGg], &[], &[KeyboardQq], &[]]);
     }
 
-    fn process_readouts(&mut self, profile: &KeyboardProfile) {
-        profile.process_readout(&self.left_readout, &self.right_readout, &mut self.buffer);
+    #[test]
+    fn swaps_the_keys_held_across_the_swap_hands_key() {
+        let mut keyboard = KeymapTester::new(test_profile(&KEYMAP));
+        keyboard.run(&[
+            Press(LeftKey(C2R1)),
+            Press(LeftKey(LT2)),
+            Release(LeftKey(LT2)),
+            Release(LeftKey(C2R1)),
+        ]);
+        keyboard.expect_reports(&[&[KeyboardQq], &[], &[KeyboardPp], &[], &[KeyboardQq], &[]]);
     }
 }
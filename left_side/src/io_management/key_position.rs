@@ -0,0 +1,163 @@
+use crate::profiles_management::keyboard_profile::keyboard_profile::UniversalKey;
+
+use super::{
+    left_half_manager::{LeftKeyLocation, LeftReadout},
+    right_half_manager::{RightKeyLocation, RightReadout},
+};
+
+/// Number of keys of the keyboard, both halves together.
+pub const POSITIONS: usize = 42;
+/// Number of keys of a row, both halves together.
+const ROW_KEYS: usize = 12;
+/// Number of columns of the matrix the positions make up, one a key of a row.
+pub const MATRIX_COLUMNS: usize = ROW_KEYS;
+/// Number of rows of the matrix the positions make up, the thumb keys starting the last one.
+pub const MATRIX_ROWS: usize = POSITIONS.div_ceil(ROW_KEYS);
+/// Number of keys of a row of one half.
+const HALF_ROW_KEYS: usize = 6;
+/// Number of keys of the rows of one half, its thumb keys coming after them in its locations.
+const HALF_MATRIX_KEYS: usize = 3 * HALF_ROW_KEYS;
+/// Position of the first thumb key, the thumb keys come after the three rows.
+const FIRST_THUMB: usize = 3 * ROW_KEYS;
+/// Number of thumb keys of one half.
+const HALF_THUMBS: usize = 3;
+
+/// The keys of the left half in the order of [LeftKeyLocation].
+const LEFT_LOCATIONS: [LeftKeyLocation; 21] = {
+    use LeftKeyLocation::*;
+    [
+        C1R1, C2R1, C3R1, C4R1, C5R1, C6R1, C1R2, C2R2, C3R2, C4R2, C5R2, C6R2, C1R3, C2R3, C3R3,
+        C4R3, C5R3, C6R3, LT1, LT2, LT3,
+    ]
+};
+
+/// The keys of the right half in the order of [RightKeyLocation].
+const RIGHT_LOCATIONS: [RightKeyLocation; 21] = {
+    use RightKeyLocation::*;
+    [
+        C7R1, C8R1, C9R1, C10R1, C11R1, C12R1, C7R2, C8R2, C9R2, C10R2, C11R2, C12R2, C7R3, C8R3,
+        C9R3, C10R3, C11R3, C12R3, RT1, RT2, RT3,
+    ]
+};
+
+/// A key of either half, numbered row by row from the outer column of the left half to the
+/// outer column of the right half, the thumb keys of the left then the right half coming last.
+/// Keymaps are indexed by it, and the keys of a report come in its order.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct KeyPosition(u8);
+
+impl KeyPosition {
+    pub const fn left(location: LeftKeyLocation) -> KeyPosition {
+        let index = location as usize;
+        if index < HALF_MATRIX_KEYS {
+            let row = index / HALF_ROW_KEYS;
+            return KeyPosition((row * ROW_KEYS + index % HALF_ROW_KEYS) as u8);
+        }
+        return KeyPosition((FIRST_THUMB + index - HALF_MATRIX_KEYS) as u8);
+    }
+
+    pub const fn right(location: RightKeyLocation) -> KeyPosition {
+        let index = location as usize;
+        if index < HALF_MATRIX_KEYS {
+            let row = index / HALF_ROW_KEYS;
+            return KeyPosition((row * ROW_KEYS + HALF_ROW_KEYS + index % HALF_ROW_KEYS) as u8);
+        }
+        return KeyPosition((FIRST_THUMB + HALF_THUMBS + index - HALF_MATRIX_KEYS) as u8);
+    }
+
+    /// Returns the position at the index, [None] past the last key.
+    pub const fn from_index(index: usize) -> Option<KeyPosition> {
+        if index >= POSITIONS {
+            return None;
+        }
+        return Some(KeyPosition(index as u8));
+    }
+
+    pub const fn index(self) -> usize {
+        return self.0 as usize;
+    }
+
+    /// Returns all the positions, in order.
+    pub fn all() -> impl Iterator<Item = KeyPosition> {
+        return (0..POSITIONS as u8).map(KeyPosition);
+    }
+
+    /// Returns the half the key is on, along with its place on it.
+    pub fn location(self) -> UniversalKey {
+        let index = self.index();
+        if index >= FIRST_THUMB + HALF_THUMBS {
+            let thumb = index - FIRST_THUMB - HALF_THUMBS;
+            return UniversalKey::RightKey(RIGHT_LOCATIONS[HALF_MATRIX_KEYS + thumb]);
+        }
+        if index >= FIRST_THUMB {
+            let thumb = index - FIRST_THUMB;
+            return UniversalKey::LeftKey(LEFT_LOCATIONS[HALF_MATRIX_KEYS + thumb]);
+        }
+        let (row, column) = (index / ROW_KEYS, index % ROW_KEYS);
+        if column < HALF_ROW_KEYS {
+            return UniversalKey::LeftKey(LEFT_LOCATIONS[row * HALF_ROW_KEYS + column]);
+        }
+        let column = column - HALF_ROW_KEYS;
+        return UniversalKey::RightKey(RIGHT_LOCATIONS[row * HALF_ROW_KEYS + column]);
+    }
+
+    pub fn is_pressed(self, left_readout: &LeftReadout, right_readout: &RightReadout) -> bool {
+        match self.location() {
+            UniversalKey::LeftKey(location) => return left_readout.is_pressed(&location),
+            UniversalKey::RightKey(location) => return right_readout.is_pressed(&location),
+        }
+    }
+}
+
+impl From<LeftKeyLocation> for KeyPosition {
+    fn from(location: LeftKeyLocation) -> Self {
+        return KeyPosition::left(location);
+    }
+}
+
+impl From<RightKeyLocation> for KeyPosition {
+    fn from(location: RightKeyLocation) -> Self {
+        return KeyPosition::right(location);
+    }
+}
+
+impl From<UniversalKey> for KeyPosition {
+    fn from(key: UniversalKey) -> Self {
+        match key {
+            UniversalKey::LeftKey(location) => return KeyPosition::left(location),
+            UniversalKey::RightKey(location) => return KeyPosition::right(location),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numbers_the_keys_row_by_row() {
+        assert_eq!(KeyPosition::left(LeftKeyLocation::C1R1).index(), 0);
+        assert_eq!(KeyPosition::right(RightKeyLocation::C7R1).index(), 6);
+        assert_eq!(KeyPosition::right(RightKeyLocation::C12R1).index(), 11);
+        assert_eq!(KeyPosition::left(LeftKeyLocation::C1R2).index(), 12);
+        assert_eq!(KeyPosition::right(RightKeyLocation::C12R3).index(), 35);
+        assert_eq!(KeyPosition::left(LeftKeyLocation::LT1).index(), 36);
+        assert_eq!(KeyPosition::right(RightKeyLocation::RT3).index(), 41);
+        assert_eq!(KeyPosition::from_index(POSITIONS), None);
+    }
+
+    #[test]
+    fn converts_every_location_back_and_forth() {
+        for location in LEFT_LOCATIONS {
+            let position = KeyPosition::from(location);
+            assert!(position.location() == UniversalKey::LeftKey(location));
+        }
+        for location in RIGHT_LOCATIONS {
+            let position = KeyPosition::from(location);
+            assert!(position.location() == UniversalKey::RightKey(location));
+        }
+        for position in KeyPosition::all() {
+            assert_eq!(KeyPosition::from(position.location()), position);
+        }
+    }
+}
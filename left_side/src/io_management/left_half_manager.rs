@@ -1,9 +1,15 @@
+#[cfg(target_os = "none")]
+use embassy_futures::select::select_array;
+#[cfg(target_os = "none")]
 use embassy_rp::{
     gpio::{Input, Output, Pin},
     Peripheral,
 };
+#[cfg(target_os = "none")]
 use embassy_time::Timer;
 
+/// Scans the left half matrix, only part of firmware builds.
+#[cfg(target_os = "none")]
 pub struct LeftIoManager<'a> {
     row_1: Output<'a>,
     row_2: Output<'a>,
@@ -19,6 +25,7 @@ pub struct LeftIoManager<'a> {
     lt_3: Input<'a>,
 }
 
+#[cfg(target_os = "none")]
 impl<'d> LeftIoManager<'d> {
     pub fn new(
         row_1: impl Peripheral<P = impl Pin> + 'd,
@@ -129,6 +136,34 @@ impl<'d> LeftIoManager<'d> {
             row_3: row_3_readout,
         };
     }
+
+    /// Drives all the rows low and waits until any column or thumb cluster input goes low,
+    /// i.e. until any key is pressed. The core is free to sleep while waiting. The rows are
+    /// released again before returning, so the next [LeftIoManager::produce_readout] works as usual.
+    pub async fn wait_for_key_press(&mut self) {
+        self.row_1.set_low();
+        self.row_2.set_low();
+        self.row_3.set_low();
+        Timer::after_micros(100).await;
+
+        select_array([
+            self.column_1.wait_for_low(),
+            self.column_2.wait_for_low(),
+            self.column_3.wait_for_low(),
+            self.column_4.wait_for_low(),
+            self.column_5.wait_for_low(),
+            self.column_6.wait_for_low(),
+            self.lt_1.wait_for_low(),
+            self.lt_2.wait_for_low(),
+            self.lt_3.wait_for_low(),
+        ])
+        .await;
+
+        self.row_1.set_high();
+        self.row_2.set_high();
+        self.row_3.set_high();
+        Timer::after_micros(100).await;
+    }
 }
 
 #[derive(PartialEq, Clone, Copy)]
@@ -150,6 +185,26 @@ impl Default for LeftReadout {
 }
 
 impl LeftReadout {
+    /// Builds a readout from bytes in [ row_1 , row_2 , row_3 , thumb_cluster ] order
+    pub fn from_bytes(bytes: [u8; 4]) -> Self {
+        return LeftReadout {
+            row_1: bytes[0],
+            row_2: bytes[1],
+            row_3: bytes[2],
+            ltc: bytes[3],
+        };
+    }
+
+    /// Returns bytes in [ row_1 , row_2 , row_3 , thumb_cluster ] order
+    pub fn as_bytes(&self) -> [u8; 4] {
+        return [self.row_1, self.row_2, self.row_3, self.ltc];
+    }
+
+    /// Returns true if no key is pressed.
+    pub fn is_empty(&self) -> bool {
+        return self.as_bytes() == [0; 4];
+    }
+
     pub fn is_pressed(&self, location: &LeftKeyLocation) -> bool {
         match location {
             LeftKeyLocation::C1R1 => return self.row_1 & 0b00100000 != 0,
@@ -177,6 +232,7 @@ impl LeftReadout {
     }
 }
 
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub enum LeftKeyLocation {
     C1R1,
     C2R1,
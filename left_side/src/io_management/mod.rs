@@ -1,3 +1,11 @@
+pub mod debounce;
 pub mod full_keyboard_manager;
+pub mod key_position;
 pub mod left_half_manager;
+#[cfg(all(target_os = "none", feature = "rotary-encoder"))]
+pub mod pio_encoder;
+#[cfg(all(target_os = "none", feature = "pio-scan"))]
+pub mod pio_scanner;
 pub mod right_half_manager;
+pub mod rotary_encoder;
+pub mod virtual_keyboard;
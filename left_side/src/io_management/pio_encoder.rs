@@ -0,0 +1,76 @@
+use embassy_rp::{
+    peripherals::PIO1,
+    pio::{Common, Config, Direction, FifoJoin, PioPin, ShiftDirection, StateMachine},
+    Peripheral,
+};
+use fixed::traits::ToFixed;
+
+/// A rotary encoder decoded on a PIO state machine.
+///
+/// The state machine pushes one count for every full quadrature cycle. The counts are grouped
+/// into steps by the key processing of the left half, at the resolution the profile sets for the
+/// encoder.
+pub struct PioRotaryEncoder<'a> {
+    sm: StateMachine<'a, PIO1, 0>,
+    /// Counts not yet taken, positive counts being clockwise.
+    counts: i8,
+}
+
+impl<'d> PioRotaryEncoder<'d> {
+    pub fn new(
+        common: &mut Common<'d, PIO1>,
+        mut sm: StateMachine<'d, PIO1, 0>,
+        pin_a: impl Peripheral<P = impl PioPin + 'd> + 'd,
+        pin_b: impl Peripheral<P = impl PioPin + 'd> + 'd,
+    ) -> PioRotaryEncoder<'d> {
+        // waits for a falling edge on B, A is high on it when turning clockwise
+        let program =
+            pio_proc::pio_asm!("wait 1 pin 1", "wait 0 pin 1", "in pins, 2", "push block");
+        let program = common.load_program(&program.program);
+
+        let mut pin_a = common.make_pio_pin(pin_a);
+        pin_a.set_pull(embassy_rp::gpio::Pull::Up);
+        let mut pin_b = common.make_pio_pin(pin_b);
+        pin_b.set_pull(embassy_rp::gpio::Pull::Up);
+        sm.set_pin_dirs(Direction::In, &[&pin_a, &pin_b]);
+
+        let mut config = Config::default();
+        config.use_program(&program, &[]);
+        config.set_in_pins(&[&pin_a, &pin_b]);
+        config.fifo_join = FifoJoin::RxOnly;
+        config.shift_in.direction = ShiftDirection::Left;
+        // slow sampling filters out contact bounce
+        config.clock_divider = 10_000.to_fixed();
+        sm.set_config(&config);
+        sm.set_enable(true);
+
+        return PioRotaryEncoder { sm, counts: 0 };
+    }
+
+    /// Waits until the encoder has been turned by at least one count. The counts are left to be
+    /// taken with [PioRotaryEncoder::take_counts].
+    pub async fn wait_for_counts(&mut self) {
+        while self.counts == 0 {
+            let count = self.sm.rx().wait_pull().await;
+            self.count(count);
+        }
+    }
+
+    /// Takes all the counts made since the last call, without waiting.
+    pub fn take_counts(&mut self) -> i8 {
+        while let Some(count) = self.sm.rx().try_pull() {
+            self.count(count);
+        }
+        let counts = self.counts;
+        self.counts = 0;
+        return counts;
+    }
+
+    fn count(&mut self, count: u32) {
+        match count {
+            0 => self.counts = self.counts.saturating_sub(1),
+            1 => self.counts = self.counts.saturating_add(1),
+            _ => {}
+        }
+    }
+}
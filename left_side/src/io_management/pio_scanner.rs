@@ -0,0 +1,179 @@
+use embassy_futures::select::{select, select_array};
+use embassy_rp::{
+    dma::{AnyChannel, Channel},
+    gpio::{Input, Level, Pin},
+    peripherals::PIO0,
+    pio::{Common, Config, Direction, FifoJoin, PioPin, ShiftDirection, StateMachine},
+    Peripheral, PeripheralRef,
+};
+use fixed::traits::ToFixed;
+
+use super::left_half_manager::LeftReadout;
+
+/// Number of state machine cycles the scanning program takes for one unchanged scan of the
+/// matrix, used to derive the clock divider from the requested scan rate.
+const CYCLES_PER_SCAN: u32 = 103;
+
+/// Value written into the DMA buffer before a transfer, the program never pushes it.
+const NO_SCAN: u32 = u32::MAX;
+
+/// Scans the key matrix on a PIO state machine instead of the CPU.
+///
+/// The state machine strobes the rows and samples the columns at a fixed rate, and only pushes
+/// a scan into its FIFO when it differs from the previous one. The scan is moved into memory by
+/// DMA, so the CPU only wakes up when the matrix changes. The thumb cluster is not part of the
+/// matrix and is watched through GPIO edge interrupts instead.
+pub struct LeftPioScanner<'a> {
+    sm: StateMachine<'a, PIO0, 0>,
+    dma: PeripheralRef<'a, AnyChannel>,
+    lt_1: Input<'a>,
+    lt_2: Input<'a>,
+    lt_3: Input<'a>,
+    /// DMA buffer, holds [NO_SCAN] until a scan lands.
+    scan: [u32; 1],
+    /// Last matrix scan, bit set means pressed.
+    matrix: u32,
+}
+
+impl<'d> LeftPioScanner<'d> {
+    pub fn new(
+        common: &mut Common<'d, PIO0>,
+        mut sm: StateMachine<'d, PIO0, 0>,
+        dma: impl Peripheral<P = impl Channel> + 'd,
+        row_1: impl Peripheral<P = impl PioPin + 'd> + 'd,
+        row_2: impl Peripheral<P = impl PioPin + 'd> + 'd,
+        row_3: impl Peripheral<P = impl PioPin + 'd> + 'd,
+        column_1: impl Peripheral<P = impl PioPin + 'd> + 'd,
+        column_2: impl Peripheral<P = impl PioPin + 'd> + 'd,
+        column_3: impl Peripheral<P = impl PioPin + 'd> + 'd,
+        column_4: impl Peripheral<P = impl PioPin + 'd> + 'd,
+        column_5: impl Peripheral<P = impl PioPin + 'd> + 'd,
+        column_6: impl Peripheral<P = impl PioPin + 'd> + 'd,
+        lt_1: impl Peripheral<P = impl Pin> + 'd,
+        lt_2: impl Peripheral<P = impl Pin> + 'd,
+        lt_3: impl Peripheral<P = impl Pin> + 'd,
+        scan_rate_hz: u32,
+    ) -> LeftPioScanner<'d> {
+        // Rows are driven low one at a time (active low, like the GPIO scanner), each row is
+        // given 32 cycles to settle before its 6 columns are shifted into the ISR. The 18 bit
+        // scan is compared against the previous one kept in Y and only pushed when it changed.
+        let program = pio_proc::pio_asm!(
+            "    set y, 0",
+            ".wrap_target",
+            "scan:",
+            "    set pins, 0b110 [31]",
+            "    in pins, 6",
+            "    set pins, 0b101 [31]",
+            "    in pins, 6",
+            "    set pins, 0b011 [31]",
+            "    in pins, 6",
+            "    set pins, 0b111",
+            "    mov x, isr",
+            "    mov isr, null",
+            "    jmp x!=y changed",
+            ".wrap",
+            "changed:",
+            "    mov y, x",
+            "    in y, 32",
+            "    push block",
+            "    jmp scan",
+        );
+        let program = common.load_program(&program.program);
+
+        // rows, the set pins must be consecutive: row_1 is the lowest pin
+        let row_1 = common.make_pio_pin(row_1);
+        let row_2 = common.make_pio_pin(row_2);
+        let row_3 = common.make_pio_pin(row_3);
+        sm.set_pins(Level::High, &[&row_1, &row_2, &row_3]);
+        sm.set_pin_dirs(Direction::Out, &[&row_1, &row_2, &row_3]);
+
+        // columns, the in pins must be consecutive: column_6 is the lowest pin
+        let mut column_1 = common.make_pio_pin(column_1);
+        column_1.set_pull(embassy_rp::gpio::Pull::Up);
+        let mut column_2 = common.make_pio_pin(column_2);
+        column_2.set_pull(embassy_rp::gpio::Pull::Up);
+        let mut column_3 = common.make_pio_pin(column_3);
+        column_3.set_pull(embassy_rp::gpio::Pull::Up);
+        let mut column_4 = common.make_pio_pin(column_4);
+        column_4.set_pull(embassy_rp::gpio::Pull::Up);
+        let mut column_5 = common.make_pio_pin(column_5);
+        column_5.set_pull(embassy_rp::gpio::Pull::Up);
+        let mut column_6 = common.make_pio_pin(column_6);
+        column_6.set_pull(embassy_rp::gpio::Pull::Up);
+        let columns = [
+            &column_6, &column_5, &column_4, &column_3, &column_2, &column_1,
+        ];
+        sm.set_pin_dirs(Direction::In, &columns);
+
+        let mut config = Config::default();
+        config.use_program(&program, &[]);
+        config.set_set_pins(&[&row_1, &row_2, &row_3]);
+        config.set_in_pins(&columns);
+        config.shift_in.direction = ShiftDirection::Left;
+        config.fifo_join = FifoJoin::RxOnly;
+        config.clock_divider =
+            (embassy_rp::clocks::clk_sys_freq() / (scan_rate_hz * CYCLES_PER_SCAN)).to_fixed();
+        sm.set_config(&config);
+        sm.set_enable(true);
+
+        // left thumb cluster
+        let mut lt_1 = Input::new(lt_1, embassy_rp::gpio::Pull::Up);
+        lt_1.set_schmitt(true);
+        let mut lt_2 = Input::new(lt_2, embassy_rp::gpio::Pull::Up);
+        lt_2.set_schmitt(true);
+        let mut lt_3 = Input::new(lt_3, embassy_rp::gpio::Pull::Up);
+        lt_3.set_schmitt(true);
+
+        return LeftPioScanner {
+            sm,
+            dma: dma.into_ref().map_into(),
+            lt_1,
+            lt_2,
+            lt_3,
+            scan: [NO_SCAN],
+            matrix: 0,
+        };
+    }
+
+    /// Waits until the matrix or the thumb cluster changes and returns the new readout.
+    ///
+    /// This is cancel safe: a scan that lands while the returned future is being dropped is
+    /// picked up by the next call.
+    pub async fn wait_for_change(&mut self) -> LeftReadout {
+        if self.scan[0] == NO_SCAN {
+            let thumb_cluster = select_array([
+                self.lt_1.wait_for_any_edge(),
+                self.lt_2.wait_for_any_edge(),
+                self.lt_3.wait_for_any_edge(),
+            ]);
+            let transfer = self.sm.rx().dma_pull(self.dma.reborrow(), &mut self.scan);
+            select(transfer, thumb_cluster).await;
+        }
+        // the transfer may have finished right before the thumb cluster won the race
+        if self.scan[0] != NO_SCAN {
+            // columns read low when pressed
+            self.matrix = !self.scan[0];
+            self.scan[0] = NO_SCAN;
+        }
+        return self.readout();
+    }
+
+    /// Returns the readout of the last scan, without waiting for a change.
+    pub fn readout(&self) -> LeftReadout {
+        let mut left_thumb_cluster_readout: u8 = 0;
+        left_thumb_cluster_readout =
+            self.lt_1.is_low() as u8 * 0b00000001 + left_thumb_cluster_readout;
+        left_thumb_cluster_readout =
+            self.lt_2.is_low() as u8 * 0b00000010 + left_thumb_cluster_readout;
+        left_thumb_cluster_readout =
+            self.lt_3.is_low() as u8 * 0b00000100 + left_thumb_cluster_readout;
+
+        // rows are shifted in first to last, columns with column_1 as the highest bit
+        return LeftReadout::from_bytes([
+            (self.matrix >> 12) as u8 & 0b00111111,
+            (self.matrix >> 6) as u8 & 0b00111111,
+            self.matrix as u8 & 0b00111111,
+            left_thumb_cluster_readout,
+        ]);
+    }
+}
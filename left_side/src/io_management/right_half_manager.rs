@@ -1,4 +1,4 @@
-#[derive(PartialEq)]
+#[derive(PartialEq, Clone, Copy)]
 pub struct RightReadout {
     row_1: u8,
     row_2: u8,
@@ -53,6 +53,7 @@ impl RightReadout {
     }
 }
 
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub enum RightKeyLocation {
     C7R1,
     C8R1,
@@ -0,0 +1,98 @@
+#[derive(Clone, Copy, PartialEq)]
+pub enum EncoderDirection {
+    Clockwise,
+    CounterClockwise,
+}
+
+/// Identifies which half of the keyboard an encoder sits on.
+#[derive(Clone, Copy, PartialEq)]
+pub enum EncoderLocation {
+    Left,
+    Right,
+}
+
+/// Groups the counts of a quadrature decoder into steps, one step every `counts_per_step` counts
+/// in the same direction. This sets the resolution of the encoder: every step triggers one
+/// action. Turning back resets the partial count.
+pub struct EncoderStepper {
+    /// Counts towards the next step, positive counts being clockwise.
+    count: i16,
+}
+
+impl Default for EncoderStepper {
+    fn default() -> Self {
+        return EncoderStepper::new();
+    }
+}
+
+impl EncoderStepper {
+    pub const fn new() -> EncoderStepper {
+        return EncoderStepper { count: 0 };
+    }
+
+    /// Feeds `counts` counts in the direction and returns the number of steps they completed.
+    pub fn count(&mut self, direction: EncoderDirection, counts: u8, counts_per_step: u8) -> u8 {
+        let counts_per_step = counts_per_step.max(1) as i16;
+        let mut steps = 0;
+        for _ in 0..counts {
+            match direction {
+                EncoderDirection::Clockwise => {
+                    self.count = self.count.max(0) + 1;
+                }
+                EncoderDirection::CounterClockwise => {
+                    self.count = self.count.min(0) - 1;
+                }
+            }
+            if self.count.abs() >= counts_per_step {
+                self.count = 0;
+                steps += 1;
+            }
+        }
+        return steps;
+    }
+}
+
+/// Splits signed counts, positive counts being clockwise, into their direction and number. This
+/// is also how the counts of the right half encoder travel over the split link.
+pub fn split_counts(counts: i8) -> (EncoderDirection, u8) {
+    if counts < 0 {
+        return (EncoderDirection::CounterClockwise, counts.unsigned_abs());
+    }
+    return (EncoderDirection::Clockwise, counts as u8);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn groups_counts_into_steps_at_the_resolution() {
+        let mut stepper = EncoderStepper::new();
+        assert_eq!(stepper.count(EncoderDirection::Clockwise, 3, 1), 3);
+        assert_eq!(stepper.count(EncoderDirection::Clockwise, 3, 4), 0);
+        // the partial count carries over to the next counts
+        assert_eq!(stepper.count(EncoderDirection::Clockwise, 6, 4), 2);
+        assert_eq!(stepper.count(EncoderDirection::Clockwise, 1, 4), 0);
+        assert_eq!(stepper.count(EncoderDirection::Clockwise, u8::MAX, 2), 128);
+        // no resolution is taken as one count per step
+        assert_eq!(stepper.count(EncoderDirection::CounterClockwise, 2, 0), 2);
+    }
+
+    #[test]
+    fn turning_back_resets_the_partial_count() {
+        let mut stepper = EncoderStepper::new();
+        assert_eq!(stepper.count(EncoderDirection::Clockwise, 3, 4), 0);
+        assert_eq!(stepper.count(EncoderDirection::CounterClockwise, 3, 4), 0);
+        assert_eq!(stepper.count(EncoderDirection::CounterClockwise, 1, 4), 1);
+        assert_eq!(stepper.count(EncoderDirection::Clockwise, 4, 4), 1);
+    }
+
+    #[test]
+    fn splits_counts_over_the_whole_range() {
+        assert!(split_counts(0) == (EncoderDirection::Clockwise, 0));
+        assert!(split_counts(1) == (EncoderDirection::Clockwise, 1));
+        assert!(split_counts(-1) == (EncoderDirection::CounterClockwise, 1));
+        assert!(split_counts(i8::MAX) == (EncoderDirection::Clockwise, 127));
+        assert!(split_counts(i8::MIN) == (EncoderDirection::CounterClockwise, 128));
+    }
+}
@@ -0,0 +1,134 @@
+use crate::{
+    profiles_management::keyboard_profile::keyboard_profile::KeyboardProfile,
+    report_buffer::buffer::{QueuedReport, ReportConsumer},
+};
+
+use super::full_keyboard_manager::{FullKeyboardManager, MacroPlayback};
+
+/// Plays the part of the left half firmware around a [FullKeyboardManager] on a virtual clock:
+/// the macro runner, the timer of the timed modes and a HID writer taking every report as soon
+/// as it is queued, for the simulator and the keymap tests to drive the key processing the way
+/// the firmware does.
+///
+/// The reports go to the `write` callback of the calls they are sent during. Time only moves
+/// forward through [VirtualKeyboard::run_until].
+pub struct VirtualKeyboard<'a> {
+    manager: FullKeyboardManager<'a>,
+    consumer: ReportConsumer<'a>,
+    now_us: u64,
+    /// Time the macro runner wakes up at, [None] while it waits for a macro to be triggered.
+    macro_wake_us: Option<u64>,
+}
+
+impl<'a> VirtualKeyboard<'a> {
+    pub fn new(manager: FullKeyboardManager<'a>, consumer: ReportConsumer<'a>) -> Self {
+        return VirtualKeyboard {
+            manager,
+            consumer,
+            now_us: 0,
+            macro_wake_us: None,
+        };
+    }
+
+    pub fn manager(&self) -> &FullKeyboardManager<'a> {
+        return &self.manager;
+    }
+
+    pub fn manager_mut(&mut self) -> &mut FullKeyboardManager<'a> {
+        return &mut self.manager;
+    }
+
+    /// Returns the consumer end of the report buffer, e.g. for its statistics.
+    pub fn consumer(&self) -> &ReportConsumer<'a> {
+        return &self.consumer;
+    }
+
+    /// Returns the time on the virtual clock.
+    pub fn now_us(&self) -> u64 {
+        return self.now_us;
+    }
+
+    /// Hands a key change over to the manager at the current time through `change`, then plays
+    /// the macros it triggered as far as they go without waiting. The keys go through the
+    /// profile.
+    pub fn change_keys(
+        &mut self,
+        profile: &KeyboardProfile,
+        change: impl FnOnce(&mut FullKeyboardManager<'a>, u64),
+        write: &mut impl FnMut(QueuedReport),
+    ) {
+        change(&mut self.manager, self.now_us);
+        self.wake_macro_runner(self.now_us);
+        self.write_reports(write);
+        self.run_until(self.now_us, profile, write);
+    }
+
+    /// Lets the time pass up to `time_us`, waking up the macro runner and the timed modes when
+    /// they ask for it.
+    pub fn run_until(
+        &mut self,
+        time_us: u64,
+        profile: &KeyboardProfile,
+        write: &mut impl FnMut(QueuedReport),
+    ) {
+        loop {
+            let deadline_us = self
+                .manager
+                .next_deadline_us()
+                .filter(|deadline_us| *deadline_us <= time_us);
+            let wake_us = self.macro_wake_us.filter(|wake_us| *wake_us <= time_us);
+            match (deadline_us, wake_us) {
+                (None, None) => break,
+                (Some(deadline_us), Some(wake_us)) if wake_us <= deadline_us => {
+                    self.play_macro(wake_us, write)
+                }
+                (Some(deadline_us), _) => {
+                    self.now_us = self.now_us.max(deadline_us);
+                    self.manager.tick(profile, deadline_us);
+                    self.wake_macro_runner(deadline_us);
+                    self.write_reports(write);
+                }
+                (None, Some(wake_us)) => self.play_macro(wake_us, write),
+            }
+        }
+        self.now_us = self.now_us.max(time_us);
+    }
+
+    /// Wakes the macro runner up at `now_us` if a macro has been triggered while it slept.
+    fn wake_macro_runner(&mut self, now_us: u64) {
+        if self.manager.is_macro_pending() && self.macro_wake_us.is_none() {
+            self.macro_wake_us = Some(now_us);
+        }
+    }
+
+    fn play_macro(&mut self, wake_us: u64, write: &mut impl FnMut(QueuedReport)) {
+        self.now_us = self.now_us.max(wake_us);
+        let playback = self.manager.play_macro(wake_us);
+        self.write_reports(write);
+        match playback {
+            MacroPlayback::Finished => self.macro_wake_us = None,
+            MacroPlayback::Delay(delay_ms) => {
+                self.macro_wake_us = Some(wake_us.saturating_add(delay_ms as u64 * 1000))
+            }
+            // the buffer has just been drained, playback goes on right away
+            MacroPlayback::BufferFull => {}
+        }
+    }
+
+    /// Drains the buffer the way the HID writer does.
+    fn write_reports(&mut self, write: &mut impl FnMut(QueuedReport)) {
+        loop {
+            let queued = match self.consumer.take_report() {
+                Some(queued) => queued,
+                None => {
+                    self.manager.flush_reports();
+                    match self.consumer.take_report() {
+                        Some(queued) => queued,
+                        None => return,
+                    }
+                }
+            };
+            write(queued);
+        }
+    }
+}
@@ -0,0 +1,288 @@
+use usbd_hid::descriptor::KeyboardUsage;
+
+use crate::hid_helper::{keyboard_report::KeyboardReportHelper, modifiers::Modifiers};
+
+/// The auto-shift settings of a profile.
+#[derive(Clone, Copy)]
+pub struct AutoShiftConfig {
+    /// Time a key has to be held for to be sent shifted.
+    pub timeout_ms: u16,
+    /// Auto-shift applies to `a` to `z`.
+    pub letters: bool,
+    /// Auto-shift applies to `1` to `0`.
+    pub numbers: bool,
+    /// Auto-shift applies to the symbols of the main block, `-` to `/`.
+    pub symbols: bool,
+}
+
+impl AutoShiftConfig {
+    pub const DISABLED: AutoShiftConfig = AutoShiftConfig {
+        timeout_ms: 0,
+        letters: false,
+        numbers: false,
+        symbols: false,
+    };
+
+    fn applies_to(&self, key: KeyboardUsage) -> bool {
+        let usage = key as u8;
+        if (KeyboardUsage::KeyboardAa as u8..=KeyboardUsage::KeyboardZz as u8).contains(&usage) {
+            return self.letters;
+        }
+        if (KeyboardUsage::Keyboard1Exclamation as u8..=KeyboardUsage::Keyboard0CloseParens as u8)
+            .contains(&usage)
+        {
+            return self.numbers;
+        }
+        if (KeyboardUsage::KeyboardDashUnderscore as u8
+            ..=KeyboardUsage::KeyboardSlashQuestion as u8)
+            .contains(&usage)
+        {
+            return self.symbols;
+        }
+        return false;
+    }
+}
+
+/// Auto-shift: a key held past the timeout of the [AutoShiftConfig] is sent shifted, a tap sends
+/// it as it is. The key is held back from the reports until one or the other is known. Pressing
+/// another key in the meantime sends it as it is too, so fast typing is never delayed for long.
+/// Modifiers and layer keys are never held back, and neither are the keys of a shortcut, pressed
+/// while Ctrl, Alt or GUI is held.
+pub struct AutoShift {
+    config: AutoShiftConfig,
+    /// Key held back, along with the time it has been pressed at.
+    pending: Option<(KeyboardUsage, u64)>,
+    /// Key held past the timeout.
+    shifted: Option<KeyboardUsage>,
+    /// Shift is added to the reports, as the last key pressed is the shifted one.
+    shift_on: bool,
+}
+
+impl Default for AutoShift {
+    fn default() -> Self {
+        return AutoShift::new();
+    }
+}
+
+impl AutoShift {
+    pub const fn new() -> AutoShift {
+        return AutoShift {
+            config: AutoShiftConfig::DISABLED,
+            pending: None,
+            shifted: None,
+            shift_on: false,
+        };
+    }
+
+    /// Starts a report with the settings of the profile it goes through.
+    pub fn begin_report(&mut self, config: AutoShiftConfig) {
+        self.config = config;
+    }
+
+    /// Updates the held back key with the keys of the report built at `now_us`, `previous_keys`
+    /// being the ones of the previous report. Keys newly pressed while `hold_back` is false, or
+    /// along with a modifier other than shift, are sent as they are. Returns the key released
+    /// before the timeout, which has to be tapped.
+    pub fn finish_report(
+        &mut self,
+        keys: &[KeyboardUsage],
+        previous_keys: &[KeyboardUsage],
+        now_us: u64,
+        hold_back: bool,
+    ) -> Option<KeyboardUsage> {
+        let tapped = match self.pending {
+            Some((key, _)) if !keys.contains(&key) => {
+                self.pending = None;
+                Some(key)
+            }
+            _ => None,
+        };
+        let shortcut = keys.iter().any(|key| {
+            Modifiers::from_key(*key).is_some_and(|modifier| !Modifiers::SHIFT.contains(modifier))
+        });
+        for key in keys.iter().filter(|key| !previous_keys.contains(key)) {
+            // the key held back so far is sent as it is
+            self.pending = None;
+            self.shift_on = false;
+            if hold_back && !shortcut && self.config.applies_to(*key) {
+                self.pending = Some((*key, now_us));
+            }
+        }
+        match self.shifted {
+            Some(key) if !keys.contains(&key) => {
+                self.shifted = None;
+                self.shift_on = false;
+            }
+            _ => {}
+        }
+        return tapped;
+    }
+
+    /// Holds the pending key back from the report, and adds shift if the key pressed last has
+    /// been held past the timeout.
+    pub fn apply(&self, report: &mut KeyboardReportHelper) {
+        if let Some((key, _)) = self.pending {
+            report.remove_keycode(key);
+        }
+        if self.shift_on {
+            report.add_keycode(KeyboardUsage::KeyboardLeftShift);
+        }
+    }
+
+    /// Shifts the pending key once it has been held past the timeout. Returns true if it did.
+    pub fn tick(&mut self, now_us: u64) -> bool {
+        match (self.pending, self.deadline_us()) {
+            (Some((key, _)), Some(deadline_us)) if now_us >= deadline_us => {
+                self.pending = None;
+                self.shifted = Some(key);
+                self.shift_on = true;
+                return true;
+            }
+            _ => return false,
+        }
+    }
+
+    /// Returns the time the pending key gets shifted at if it is still held then.
+    pub fn deadline_us(&self) -> Option<u64> {
+        match self.pending {
+            Some((_, pressed_us)) => {
+                return Some(pressed_us + self.config.timeout_ms as u64 * 1000)
+            }
+            None => return None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use usbd_hid::descriptor::KeyboardUsage::*;
+
+    use crate::profiles_management::keymap_tester::report;
+
+    use super::*;
+
+    const CONFIG: AutoShiftConfig = AutoShiftConfig {
+        timeout_ms: 175,
+        letters: true,
+        numbers: true,
+        symbols: false,
+    };
+
+    /// Builds the report of the keys held at `now_ms` through auto-shift, `previous_keys` being
+    /// the ones of the previous report. Returns it along with the key to tap.
+    fn send(
+        auto_shift: &mut AutoShift,
+        keys: &[KeyboardUsage],
+        previous_keys: &[KeyboardUsage],
+        now_ms: u64,
+    ) -> (KeyboardReportHelper, Option<KeyboardUsage>) {
+        auto_shift.begin_report(CONFIG);
+        let tapped = auto_shift.finish_report(keys, previous_keys, now_ms * 1000, true);
+        let mut report = report(keys);
+        auto_shift.apply(&mut report);
+        return (report, tapped);
+    }
+
+    #[test]
+    fn sends_taps_as_they_are_on_release() {
+        let mut auto_shift = AutoShift::new();
+        assert_eq!(
+            send(&mut auto_shift, &[KeyboardQq], &[], 0),
+            (report(&[]), None)
+        );
+        assert_eq!(auto_shift.deadline_us(), Some(175_000));
+        let released = send(&mut auto_shift, &[], &[KeyboardQq], 50);
+        assert_eq!(released, (report(&[]), Some(KeyboardQq)));
+        assert_eq!(auto_shift.deadline_us(), None);
+    }
+
+    #[test]
+    fn shifts_keys_held_past_the_timeout() {
+        let mut auto_shift = AutoShift::new();
+        send(&mut auto_shift, &[KeyboardQq], &[], 0);
+        assert!(!auto_shift.tick(174_999));
+        assert!(auto_shift.tick(175_000));
+        let mut held = report(&[KeyboardQq]);
+        auto_shift.apply(&mut held);
+        assert_eq!(held, report(&[KeyboardLeftShift, KeyboardQq]));
+        assert_eq!(auto_shift.deadline_us(), None);
+        assert_eq!(
+            send(&mut auto_shift, &[], &[KeyboardQq], 500),
+            (report(&[]), None)
+        );
+    }
+
+    #[test]
+    fn sends_the_held_back_key_when_another_one_is_pressed() {
+        let mut auto_shift = AutoShift::new();
+        send(&mut auto_shift, &[KeyboardQq], &[], 0);
+        let both = send(
+            &mut auto_shift,
+            &[KeyboardQq, KeyboardWw],
+            &[KeyboardQq],
+            50,
+        );
+        assert_eq!(both, (report(&[KeyboardQq]), None));
+        assert_eq!(auto_shift.deadline_us(), Some(225_000));
+        let released = send(
+            &mut auto_shift,
+            &[KeyboardWw],
+            &[KeyboardQq, KeyboardWw],
+            100,
+        );
+        assert_eq!(released, (report(&[]), None));
+        let released = send(&mut auto_shift, &[], &[KeyboardWw], 120);
+        assert_eq!(released, (report(&[]), Some(KeyboardWw)));
+    }
+
+    #[test]
+    fn leaves_modifiers_and_disabled_classes_alone() {
+        let mut auto_shift = AutoShift::new();
+        let shift = send(&mut auto_shift, &[KeyboardLeftShift], &[], 0);
+        assert_eq!(shift, (report(&[KeyboardLeftShift]), None));
+        let comma = send(
+            &mut auto_shift,
+            &[KeyboardLeftShift, KeyboardCommaLess],
+            &[KeyboardLeftShift],
+            10,
+        );
+        assert_eq!(
+            comma,
+            (report(&[KeyboardLeftShift, KeyboardCommaLess]), None)
+        );
+        assert_eq!(auto_shift.deadline_us(), None);
+    }
+
+    #[test]
+    fn sends_shortcuts_right_away() {
+        let mut auto_shift = AutoShift::new();
+        send(&mut auto_shift, &[KeyboardLeftControl], &[], 0);
+        let shortcut = send(
+            &mut auto_shift,
+            &[KeyboardLeftControl, KeyboardCc],
+            &[KeyboardLeftControl],
+            10,
+        );
+        assert_eq!(shortcut, (report(&[KeyboardLeftControl, KeyboardCc]), None));
+        assert_eq!(auto_shift.deadline_us(), None);
+        assert!(!auto_shift.tick(500_000));
+        let held = send(
+            &mut auto_shift,
+            &[KeyboardLeftControl, KeyboardCc],
+            &[KeyboardLeftControl, KeyboardCc],
+            500,
+        );
+        assert_eq!(held, (report(&[KeyboardLeftControl, KeyboardCc]), None));
+    }
+
+    #[test]
+    fn does_not_hold_back_keys_when_told_not_to() {
+        let mut auto_shift = AutoShift::new();
+        auto_shift.begin_report(CONFIG);
+        assert_eq!(auto_shift.finish_report(&[KeyboardQq], &[], 0, false), None);
+        let mut held = report(&[KeyboardQq]);
+        auto_shift.apply(&mut held);
+        assert_eq!(held, report(&[KeyboardQq]));
+        assert_eq!(auto_shift.deadline_us(), None);
+    }
+}
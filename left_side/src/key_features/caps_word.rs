@@ -0,0 +1,300 @@
+use usbd_hid::descriptor::KeyboardUsage;
+
+use crate::hid_helper::{keyboard_report::KeyboardReportHelper, modifiers::Modifiers};
+
+/// Time without a key keeping Caps Word alive after which it ends by itself.
+pub const CAPS_WORD_IDLE_TIMEOUT_MS: u64 = 5000;
+
+/// Caps Word, the mode typing a single word in capitals: while it is active, letters are sent
+/// shifted. Digits, `-`/`_` and backspace keep it alive, any other key ends it, and so does
+/// [CAPS_WORD_IDLE_TIMEOUT_MS] without a key keeping it alive. Modifiers leave it as it is, for
+/// Shift to type `_`.
+pub struct CapsWord {
+    active: bool,
+    /// Shift is added to the reports, as the last key pressed is a letter.
+    shifted: bool,
+    /// The toggle key was held in the previous report.
+    toggle_held: bool,
+    /// The toggle key is held in the report being built.
+    toggle_pressed: bool,
+    last_activity_us: u64,
+}
+
+impl Default for CapsWord {
+    fn default() -> Self {
+        return CapsWord::new();
+    }
+}
+
+impl CapsWord {
+    pub const fn new() -> CapsWord {
+        return CapsWord {
+            active: false,
+            shifted: false,
+            toggle_held: false,
+            toggle_pressed: false,
+            last_activity_us: 0,
+        };
+    }
+
+    /// Returns true while Caps Word is on, e.g. for an indicator.
+    pub fn is_active(&self) -> bool {
+        return self.active;
+    }
+
+    /// Starts a report, the toggle key has to be seen again to count as held.
+    pub fn begin_report(&mut self) {
+        self.toggle_pressed = false;
+    }
+
+    /// Called by the key toggling Caps Word for every report it is held in.
+    pub fn toggle(&mut self) {
+        self.toggle_pressed = true;
+    }
+
+    /// Updates the mode with the keys of the report built at `now_us`, `previous_keys` being the
+    /// ones of the previous report.
+    pub fn finish_report(
+        &mut self,
+        keys: &[KeyboardUsage],
+        previous_keys: &[KeyboardUsage],
+        now_us: u64,
+    ) {
+        if self.toggle_pressed && !self.toggle_held {
+            self.active = !self.active;
+            self.shifted = false;
+            self.last_activity_us = now_us;
+        }
+        self.toggle_held = self.toggle_pressed;
+        if self.active {
+            for key in keys.iter().filter(|key| !previous_keys.contains(key)) {
+                match key_class(*key) {
+                    KeyClass::Modifier => continue,
+                    KeyClass::Letter => self.shifted = true,
+                    KeyClass::KeepsAlive => self.shifted = false,
+                    KeyClass::Ends => {
+                        self.active = false;
+                        break;
+                    }
+                }
+                self.last_activity_us = now_us;
+            }
+        }
+        let letter_held = keys.iter().any(|key| key_class(*key) == KeyClass::Letter);
+        if !self.active || !letter_held {
+            self.shifted = false;
+        }
+    }
+
+    /// Adds shift to the report if the letter pressed last has to be sent in capital.
+    pub fn apply(&self, report: &mut KeyboardReportHelper) {
+        if self.shifted {
+            report.add_keycode(KeyboardUsage::KeyboardLeftShift);
+        }
+    }
+
+    /// Ends Caps Word once it has been idle for too long. Returns true if it did.
+    pub fn tick(&mut self, now_us: u64) -> bool {
+        match self.deadline_us() {
+            Some(deadline_us) if now_us >= deadline_us => {
+                self.active = false;
+                self.shifted = false;
+                return true;
+            }
+            _ => return false,
+        }
+    }
+
+    /// Returns the time Caps Word ends at if no key keeps it alive until then.
+    pub fn deadline_us(&self) -> Option<u64> {
+        if !self.active {
+            return None;
+        }
+        return Some(self.last_activity_us + CAPS_WORD_IDLE_TIMEOUT_MS * 1000);
+    }
+}
+
+#[derive(PartialEq)]
+enum KeyClass {
+    Modifier,
+    Letter,
+    KeepsAlive,
+    Ends,
+}
+
+fn key_class(key: KeyboardUsage) -> KeyClass {
+    if Modifiers::from_key(key).is_some() {
+        return KeyClass::Modifier;
+    }
+    let usage = key as u8;
+    if (KeyboardUsage::KeyboardAa as u8..=KeyboardUsage::KeyboardZz as u8).contains(&usage) {
+        return KeyClass::Letter;
+    }
+    if (KeyboardUsage::Keyboard1Exclamation as u8..=KeyboardUsage::Keyboard0CloseParens as u8)
+        .contains(&usage)
+    {
+        return KeyClass::KeepsAlive;
+    }
+    match key {
+        KeyboardUsage::KeyboardDashUnderscore | KeyboardUsage::KeyboardBackspace => {
+            return KeyClass::KeepsAlive
+        }
+        _ => return KeyClass::Ends,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use usbd_hid::descriptor::KeyboardUsage::*;
+
+    use crate::profiles_management::keymap_tester::report;
+
+    use super::*;
+
+    /// Runs the reports holding the keys through Caps Word, one every millisecond from
+    /// `start_ms` on, and returns what they are turned into.
+    fn apply_reports(
+        caps_word: &mut CapsWord,
+        start_ms: u64,
+        reports: &[&[KeyboardUsage]],
+    ) -> Vec<KeyboardReportHelper> {
+        let mut previous_keys: &[KeyboardUsage] = &[];
+        let mut applied = Vec::new();
+        for (index, keys) in reports.iter().enumerate() {
+            caps_word.begin_report();
+            caps_word.finish_report(keys, previous_keys, (start_ms + index as u64) * 1000);
+            let mut report = report(keys);
+            caps_word.apply(&mut report);
+            applied.push(report);
+            previous_keys = keys;
+        }
+        return applied;
+    }
+
+    /// Taps the toggle key at `now_ms`.
+    fn toggle(caps_word: &mut CapsWord, now_ms: u64) {
+        caps_word.begin_report();
+        caps_word.toggle();
+        caps_word.finish_report(&[], &[], now_ms * 1000);
+        caps_word.begin_report();
+        caps_word.finish_report(&[], &[], now_ms * 1000);
+    }
+
+    #[test]
+    fn shifts_letters_until_a_word_ends() {
+        let mut caps_word = CapsWord::new();
+        toggle(&mut caps_word, 0);
+        assert!(caps_word.is_active());
+        let reports: &[&[KeyboardUsage]] = &[
+            &[KeyboardQq],
+            &[],
+            &[KeyboardBackspace],
+            &[KeyboardDashUnderscore],
+            &[Keyboard1Exclamation],
+            &[KeyboardWw],
+            &[],
+            &[KeyboardSpacebar],
+            &[KeyboardEe],
+            &[],
+        ];
+        let applied = apply_reports(&mut caps_word, 1, reports);
+        assert_eq!(
+            applied,
+            [
+                report(&[KeyboardLeftShift, KeyboardQq]),
+                report(&[]),
+                report(&[KeyboardBackspace]),
+                report(&[KeyboardDashUnderscore]),
+                report(&[Keyboard1Exclamation]),
+                report(&[KeyboardLeftShift, KeyboardWw]),
+                report(&[]),
+                // space ends the word
+                report(&[KeyboardSpacebar]),
+                report(&[KeyboardEe]),
+                report(&[]),
+            ]
+        );
+        assert!(!caps_word.is_active());
+    }
+
+    #[test]
+    fn only_shifts_the_key_pressed_last() {
+        let mut caps_word = CapsWord::new();
+        toggle(&mut caps_word, 0);
+        let reports: &[&[KeyboardUsage]] = &[
+            &[KeyboardQq],
+            &[KeyboardQq, KeyboardUu],
+            &[KeyboardUu],
+            &[KeyboardUu, Keyboard2At],
+            &[],
+        ];
+        let applied = apply_reports(&mut caps_word, 1, reports);
+        assert_eq!(
+            applied,
+            [
+                report(&[KeyboardLeftShift, KeyboardQq]),
+                report(&[KeyboardLeftShift, KeyboardQq, KeyboardUu]),
+                report(&[KeyboardLeftShift, KeyboardUu]),
+                // the digit keeps the word going but is not shifted, nor is the letter under it
+                report(&[KeyboardUu, Keyboard2At]),
+                report(&[]),
+            ]
+        );
+        assert!(caps_word.is_active());
+    }
+
+    #[test]
+    fn keeps_going_through_a_shifted_dash() {
+        let mut caps_word = CapsWord::new();
+        toggle(&mut caps_word, 0);
+        let reports: &[&[KeyboardUsage]] = &[
+            &[KeyboardQq],
+            &[],
+            &[KeyboardLeftShift],
+            &[KeyboardLeftShift, KeyboardDashUnderscore],
+            &[KeyboardLeftShift],
+            &[],
+            &[KeyboardWw],
+            &[],
+        ];
+        let applied = apply_reports(&mut caps_word, 1, reports);
+        assert_eq!(
+            applied,
+            [
+                report(&[KeyboardLeftShift, KeyboardQq]),
+                report(&[]),
+                report(&[KeyboardLeftShift]),
+                report(&[KeyboardLeftShift, KeyboardDashUnderscore]),
+                report(&[KeyboardLeftShift]),
+                report(&[]),
+                report(&[KeyboardLeftShift, KeyboardWw]),
+                report(&[]),
+            ]
+        );
+        assert!(caps_word.is_active());
+    }
+
+    #[test]
+    fn toggles_off_and_times_out() {
+        let mut caps_word = CapsWord::new();
+        toggle(&mut caps_word, 0);
+        toggle(&mut caps_word, 10);
+        assert!(!caps_word.is_active());
+        assert_eq!(caps_word.deadline_us(), None);
+
+        toggle(&mut caps_word, 100);
+        assert_eq!(
+            caps_word.deadline_us(),
+            Some((100 + CAPS_WORD_IDLE_TIMEOUT_MS) * 1000)
+        );
+        // a key keeping the word going moves the deadline
+        apply_reports(&mut caps_word, 200, &[&[KeyboardQq], &[]]);
+        let deadline_us = (200 + CAPS_WORD_IDLE_TIMEOUT_MS) * 1000;
+        assert_eq!(caps_word.deadline_us(), Some(deadline_us));
+        assert!(!caps_word.tick(deadline_us - 1));
+        assert!(caps_word.is_active());
+        assert!(caps_word.tick(deadline_us));
+        assert!(!caps_word.is_active());
+        assert!(!caps_word.tick(deadline_us + 1));
+    }
+}
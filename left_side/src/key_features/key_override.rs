@@ -0,0 +1,172 @@
+use usbd_hid::descriptor::KeyboardUsage;
+
+use crate::hid_helper::{keyboard_report::KeyboardReportHelper, modifiers::Modifiers};
+
+/// Number of key overrides of a profile that are taken into account, the others are ignored.
+pub const MAX_KEY_OVERRIDES: usize = 32;
+
+/// Replaces a key pressed along with some modifiers, e.g. Shift+Backspace by Delete.
+#[derive(Clone, Copy)]
+pub struct KeyOverride {
+    /// Modifiers that have to be held, on either side of the keyboard.
+    pub modifiers: Modifiers,
+    pub trigger: KeyboardUsage,
+    pub replacement: KeyboardUsage,
+    /// Releases the modifiers while the replacement is held, e.g. for Shift+`,` to send an
+    /// unshifted `;`.
+    pub suppress_modifiers: bool,
+}
+
+/// Applies the key overrides of a profile to the reports about to be sent. An override kicks in
+/// when its trigger key is held along with its modifiers, and then lasts until the trigger key
+/// is released, so letting go of the modifiers first does not send the trigger key itself.
+pub struct KeyOverrides {
+    /// One bit per override of the profile, set while it is in effect.
+    active: u32,
+}
+
+impl Default for KeyOverrides {
+    fn default() -> Self {
+        return KeyOverrides::new();
+    }
+}
+
+impl KeyOverrides {
+    pub const fn new() -> KeyOverrides {
+        return KeyOverrides { active: 0 };
+    }
+
+    pub fn apply(&mut self, overrides: &[KeyOverride], report: &mut KeyboardReportHelper) {
+        let modifiers = report.modifiers();
+        let mut suppressed = Modifiers::NONE;
+        for (index, key_override) in overrides.iter().take(MAX_KEY_OVERRIDES).enumerate() {
+            let bit = 1 << index;
+            if !report.contains_keycode(key_override.trigger) {
+                self.active &= !bit;
+                continue;
+            }
+            if modifiers.matches(key_override.modifiers) {
+                self.active |= bit;
+            }
+            if self.active & bit == 0 {
+                continue;
+            }
+            report.replace_keycode(key_override.trigger, key_override.replacement);
+            if key_override.suppress_modifiers {
+                suppressed = suppressed.union(key_override.modifiers.either_side());
+            }
+        }
+        report.remove_modifiers(suppressed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use usbd_hid::descriptor::KeyboardUsage::*;
+
+    use crate::profiles_management::keymap_tester::report;
+
+    use super::*;
+
+    const OVERRIDES: &[KeyOverride] = &[
+        KeyOverride {
+            modifiers: Modifiers::SHIFT,
+            trigger: KeyboardBackspace,
+            replacement: KeyboardDelete,
+            suppress_modifiers: true,
+        },
+        KeyOverride {
+            modifiers: Modifiers::SHIFT,
+            trigger: KeyboardCommaLess,
+            replacement: KeyboardSemiColon,
+            suppress_modifiers: true,
+        },
+        KeyOverride {
+            modifiers: Modifiers::CTRL,
+            trigger: KeyboardHh,
+            replacement: KeyboardLeftArrow,
+            suppress_modifiers: false,
+        },
+    ];
+
+    /// Runs the reports holding the keys through the overrides and returns what they are turned
+    /// into.
+    fn apply_reports(
+        key_overrides: &mut KeyOverrides,
+        reports: &[&[KeyboardUsage]],
+    ) -> Vec<KeyboardReportHelper> {
+        let mut applied = Vec::new();
+        for keys in reports {
+            let mut report = report(keys);
+            key_overrides.apply(OVERRIDES, &mut report);
+            applied.push(report);
+        }
+        return applied;
+    }
+
+    #[test]
+    fn replaces_triggers_held_with_their_modifiers() {
+        let mut key_overrides = KeyOverrides::new();
+        let reports: &[&[KeyboardUsage]] = &[
+            &[KeyboardBackspace],
+            &[KeyboardRightShift, KeyboardBackspace],
+            &[KeyboardLeftControl, KeyboardHh],
+            &[],
+        ];
+        assert_eq!(
+            apply_reports(&mut key_overrides, reports),
+            [
+                report(&[KeyboardBackspace]),
+                // on either side, and released while the replacement is held
+                report(&[KeyboardDelete]),
+                report(&[KeyboardLeftControl, KeyboardLeftArrow]),
+                report(&[]),
+            ]
+        );
+    }
+
+    #[test]
+    fn last_until_the_trigger_is_released() {
+        let mut key_overrides = KeyOverrides::new();
+        let reports: &[&[KeyboardUsage]] = &[
+            &[KeyboardLeftShift],
+            &[KeyboardLeftShift, KeyboardBackspace],
+            &[KeyboardBackspace],
+            &[],
+            &[KeyboardBackspace],
+        ];
+        assert_eq!(
+            apply_reports(&mut key_overrides, reports),
+            [
+                report(&[KeyboardLeftShift]),
+                report(&[KeyboardDelete]),
+                report(&[KeyboardDelete]),
+                report(&[]),
+                report(&[KeyboardBackspace]),
+            ]
+        );
+    }
+
+    #[test]
+    fn keep_the_other_keys_and_modifiers() {
+        let mut key_overrides = KeyOverrides::new();
+        let reports: &[&[KeyboardUsage]] = &[
+            &[KeyboardLeftControl, KeyboardLeftShift, KeyboardQq],
+            &[
+                KeyboardLeftControl,
+                KeyboardLeftShift,
+                KeyboardQq,
+                KeyboardCommaLess,
+            ],
+            &[KeyboardLeftControl, KeyboardLeftShift, KeyboardQq],
+        ];
+        assert_eq!(
+            apply_reports(&mut key_overrides, reports),
+            [
+                report(&[KeyboardLeftControl, KeyboardLeftShift, KeyboardQq]),
+                report(&[KeyboardLeftControl, KeyboardQq, KeyboardSemiColon]),
+                report(&[KeyboardLeftControl, KeyboardLeftShift, KeyboardQq]),
+            ]
+        );
+    }
+}
@@ -0,0 +1,212 @@
+use heapless::Vec;
+use usbd_hid::descriptor::KeyboardUsage;
+
+use crate::{
+    hid_helper::{
+        host_layout::HostLayout, keyboard_report::KeyboardReportHelper, unicode_input::UnicodeInput,
+    },
+    macros::macro_player::MacroPlayer,
+    profiles_management::{
+        keyboard_profile::keyboard_profile::KeyboardProfile, keymap_store::KeymapStore,
+        layers::LAYER_COUNT,
+    },
+    settings::keyboard_settings::KeyboardSettings,
+};
+
+use super::{
+    auto_shift::AutoShift, caps_word::CapsWord, key_override::KeyOverrides, leader::Leader,
+    one_shot::OneShot, swap_hands::SwapHands,
+};
+
+/// Number of keys held at once that are kept track of, modifiers included.
+const MAX_TRACKED_KEYS: usize = 16;
+
+/// Everything key actions act on besides the report: the macros and the modes keys turn on.
+///
+/// A report goes through [KeyState::begin_report], [KeyState::press_position] and
+/// [KeyState::add_key] for the held keys, [KeyState::finish_report] and [KeyState::apply], in that
+/// order. The keys added are kept until the next report, which is how the modes tell the newly
+/// pressed keys apart.
+pub struct KeyState {
+    pub macros: MacroPlayer,
+    pub caps_word: CapsWord,
+    pub auto_shift: AutoShift,
+    pub key_overrides: KeyOverrides,
+    pub one_shot: OneShot,
+    pub leader: Leader,
+    pub swap_hands: SwapHands,
+    /// The keymap edited at runtime, overriding the one of the profile once loaded.
+    pub keymap_store: KeymapStore,
+    /// Keys of the last finished report.
+    previous_keys: Vec<KeyboardUsage, MAX_TRACKED_KEYS>,
+    /// Keys of the report being built.
+    keys: Vec<KeyboardUsage, MAX_TRACKED_KEYS>,
+    /// Positions of the keys held in the last finished report, a bit per position.
+    previous_positions: u64,
+    /// Positions of the keys held in the report being built.
+    positions: u64,
+    /// A report is being built, finishing a report twice leaves the modes as they were.
+    building: bool,
+    now_us: u64,
+    settings: KeyboardSettings,
+    /// The settings changed since they have last been handed out to be saved.
+    settings_changed: bool,
+}
+
+impl Default for KeyState {
+    fn default() -> Self {
+        return KeyState::new();
+    }
+}
+
+impl KeyState {
+    pub const fn new() -> KeyState {
+        return KeyState {
+            macros: MacroPlayer::new(),
+            caps_word: CapsWord::new(),
+            auto_shift: AutoShift::new(),
+            key_overrides: KeyOverrides::new(),
+            one_shot: OneShot::new(),
+            leader: Leader::new(),
+            swap_hands: SwapHands::new(),
+            keymap_store: KeymapStore::new(),
+            previous_keys: Vec::new(),
+            keys: Vec::new(),
+            previous_positions: 0,
+            positions: 0,
+            building: false,
+            now_us: 0,
+            settings: KeyboardSettings::new(),
+            settings_changed: false,
+        };
+    }
+
+    pub fn settings(&self) -> KeyboardSettings {
+        return self.settings;
+    }
+
+    /// Replaces the settings, e.g. with the ones saved before a restart.
+    pub fn load_settings(&mut self, settings: KeyboardSettings) {
+        self.settings = settings;
+        self.settings_changed = false;
+    }
+
+    /// Returns the settings if they changed since the last call, for them to be saved.
+    pub fn take_changed_settings(&mut self) -> Option<KeyboardSettings> {
+        if !self.settings_changed {
+            return None;
+        }
+        self.settings_changed = false;
+        return Some(self.settings);
+    }
+
+    pub fn set_unicode_input(&mut self, unicode_input: UnicodeInput) {
+        if self.settings.unicode_input != unicode_input {
+            self.settings.unicode_input = unicode_input;
+            self.settings_changed = true;
+        }
+    }
+
+    pub fn set_host_layout(&mut self, host_layout: HostLayout) {
+        if self.settings.host_layout != host_layout {
+            self.settings.host_layout = host_layout;
+            self.settings_changed = true;
+        }
+    }
+
+    /// Changes the default layer, layers the keys have no action on are ignored.
+    pub fn set_default_layer(&mut self, layer: u8) {
+        if layer < LAYER_COUNT && self.settings.default_layer != layer {
+            self.settings.default_layer = layer;
+            self.settings_changed = true;
+        }
+    }
+
+    /// Starts a report of the keys held at `now_us`, going through the profile.
+    pub fn begin_report(&mut self, now_us: u64, profile: &KeyboardProfile) {
+        self.now_us = now_us;
+        self.keys.clear();
+        self.positions = 0;
+        self.building = true;
+        self.caps_word.begin_report();
+        self.auto_shift.begin_report(profile.auto_shift);
+        self.one_shot.begin_report(now_us, profile.one_shot);
+        self.leader.begin_report(profile.leader);
+        self.swap_hands.begin_report();
+    }
+
+    /// Records the key at the position, up to 64 of them, as held in the report being built.
+    /// Returns true if it was not held in the previous report.
+    pub fn press_position(&mut self, position: usize) -> bool {
+        let bit = 1 << position;
+        self.positions |= bit;
+        return self.previous_positions & bit == 0;
+    }
+
+    /// Adds a key sent by a key action to the report being built.
+    pub fn add_key(&mut self, report: &mut KeyboardReportHelper, key: KeyboardUsage) {
+        let _ = self.keys.push(key);
+        report.add_keycode(key);
+    }
+
+    /// Updates the modes with the keys of the report, once all of them have been added. Returns
+    /// the key auto-shift held back and has to be tapped now, if any.
+    pub fn finish_report(&mut self) -> Option<KeyboardUsage> {
+        if !self.building {
+            return None;
+        }
+        self.building = false;
+        self.leader.finish_report(
+            &self.keys,
+            &self.previous_keys,
+            self.now_us,
+            &mut self.macros,
+        );
+        // the keys of a leader sequence are not typed, the modes do not see them either
+        let leader = &self.leader;
+        self.keys.retain(|key| !leader.holds_back(*key));
+        // Caps Word takes care of the letters while it is on
+        let hold_back = !self.caps_word.is_active();
+        let tapped =
+            self.auto_shift
+                .finish_report(&self.keys, &self.previous_keys, self.now_us, hold_back);
+        self.caps_word
+            .finish_report(&self.keys, &self.previous_keys, self.now_us);
+        self.one_shot.finish_report(&self.keys, &self.previous_keys);
+        self.swap_hands.finish_report();
+        core::mem::swap(&mut self.previous_keys, &mut self.keys);
+        self.previous_positions = self.positions;
+        return tapped;
+    }
+
+    /// Applies the modes, then the key overrides of the profile, to a report about to be sent.
+    pub fn apply(&mut self, report: &mut KeyboardReportHelper, profile: &KeyboardProfile) {
+        self.auto_shift.apply(report);
+        self.caps_word.apply(report);
+        self.one_shot.apply(report);
+        self.leader.apply(report);
+        self.key_overrides.apply(profile.key_overrides, report);
+    }
+
+    /// Lets the modes expire at `now_us`. Returns true if the reports have to be built again.
+    pub fn tick(&mut self, now_us: u64) -> bool {
+        let auto_shift = self.auto_shift.tick(now_us);
+        let caps_word = self.caps_word.tick(now_us);
+        let one_shot = self.one_shot.tick(now_us);
+        self.leader.tick(now_us, &mut self.macros);
+        return auto_shift || caps_word || one_shot;
+    }
+
+    /// Returns the next time [KeyState::tick] has to be called at, if any.
+    pub fn next_deadline_us(&self) -> Option<u64> {
+        return [
+            self.auto_shift.deadline_us(),
+            self.caps_word.deadline_us(),
+            self.one_shot.deadline_us(),
+            self.leader.deadline_us(),
+        ]
+        .into_iter()
+        .flatten()
+        .min();
+    }
+}
@@ -0,0 +1,450 @@
+use heapless::Vec;
+use usbd_hid::descriptor::KeyboardUsage;
+
+use crate::{
+    hid_helper::{keyboard_report::KeyboardReportHelper, modifiers::Modifiers},
+    macros::{
+        macro_player::MacroPlayer, macro_recorder::DynamicMacroAction, macro_step::MacroStep,
+    },
+};
+
+/// Number of keys the longest leader sequence can have.
+pub const MAX_SEQUENCE_KEYS: usize = 8;
+
+/// What a complete leader sequence triggers.
+#[derive(Clone, Copy)]
+pub enum LeaderAction {
+    /// Plays the macro, see [MacroStep].
+    Macro(&'static [MacroStep<'static>]),
+    /// Records or plays back a macro at runtime.
+    DynamicMacro(DynamicMacroAction),
+}
+
+/// What comes after a key of a leader sequence.
+#[derive(Clone, Copy)]
+pub enum LeaderNext {
+    /// The sequence is complete.
+    Action(LeaderAction),
+    /// The sequence goes on with one of the keys.
+    Keys(&'static [LeaderNode]),
+}
+
+/// A key of the leader sequences, in the trie of a [LeaderConfig]: the sequences starting with
+/// the same keys share their nodes.
+#[derive(Clone, Copy)]
+pub struct LeaderNode {
+    pub key: KeyboardUsage,
+    pub next: LeaderNext,
+}
+
+/// The leader key settings of a profile.
+#[derive(Clone, Copy)]
+pub struct LeaderConfig {
+    /// Time allowed between two keys of a sequence, 0 to wait forever.
+    pub timeout_ms: u16,
+    /// Taps the keys of a sequence that does not match to the host, as if there had been no
+    /// leader key.
+    pub replay_unmatched: bool,
+    /// The first keys of the sequences, see [is_valid].
+    pub sequences: &'static [LeaderNode],
+}
+
+impl LeaderConfig {
+    pub const DISABLED: LeaderConfig = LeaderConfig {
+        timeout_ms: 0,
+        replay_unmatched: false,
+        sequences: &[],
+    };
+}
+
+/// Returns true if the sequences can all be typed: no sequence is a prefix of another, as no
+/// two keys following the same keys are the same, no sequence is left without an action and
+/// none is longer than [MAX_SEQUENCE_KEYS]. Meant to be checked at compile time:
+/// ```ignore
+/// const _: () = assert!(is_valid(LEADER_SEQUENCES));
+/// ```
+pub const fn is_valid(sequences: &[LeaderNode]) -> bool {
+    return sequences.is_empty() || are_valid_keys(sequences, 1);
+}
+
+const fn are_valid_keys(nodes: &[LeaderNode], depth: usize) -> bool {
+    if nodes.is_empty() || depth > MAX_SEQUENCE_KEYS {
+        return false;
+    }
+    let mut index = 0;
+    while index < nodes.len() {
+        let mut other = index + 1;
+        while other < nodes.len() {
+            if nodes[index].key as u8 == nodes[other].key as u8 {
+                return false;
+            }
+            other += 1;
+        }
+        match nodes[index].next {
+            LeaderNext::Keys(next) => {
+                if !are_valid_keys(next, depth + 1) {
+                    return false;
+                }
+            }
+            LeaderNext::Action(_) => {}
+        }
+        index += 1;
+    }
+    return true;
+}
+
+/// The leader key: once it is pressed, the keys typed are held back from the host and looked up
+/// in the sequences of the profile, until they make up a sequence, which triggers its action,
+/// or no sequence starts with them. Modifiers go through as usual.
+pub struct Leader {
+    config: LeaderConfig,
+    /// The keys that can come next, [None] while no sequence is being typed.
+    next: Option<&'static [LeaderNode]>,
+    /// Keys of the sequence typed so far.
+    typed: Vec<KeyboardUsage, MAX_SEQUENCE_KEYS>,
+    /// Keys of a sequence still held, kept out of the reports until they are released.
+    held_back: Vec<KeyboardUsage, MAX_SEQUENCE_KEYS>,
+    /// The leader key was held in the previous report.
+    leader_held: bool,
+    /// The leader key is held in the report being built.
+    leader_pressed: bool,
+    last_key_us: u64,
+}
+
+impl Default for Leader {
+    fn default() -> Self {
+        return Leader::new();
+    }
+}
+
+impl Leader {
+    pub const fn new() -> Leader {
+        return Leader {
+            config: LeaderConfig::DISABLED,
+            next: None,
+            typed: Vec::new(),
+            held_back: Vec::new(),
+            leader_held: false,
+            leader_pressed: false,
+            last_key_us: 0,
+        };
+    }
+
+    /// Returns true while a sequence is being typed, e.g. for an indicator.
+    pub fn is_active(&self) -> bool {
+        return self.next.is_some();
+    }
+
+    /// Starts a report going through a profile with the given settings, the leader key has to
+    /// be seen again to count as held.
+    pub fn begin_report(&mut self, config: LeaderConfig) {
+        self.config = config;
+        self.leader_pressed = false;
+    }
+
+    /// Called by the leader key for every report it is held in.
+    pub fn press(&mut self) {
+        self.leader_pressed = true;
+    }
+
+    /// Returns true if the key is part of a sequence and has to be kept out of the report.
+    pub fn holds_back(&self, key: KeyboardUsage) -> bool {
+        return self.held_back.contains(&key);
+    }
+
+    /// Looks the keys newly pressed in the report built at `now_us` up in the sequences,
+    /// `previous_keys` being the keys of the previous report. The actions and replays go to the
+    /// macro player.
+    pub fn finish_report(
+        &mut self,
+        keys: &[KeyboardUsage],
+        previous_keys: &[KeyboardUsage],
+        now_us: u64,
+        macros: &mut MacroPlayer,
+    ) {
+        self.held_back.retain(|key| keys.contains(key));
+        if self.leader_pressed && !self.leader_held {
+            self.next = Some(self.config.sequences);
+            self.typed.clear();
+            self.last_key_us = now_us;
+        }
+        self.leader_held = self.leader_pressed;
+        for key in keys {
+            if previous_keys.contains(key)
+                || self.held_back.contains(key)
+                || Modifiers::from_key(*key).is_some()
+            {
+                continue;
+            }
+            let nodes = match self.next {
+                Some(nodes) => nodes,
+                None => break,
+            };
+            let _ = self.held_back.push(*key);
+            let _ = self.typed.push(*key);
+            self.last_key_us = now_us;
+            match nodes.iter().find(|node| node.key == *key) {
+                Some(node) => match node.next {
+                    LeaderNext::Keys(next) => self.next = Some(next),
+                    LeaderNext::Action(action) => {
+                        self.next = None;
+                        match action {
+                            LeaderAction::Macro(steps) => macros.enqueue(steps),
+                            LeaderAction::DynamicMacro(action) => macros.dynamic_macro(action),
+                        }
+                    }
+                },
+                None => self.end_unmatched(macros),
+            }
+        }
+    }
+
+    /// Removes the keys of a sequence from a report about to be sent.
+    pub fn apply(&self, report: &mut KeyboardReportHelper) {
+        for key in self.held_back.iter() {
+            report.remove_keycode(*key);
+        }
+    }
+
+    /// Ends the sequence once no key came in time, replaying it if the profile asks for it.
+    pub fn tick(&mut self, now_us: u64, macros: &mut MacroPlayer) {
+        match self.deadline_us() {
+            Some(deadline_us) if now_us >= deadline_us => self.end_unmatched(macros),
+            _ => {}
+        }
+    }
+
+    /// Returns the time the sequence being typed ends at if no key comes until then.
+    pub fn deadline_us(&self) -> Option<u64> {
+        if self.next.is_none() || self.config.timeout_ms == 0 {
+            return None;
+        }
+        return Some(self.last_key_us + self.config.timeout_ms as u64 * 1000);
+    }
+
+    fn end_unmatched(&mut self, macros: &mut MacroPlayer) {
+        self.next = None;
+        if self.config.replay_unmatched && !self.typed.is_empty() {
+            macros.tap_keys(&self.typed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use usbd_hid::descriptor::KeyboardUsage::*;
+
+    use crate::{
+        macros::macro_player::MacroOutput,
+        profiles_management::{keymap_store::KeymapStore, keymap_tester::report},
+        settings::keyboard_settings::KeyboardSettings,
+    };
+
+    use super::*;
+
+    /// `g c` taps F1.
+    const SEQUENCES: &[LeaderNode] = &[LeaderNode {
+        key: KeyboardGg,
+        next: LeaderNext::Keys(&[LeaderNode {
+            key: KeyboardCc,
+            next: LeaderNext::Action(LeaderAction::Macro(&[MacroStep::Tap(KeyboardF1)])),
+        }]),
+    }];
+    const _: () = assert!(is_valid(SEQUENCES));
+
+    fn config(replay_unmatched: bool) -> LeaderConfig {
+        return LeaderConfig {
+            timeout_ms: 1000,
+            replay_unmatched,
+            sequences: SEQUENCES,
+        };
+    }
+
+    /// A report to build: the time it is built at, whether the leader key is held in it and the
+    /// other keys held.
+    type Keys<'a> = (u64, bool, &'a [KeyboardUsage]);
+
+    /// Runs the reports through the leader key and returns what they are turned into.
+    fn apply_reports(
+        leader: &mut Leader,
+        config: LeaderConfig,
+        macros: &mut MacroPlayer,
+        reports: &[Keys],
+    ) -> std::vec::Vec<KeyboardReportHelper> {
+        let mut previous_keys: &[KeyboardUsage] = &[];
+        let mut applied = std::vec::Vec::new();
+        for (now_ms, leader_held, keys) in reports {
+            leader.begin_report(config);
+            if *leader_held {
+                leader.press();
+            }
+            leader.finish_report(keys, previous_keys, now_ms * 1000, macros);
+            let mut report = report(keys);
+            leader.apply(&mut report);
+            applied.push(report);
+            previous_keys = keys;
+        }
+        return applied;
+    }
+
+    /// Plays the queued macros and returns the reports they send.
+    fn played(macros: &mut MacroPlayer) -> std::vec::Vec<KeyboardReportHelper> {
+        let store = std::boxed::Box::new(KeymapStore::new());
+        let mut reports = std::vec::Vec::new();
+        while let Some(output) = macros.next_output(&KeyboardSettings::new(), &store) {
+            match output {
+                MacroOutput::Report(report) => reports.push(report),
+                MacroOutput::Delay(_) => {}
+            }
+        }
+        return reports;
+    }
+
+    #[test]
+    fn sequences_trigger_their_action() {
+        let mut leader = Leader::new();
+        let mut macros = MacroPlayer::new();
+        let reports: &[Keys] = &[
+            (0, true, &[]),
+            (20, false, &[]),
+            (40, false, &[KeyboardGg]),
+            (60, false, &[]),
+        ];
+        assert_eq!(
+            apply_reports(&mut leader, config(true), &mut macros, reports),
+            [report(&[]); 4]
+        );
+        assert!(leader.is_active());
+        let reports: &[Keys] = &[(80, false, &[KeyboardCc]), (100, false, &[])];
+        assert_eq!(
+            apply_reports(&mut leader, config(true), &mut macros, reports),
+            [report(&[]); 2]
+        );
+        assert!(!leader.is_active());
+        assert_eq!(played(&mut macros), [report(&[KeyboardF1]), report(&[])]);
+        // the keys go through again once the sequence is over
+        let reports: &[Keys] = &[(120, false, &[KeyboardCc]), (140, false, &[])];
+        assert_eq!(
+            apply_reports(&mut leader, config(true), &mut macros, reports),
+            [report(&[KeyboardCc]), report(&[])]
+        );
+    }
+
+    #[test]
+    fn sequences_let_modifiers_through() {
+        let mut leader = Leader::new();
+        let mut macros = MacroPlayer::new();
+        let reports: &[Keys] = &[
+            (0, true, &[]),
+            (20, false, &[]),
+            (40, false, &[KeyboardLeftShift]),
+            (60, false, &[KeyboardLeftShift, KeyboardGg]),
+            (80, false, &[KeyboardLeftShift]),
+            (100, false, &[]),
+        ];
+        assert_eq!(
+            apply_reports(&mut leader, config(true), &mut macros, reports),
+            [
+                report(&[]),
+                report(&[]),
+                report(&[KeyboardLeftShift]),
+                report(&[KeyboardLeftShift]),
+                report(&[KeyboardLeftShift]),
+                report(&[]),
+            ]
+        );
+        assert!(leader.is_active());
+    }
+
+    #[test]
+    fn unmatched_sequences_are_replayed() {
+        let mut leader = Leader::new();
+        let mut macros = MacroPlayer::new();
+        let reports: &[Keys] = &[
+            (0, true, &[]),
+            (20, false, &[]),
+            (40, false, &[KeyboardGg]),
+            (60, false, &[]),
+            (80, false, &[KeyboardXx]),
+            (100, false, &[]),
+        ];
+        assert_eq!(
+            apply_reports(&mut leader, config(true), &mut macros, reports),
+            [report(&[]); 6]
+        );
+        assert!(!leader.is_active());
+        assert_eq!(
+            played(&mut macros),
+            [
+                report(&[KeyboardGg]),
+                report(&[]),
+                report(&[KeyboardXx]),
+                report(&[]),
+            ]
+        );
+
+        // a sequence left unfinished is replayed once it times out
+        let reports: &[Keys] = &[
+            (1000, true, &[]),
+            (1020, false, &[]),
+            (1040, false, &[KeyboardGg]),
+            (1060, false, &[]),
+        ];
+        apply_reports(&mut leader, config(true), &mut macros, reports);
+        assert_eq!(leader.deadline_us(), Some(2_040_000));
+        leader.tick(2_039_999, &mut macros);
+        assert!(!macros.is_pending());
+        leader.tick(2_040_000, &mut macros);
+        assert!(!leader.is_active());
+        assert_eq!(played(&mut macros), [report(&[KeyboardGg]), report(&[])]);
+    }
+
+    #[test]
+    fn unmatched_sequences_can_be_dropped() {
+        let mut leader = Leader::new();
+        let mut macros = MacroPlayer::new();
+        let reports: &[Keys] = &[
+            (0, true, &[]),
+            (20, false, &[]),
+            (40, false, &[KeyboardGg]),
+            (60, false, &[]),
+            (80, false, &[KeyboardXx]),
+            (100, false, &[]),
+            (120, false, &[KeyboardXx]),
+            (140, false, &[]),
+        ];
+        let mut expected = [report(&[]); 8];
+        expected[6] = report(&[KeyboardXx]);
+        assert_eq!(
+            apply_reports(&mut leader, config(false), &mut macros, reports),
+            expected
+        );
+        assert!(!macros.is_pending());
+    }
+
+    #[test]
+    fn sequences_cannot_be_prefixes_of_each_other() {
+        const TAP_F1: LeaderNext =
+            LeaderNext::Action(LeaderAction::Macro(&[MacroStep::Tap(KeyboardF1)]));
+        assert!(is_valid(&[]));
+        // `g` and `g c`
+        assert!(!is_valid(&[
+            LeaderNode {
+                key: KeyboardGg,
+                next: TAP_F1,
+            },
+            LeaderNode {
+                key: KeyboardGg,
+                next: LeaderNext::Keys(&[LeaderNode {
+                    key: KeyboardCc,
+                    next: TAP_F1,
+                }]),
+            },
+        ]));
+        // `g` leading nowhere
+        assert!(!is_valid(&[LeaderNode {
+            key: KeyboardGg,
+            next: LeaderNext::Keys(&[]),
+        }]));
+    }
+}
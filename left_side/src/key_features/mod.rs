@@ -0,0 +1,7 @@
+pub mod auto_shift;
+pub mod caps_word;
+pub mod key_override;
+pub mod key_state;
+pub mod leader;
+pub mod one_shot;
+pub mod swap_hands;
@@ -0,0 +1,423 @@
+use heapless::Vec;
+use usbd_hid::descriptor::KeyboardUsage;
+
+use crate::hid_helper::{keyboard_report::KeyboardReportHelper, modifiers::Modifiers};
+
+/// Number of one-shot keys kept track of at once.
+const MAX_ONE_SHOT_KEYS: usize = 4;
+
+/// The one-shot modifier settings of a profile.
+#[derive(Clone, Copy)]
+pub struct OneShotConfig {
+    /// A one-shot key held longer than this is a plain modifier once released.
+    pub tap_timeout_ms: u16,
+    /// Time a tapped one-shot key waits for the next key before it is dropped, 0 to wait
+    /// forever.
+    pub idle_timeout_ms: u16,
+}
+
+#[derive(Clone, Copy)]
+enum Phase {
+    /// Held down since `pressed_us`, `used` once another key has been pressed meanwhile. `taps`
+    /// is the number of taps the key had before: 1 if it was waiting for a key, 2 if locked.
+    Held {
+        pressed_us: u64,
+        used: bool,
+        taps: u8,
+    },
+    /// Tapped at `tapped_us`, waiting for the next key.
+    Tapped { tapped_us: u64 },
+    /// Applied to the key, until it is released or another key is pressed.
+    Applied(KeyboardUsage),
+    /// Tapped twice, applied until tapped again.
+    Locked,
+}
+
+#[derive(Clone, Copy)]
+struct OneShotKey {
+    modifiers: Modifiers,
+    phase: Phase,
+    /// Held in the report being built.
+    held: bool,
+}
+
+/// One-shot modifiers: tapping a one-shot key applies its modifiers to the next key only,
+/// holding it makes it a plain modifier and tapping it twice locks it until it is tapped again.
+/// Escape drops the ones tapped or locked.
+pub struct OneShot {
+    config: OneShotConfig,
+    keys: Vec<OneShotKey, MAX_ONE_SHOT_KEYS>,
+    now_us: u64,
+}
+
+impl Default for OneShot {
+    fn default() -> Self {
+        return OneShot::new();
+    }
+}
+
+impl OneShot {
+    pub const fn new() -> OneShot {
+        return OneShot {
+            config: OneShotConfig {
+                tap_timeout_ms: 0,
+                idle_timeout_ms: 0,
+            },
+            keys: Vec::new(),
+            now_us: 0,
+        };
+    }
+
+    /// Returns the modifiers of the one-shot keys tapped or locked, e.g. for an indicator.
+    pub fn waiting_modifiers(&self) -> Modifiers {
+        let mut modifiers = Modifiers::NONE;
+        for key in self.keys.iter() {
+            match key.phase {
+                Phase::Tapped { .. } | Phase::Locked => modifiers = modifiers.union(key.modifiers),
+                _ => {}
+            }
+        }
+        return modifiers;
+    }
+
+    /// Starts a report of the keys held at `now_us`, going through a profile with the given
+    /// settings. The one-shot keys have to be seen again to count as held.
+    pub fn begin_report(&mut self, now_us: u64, config: OneShotConfig) {
+        self.config = config;
+        self.now_us = now_us;
+        for key in self.keys.iter_mut() {
+            key.held = false;
+        }
+    }
+
+    /// Called by a one-shot key for every report it is held in.
+    pub fn hold(&mut self, modifiers: Modifiers) {
+        match self.keys.iter_mut().find(|key| key.modifiers == modifiers) {
+            Some(key) => key.held = true,
+            None => {
+                let _ = self.keys.push(OneShotKey {
+                    modifiers,
+                    phase: Phase::Held {
+                        pressed_us: self.now_us,
+                        used: false,
+                        taps: 0,
+                    },
+                    held: true,
+                });
+            }
+        }
+    }
+
+    /// Moves the one-shot keys along with the keys of the report, `previous_keys` being the ones
+    /// of the previous report.
+    pub fn finish_report(&mut self, keys: &[KeyboardUsage], previous_keys: &[KeyboardUsage]) {
+        let mut pressed = keys
+            .iter()
+            .copied()
+            .filter(|key| !previous_keys.contains(key) && Modifiers::from_key(*key).is_none());
+        let first_pressed = pressed.clone().next();
+        if pressed.any(|key| key == KeyboardUsage::KeyboardEscape) {
+            self.keys.retain(|key| key.held);
+        }
+        let now_us = self.now_us;
+        let tap_timeout_us = self.config.tap_timeout_ms as u64 * 1000;
+        self.keys.retain_mut(|key| {
+            match (key.held, key.phase) {
+                (
+                    true,
+                    Phase::Held {
+                        pressed_us,
+                        used,
+                        taps,
+                    },
+                ) => {
+                    key.phase = Phase::Held {
+                        pressed_us,
+                        used: used || first_pressed.is_some(),
+                        taps,
+                    };
+                }
+                (true, phase) => {
+                    let taps = match phase {
+                        Phase::Tapped { .. } => 1,
+                        Phase::Locked => 2,
+                        _ => 0,
+                    };
+                    key.phase = Phase::Held {
+                        pressed_us: now_us,
+                        used: first_pressed.is_some(),
+                        taps,
+                    };
+                }
+                (
+                    false,
+                    Phase::Held {
+                        pressed_us,
+                        used,
+                        taps,
+                    },
+                ) => {
+                    // held too long or along with another key, it was a plain modifier
+                    if used || now_us - pressed_us > tap_timeout_us {
+                        return false;
+                    }
+                    match taps {
+                        0 => key.phase = Phase::Tapped { tapped_us: now_us },
+                        1 => key.phase = Phase::Locked,
+                        _ => return false,
+                    }
+                }
+                (false, Phase::Tapped { .. }) => {
+                    if let Some(pressed) = first_pressed {
+                        key.phase = Phase::Applied(pressed);
+                    }
+                }
+                (false, Phase::Applied(applied)) => {
+                    if !keys.contains(&applied) || first_pressed.is_some() {
+                        return false;
+                    }
+                }
+                (false, Phase::Locked) => {}
+            }
+            return true;
+        });
+    }
+
+    /// Adds the modifiers of the one-shot keys held, applied or locked to the report.
+    pub fn apply(&self, report: &mut KeyboardReportHelper) {
+        for key in self.keys.iter() {
+            match key.phase {
+                Phase::Tapped { .. } => {}
+                _ => report.add_modifiers(key.modifiers),
+            }
+        }
+    }
+
+    /// Drops the tapped one-shot keys that waited too long for the next key. Returns true if
+    /// any was dropped.
+    pub fn tick(&mut self, now_us: u64) -> bool {
+        match self.deadline_us() {
+            Some(deadline_us) if now_us >= deadline_us => {
+                let idle_timeout_us = self.config.idle_timeout_ms as u64 * 1000;
+                self.keys.retain(|key| match key.phase {
+                    Phase::Tapped { tapped_us } => now_us < tapped_us + idle_timeout_us,
+                    _ => true,
+                });
+                return true;
+            }
+            _ => return false,
+        }
+    }
+
+    /// Returns the time the first tapped one-shot key is dropped at if no key comes until then.
+    pub fn deadline_us(&self) -> Option<u64> {
+        if self.config.idle_timeout_ms == 0 {
+            return None;
+        }
+        let idle_timeout_us = self.config.idle_timeout_ms as u64 * 1000;
+        return self
+            .keys
+            .iter()
+            .filter_map(|key| match key.phase {
+                Phase::Tapped { tapped_us } => Some(tapped_us + idle_timeout_us),
+                _ => None,
+            })
+            .min();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use usbd_hid::descriptor::KeyboardUsage::*;
+
+    use crate::profiles_management::keymap_tester::report;
+
+    use super::*;
+
+    const CONFIG: OneShotConfig = OneShotConfig {
+        tap_timeout_ms: 200,
+        idle_timeout_ms: 3000,
+    };
+
+    /// A report to build: the time it is built at, the modifiers of the one-shot keys held in it
+    /// and the other keys held.
+    type Keys<'a> = (u64, &'a [Modifiers], &'a [KeyboardUsage]);
+
+    /// Runs the reports through the one-shot keys and returns what they are turned into.
+    fn apply_reports(
+        one_shot: &mut OneShot,
+        reports: &[Keys],
+    ) -> std::vec::Vec<KeyboardReportHelper> {
+        let mut previous_keys: &[KeyboardUsage] = &[];
+        let mut applied = std::vec::Vec::new();
+        for (now_ms, held, keys) in reports {
+            one_shot.begin_report(now_ms * 1000, CONFIG);
+            for modifiers in held.iter() {
+                one_shot.hold(*modifiers);
+            }
+            one_shot.finish_report(keys, previous_keys);
+            let mut report = report(keys);
+            one_shot.apply(&mut report);
+            applied.push(report);
+            previous_keys = keys;
+        }
+        return applied;
+    }
+
+    const SHIFT: &[Modifiers] = &[Modifiers::LEFT_SHIFT];
+
+    #[test]
+    fn apply_to_the_next_key_only() {
+        let mut one_shot = OneShot::new();
+        let reports: &[Keys] = &[
+            (0, SHIFT, &[]),
+            (20, &[], &[]),
+            (40, &[], &[KeyboardQq]),
+            (60, &[], &[]),
+            (80, &[], &[KeyboardWw]),
+            (100, &[], &[]),
+        ];
+        assert_eq!(
+            apply_reports(&mut one_shot, reports),
+            [
+                report(&[KeyboardLeftShift]),
+                report(&[]),
+                report(&[KeyboardLeftShift, KeyboardQq]),
+                report(&[]),
+                report(&[KeyboardWw]),
+                report(&[]),
+            ]
+        );
+    }
+
+    #[test]
+    fn stack_up() {
+        let mut one_shot = OneShot::new();
+        let reports: &[Keys] = &[
+            (0, &[Modifiers::LEFT_CTRL], &[]),
+            (20, &[], &[]),
+            (40, SHIFT, &[]),
+            (60, &[], &[]),
+            (80, &[], &[KeyboardQq]),
+            (100, &[], &[]),
+        ];
+        assert_eq!(
+            apply_reports(&mut one_shot, reports),
+            [
+                report(&[KeyboardLeftControl]),
+                report(&[]),
+                report(&[KeyboardLeftShift]),
+                report(&[]),
+                report(&[KeyboardLeftControl, KeyboardLeftShift, KeyboardQq]),
+                report(&[]),
+            ]
+        );
+    }
+
+    #[test]
+    fn held_ones_are_plain_modifiers() {
+        let mut one_shot = OneShot::new();
+        // held past the tap timeout
+        let reports: &[Keys] = &[
+            (0, SHIFT, &[]),
+            (300, &[], &[]),
+            (320, &[], &[KeyboardQq]),
+            (340, &[], &[]),
+        ];
+        assert_eq!(
+            apply_reports(&mut one_shot, reports),
+            [
+                report(&[KeyboardLeftShift]),
+                report(&[]),
+                report(&[KeyboardQq]),
+                report(&[]),
+            ]
+        );
+        // held along with another key
+        let reports: &[Keys] = &[
+            (1000, SHIFT, &[]),
+            (1010, SHIFT, &[KeyboardPageDown]),
+            (1020, SHIFT, &[]),
+            (1030, &[], &[]),
+            (1040, &[], &[KeyboardQq]),
+        ];
+        assert_eq!(
+            apply_reports(&mut one_shot, reports),
+            [
+                report(&[KeyboardLeftShift]),
+                report(&[KeyboardLeftShift, KeyboardPageDown]),
+                report(&[KeyboardLeftShift]),
+                report(&[]),
+                report(&[KeyboardQq]),
+            ]
+        );
+    }
+
+    #[test]
+    fn lock_when_tapped_twice() {
+        let mut one_shot = OneShot::new();
+        let reports: &[Keys] = &[
+            (0, SHIFT, &[]),
+            (20, &[], &[]),
+            (40, SHIFT, &[]),
+            (60, &[], &[]),
+        ];
+        apply_reports(&mut one_shot, reports);
+        assert_eq!(one_shot.waiting_modifiers(), Modifiers::LEFT_SHIFT);
+        let reports: &[Keys] = &[
+            (80, &[], &[KeyboardQq]),
+            (100, &[], &[]),
+            (5000, &[], &[KeyboardWw]),
+            (5020, &[], &[]),
+            // a third tap unlocks it
+            (5040, SHIFT, &[]),
+            (5060, &[], &[]),
+            (5080, &[], &[KeyboardQq]),
+        ];
+        assert_eq!(
+            apply_reports(&mut one_shot, reports),
+            [
+                report(&[KeyboardLeftShift, KeyboardQq]),
+                report(&[KeyboardLeftShift]),
+                report(&[KeyboardLeftShift, KeyboardWw]),
+                report(&[KeyboardLeftShift]),
+                report(&[KeyboardLeftShift]),
+                report(&[]),
+                report(&[KeyboardQq]),
+            ]
+        );
+        assert_eq!(one_shot.waiting_modifiers(), Modifiers::NONE);
+    }
+
+    #[test]
+    fn escape_and_the_idle_timeout_drop_them() {
+        let mut one_shot = OneShot::new();
+        let reports: &[Keys] = &[
+            (0, SHIFT, &[]),
+            (20, &[], &[]),
+            (40, &[], &[KeyboardEscape]),
+            (60, &[], &[]),
+            (80, &[], &[KeyboardQq]),
+            (100, &[], &[]),
+        ];
+        assert_eq!(
+            apply_reports(&mut one_shot, reports),
+            [
+                report(&[KeyboardLeftShift]),
+                report(&[]),
+                report(&[KeyboardEscape]),
+                report(&[]),
+                report(&[KeyboardQq]),
+                report(&[]),
+            ]
+        );
+
+        apply_reports(&mut one_shot, &[(1000, SHIFT, &[]), (1020, &[], &[])]);
+        let deadline_us = (1020 + CONFIG.idle_timeout_ms as u64) * 1000;
+        assert_eq!(one_shot.deadline_us(), Some(deadline_us));
+        assert!(!one_shot.tick(deadline_us - 1));
+        assert!(one_shot.tick(deadline_us));
+        assert_eq!(one_shot.waiting_modifiers(), Modifiers::NONE);
+        assert_eq!(one_shot.deadline_us(), None);
+    }
+}
@@ -0,0 +1,129 @@
+use crate::io_management::{
+    key_position::KeyPosition,
+    left_half_manager::LeftKeyLocation::{self, *},
+    right_half_manager::RightKeyLocation::{self, *},
+};
+
+/// Each key of the left half along with the key mirroring it on the right half.
+const MIRRORED_KEYS: [(LeftKeyLocation, RightKeyLocation); 21] = [
+    (C1R1, C12R1),
+    (C2R1, C11R1),
+    (C3R1, C10R1),
+    (C4R1, C9R1),
+    (C5R1, C8R1),
+    (C6R1, C7R1),
+    (C1R2, C12R2),
+    (C2R2, C11R2),
+    (C3R2, C10R2),
+    (C4R2, C9R2),
+    (C5R2, C8R2),
+    (C6R2, C7R2),
+    (C1R3, C12R3),
+    (C2R3, C11R3),
+    (C3R3, C10R3),
+    (C4R3, C9R3),
+    (C5R3, C8R3),
+    (C6R3, C7R3),
+    (LT1, RT1),
+    (LT2, RT2),
+    (LT3, RT3),
+];
+
+/// Returns the key at the same place on the other half.
+pub fn mirrored(position: KeyPosition) -> KeyPosition {
+    for (left, right) in MIRRORED_KEYS {
+        if position == KeyPosition::left(left) {
+            return KeyPosition::right(right);
+        }
+        if position == KeyPosition::right(right) {
+            return KeyPosition::left(left);
+        }
+    }
+    return position;
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SwapHandsMode {
+    /// The halves are swapped while the key is held.
+    Momentary,
+    /// The key swaps the halves until it is pressed again.
+    Toggle,
+}
+
+/// Swap-hands: every key takes the action of the key mirroring it on the other half, for one
+/// hand to reach the whole keymap while the other one is on the mouse. The swap-hands keys keep
+/// their own action and the layer keys keep selecting the layers from where they are.
+///
+/// The swap follows the reports, a key changing it takes effect from the next report on.
+pub struct SwapHands {
+    toggled: bool,
+    /// A momentary key was held in the previous report.
+    held: bool,
+    /// A momentary key is held in the report being built.
+    pressed: bool,
+    toggle_held: bool,
+    toggle_pressed: bool,
+}
+
+impl Default for SwapHands {
+    fn default() -> Self {
+        return SwapHands::new();
+    }
+}
+
+impl SwapHands {
+    pub const fn new() -> SwapHands {
+        return SwapHands {
+            toggled: false,
+            held: false,
+            pressed: false,
+            toggle_held: false,
+            toggle_pressed: false,
+        };
+    }
+
+    /// Returns true while the halves are swapped, a momentary key undoing a toggled swap.
+    pub fn is_active(&self) -> bool {
+        return self.held != self.toggled;
+    }
+
+    /// Starts a report, the swap-hands keys have to be seen again to count as held.
+    pub fn begin_report(&mut self) {
+        self.pressed = false;
+        self.toggle_pressed = false;
+    }
+
+    /// Called by a swap-hands key for every report it is held in.
+    pub fn press(&mut self, mode: SwapHandsMode) {
+        match mode {
+            SwapHandsMode::Momentary => self.pressed = true,
+            SwapHandsMode::Toggle => self.toggle_pressed = true,
+        }
+    }
+
+    pub fn finish_report(&mut self) {
+        if self.toggle_pressed && !self.toggle_held {
+            self.toggled = !self.toggled;
+        }
+        self.toggle_held = self.toggle_pressed;
+        self.held = self.pressed;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mirrors_every_key_onto_the_other_half() {
+        for (left, right) in MIRRORED_KEYS {
+            assert_eq!(mirrored(KeyPosition::left(left)), KeyPosition::right(right));
+            assert_eq!(mirrored(KeyPosition::right(right)), KeyPosition::left(left));
+            let mirrors = MIRRORED_KEYS
+                .iter()
+                .filter(|(other_left, other_right)| *other_left == left || *other_right == right)
+                .count();
+            assert_eq!(mirrors, 1);
+        }
+    }
+}
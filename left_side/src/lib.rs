@@ -0,0 +1,13 @@
+//! The hardware independent part of the left half firmware: key processing, profiles, macros,
+//! the report buffer, the settings and the configuration channel. It also builds for the host,
+//! which is where its tests run.
+#![cfg_attr(not(test), no_std)]
+
+pub mod config_channel;
+pub mod hid_helper;
+pub mod io_management;
+pub mod key_features;
+pub mod macros;
+pub mod profiles_management;
+pub mod report_buffer;
+pub mod settings;
@@ -0,0 +1,447 @@
+use heapless::{Deque, Vec};
+use usbd_hid::descriptor::KeyboardUsage;
+
+use crate::{
+    hid_helper::{
+        host_layout::LayoutKey,
+        keyboard_report::KeyboardReportHelper,
+        unicode_input::{unicode_reports, UnicodeInput, MAX_UNICODE_REPORTS},
+    },
+    profiles_management::{
+        keymap_blob::{self, EncodedMacro},
+        keymap_store::{KeymapStore, POOL_CAPACITY},
+    },
+    settings::keyboard_settings::KeyboardSettings,
+};
+
+use super::{
+    macro_recorder::{DynamicMacroAction, MacroRecorder},
+    macro_step::MacroStep,
+    via_macro::{via_macro_bytes, via_macro_step_at},
+};
+
+/// Number of macros that can wait for their turn while another one is playing.
+const MACRO_QUEUE_SIZE: usize = 4;
+/// Number of keys a macro can hold down at the same time.
+const MAX_HELD_KEYS: usize = 14;
+/// Number of keys queued by [MacroPlayer::tap_keys] that can wait for their turn.
+const MAX_QUEUED_TAPS: usize = 16;
+
+/// What the macro runner has to do next.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MacroOutput {
+    /// Send the report to the host.
+    Report(KeyboardReportHelper),
+    /// Wait for the given number of milliseconds.
+    Delay(u16),
+}
+
+/// A macro waiting in the queue of the [MacroPlayer].
+#[derive(Clone, Copy)]
+enum QueuedMacro {
+    Steps(&'static [MacroStep<'static>]),
+    /// A macro of the macro pool of a keymap blob, decoded step by step.
+    Encoded(EncodedMacro),
+    /// The macro at the offset in the macro pool of the keymap store, played from
+    /// [MacroPlayer::snapshot].
+    Stored(u16),
+    /// The macro at the index of the VIA macro buffer of the keymap store, played from
+    /// [MacroPlayer::snapshot].
+    Via(u8),
+    /// The macro recorded into the slot of the [MacroRecorder].
+    Recorded(u8),
+    /// Taps the given number of keys, taken from the front of [MacroPlayer::taps].
+    Taps(u8),
+    /// Types the character through the Unicode input method.
+    Character(char),
+}
+
+/// Turns queued macros into a stream of reports and delays, one [MacroOutput] at a time.
+///
+/// The player does not keep time itself, it is driven by the macro runner which sends the
+/// reports and sleeps through the delays, so a long macro never blocks key processing. It also
+/// owns the [MacroRecorder] holding the macros recorded at runtime.
+pub struct MacroPlayer {
+    queue: Deque<QueuedMacro, MACRO_QUEUE_SIZE>,
+    current: Option<QueuedMacro>,
+    /// Index of the next step of the current macro, its byte offset for a macro decoded from
+    /// bytes.
+    step: usize,
+    /// The bytes of the current macro when it comes from the keymap store, copied as it starts
+    /// for the changes made to the store meanwhile not to show half way through.
+    snapshot: Vec<u8, POOL_CAPACITY>,
+    /// Byte offset of the next character when the current step is a [MacroStep::Text] or a
+    /// [MacroStep::Unicode].
+    text_pos: usize,
+    /// Keys held down by the current macro.
+    held: Vec<KeyboardUsage, MAX_HELD_KEYS>,
+    /// A tap has been pressed, the next output releases it.
+    tap_pressed: bool,
+    /// The delay before the current recorded report has been waited.
+    delay_done: bool,
+    recorder: MacroRecorder,
+    /// Keys queued by [MacroPlayer::tap_keys], in order.
+    taps: Deque<KeyboardUsage, MAX_QUEUED_TAPS>,
+    /// Reports left of the character being typed, through the host layout or the Unicode input
+    /// method.
+    typing: Deque<KeyboardReportHelper, MAX_UNICODE_REPORTS>,
+}
+
+impl Default for MacroPlayer {
+    fn default() -> Self {
+        return MacroPlayer::new();
+    }
+}
+
+impl MacroPlayer {
+    pub const fn new() -> MacroPlayer {
+        return MacroPlayer {
+            queue: Deque::new(),
+            current: None,
+            step: 0,
+            snapshot: Vec::new(),
+            text_pos: 0,
+            held: Vec::new(),
+            tap_pressed: false,
+            delay_done: false,
+            recorder: MacroRecorder::new(),
+            taps: Deque::new(),
+            typing: Deque::new(),
+        };
+    }
+
+    /// Queues the macro to be played after the ones already queued. The macro is dropped if the
+    /// queue is full.
+    pub fn enqueue(&mut self, steps: &'static [MacroStep<'static>]) {
+        let _ = self.queue.push_back(QueuedMacro::Steps(steps));
+    }
+
+    /// Queues a macro of a keymap blob, see [MacroPlayer::enqueue].
+    pub fn enqueue_encoded(&mut self, encoded: EncodedMacro) {
+        let _ = self.queue.push_back(QueuedMacro::Encoded(encoded));
+    }
+
+    /// Queues the macro at the offset in the macro pool of the keymap store, see
+    /// [MacroPlayer::enqueue]. The macro is read from the pool as it starts, the changes made to
+    /// the pool while it plays leave it as it was.
+    pub fn enqueue_stored(&mut self, offset: u16) {
+        let _ = self.queue.push_back(QueuedMacro::Stored(offset));
+    }
+
+    /// Queues the macro at the index of the VIA macro buffer of the keymap store, see
+    /// [MacroPlayer::enqueue_stored].
+    pub fn enqueue_via(&mut self, index: u8) {
+        let _ = self.queue.push_back(QueuedMacro::Via(index));
+    }
+
+    /// Carries out a key action controlling the recorded macros. A slot cannot be played back
+    /// while it is being recorded into.
+    pub fn dynamic_macro(&mut self, action: DynamicMacroAction) {
+        match action {
+            DynamicMacroAction::Record(slot) => self.recorder.start(slot),
+            DynamicMacroAction::Stop => self.recorder.stop(),
+            DynamicMacroAction::Play(slot) => {
+                if !self.recorder.is_recording(slot) {
+                    let _ = self.queue.push_back(QueuedMacro::Recorded(slot));
+                }
+            }
+        }
+    }
+
+    /// Queues a macro tapping the keys one after the other. The keys that do not fit are
+    /// dropped.
+    pub fn tap_keys(&mut self, keys: &[KeyboardUsage]) {
+        if self.queue.is_full() {
+            return;
+        }
+        let mut count = 0;
+        for key in keys {
+            if self.taps.push_back(*key).is_err() {
+                break;
+            }
+            count += 1;
+        }
+        let _ = self.queue.push_back(QueuedMacro::Taps(count));
+    }
+
+    /// Queues a macro typing the character through the Unicode input method.
+    pub fn type_character(&mut self, character: char) {
+        let _ = self.queue.push_back(QueuedMacro::Character(character));
+    }
+
+    /// Hands a report sent to the host at `now_ms` over to the recording in progress, if any.
+    pub fn record(&mut self, now_ms: u64, report: KeyboardReportHelper) {
+        self.recorder.record(now_ms, report);
+    }
+
+    /// Returns true if a macro is playing or waiting to be played.
+    pub fn is_pending(&self) -> bool {
+        return self.current.is_some() || !self.queue.is_empty();
+    }
+
+    /// Advances the playback and returns the next output, typing text for the host layout and
+    /// Unicode characters with the input method of the settings, and reading the stored macros
+    /// from the keymap store. Returns [None] once all the queued macros have been played.
+    pub fn next_output(
+        &mut self,
+        settings: &KeyboardSettings,
+        store: &KeymapStore,
+    ) -> Option<MacroOutput> {
+        if self.tap_pressed {
+            self.tap_pressed = false;
+            return Some(MacroOutput::Report(self.held_report()));
+        }
+        loop {
+            if let Some(report) = self.typing.pop_front() {
+                return Some(MacroOutput::Report(report));
+            }
+            // the step along with where the next one is
+            let step = match self.current {
+                Some(QueuedMacro::Steps(steps)) => {
+                    steps.get(self.step).map(|step| (*step, self.step + 1))
+                }
+                Some(QueuedMacro::Encoded(encoded)) => encoded.step(self.step),
+                Some(QueuedMacro::Stored(_)) => {
+                    keymap_blob::macro_step_at(&self.snapshot, self.step)
+                }
+                Some(QueuedMacro::Via(_)) => via_macro_step_at(&self.snapshot, self.step),
+                Some(QueuedMacro::Recorded(slot)) => match self.next_recorded_output(slot) {
+                    Some(output) => return Some(output),
+                    None => {
+                        self.current = None;
+                        continue;
+                    }
+                },
+                Some(QueuedMacro::Taps(count)) => {
+                    if self.step < count as usize {
+                        self.step += 1;
+                        if let Some(key) = self.taps.pop_front() {
+                            return Some(self.tap(key));
+                        }
+                    }
+                    self.current = None;
+                    continue;
+                }
+                Some(QueuedMacro::Character(character)) => {
+                    if self.step == 0 {
+                        self.step = 1;
+                        self.queue_unicode(settings.unicode_input, character);
+                    } else {
+                        self.current = None;
+                    }
+                    continue;
+                }
+                None => {
+                    let current = self.queue.pop_front()?;
+                    match current {
+                        QueuedMacro::Stored(offset) => {
+                            let bytes = store.pool().get(offset as usize..).unwrap_or(&[]);
+                            self.take_snapshot(&bytes[..keymap_blob::macro_size(bytes)]);
+                        }
+                        QueuedMacro::Via(index) => {
+                            self.take_snapshot(via_macro_bytes(store.via_macro_buffer(), index));
+                        }
+                        _ => {}
+                    }
+                    self.current = Some(current);
+                    self.step = 0;
+                    self.text_pos = 0;
+                    self.delay_done = false;
+                    continue;
+                }
+            };
+            let (step, next) = match step {
+                Some(step) => step,
+                None => {
+                    // the macro is over, releasing whatever it left held
+                    self.current = None;
+                    if self.held.is_empty() {
+                        continue;
+                    }
+                    self.held.clear();
+                    return Some(MacroOutput::Report(KeyboardReportHelper::new()));
+                }
+            };
+            match step {
+                MacroStep::Press(key) => {
+                    self.step = next;
+                    if !self.held.contains(&key) {
+                        let _ = self.held.push(key);
+                    }
+                    return Some(MacroOutput::Report(self.held_report()));
+                }
+                MacroStep::Release(key) => {
+                    self.step = next;
+                    self.held.retain(|held_key| *held_key != key);
+                    return Some(MacroOutput::Report(self.held_report()));
+                }
+                MacroStep::Tap(key) => {
+                    self.step = next;
+                    return Some(self.tap(key));
+                }
+                MacroStep::DelayMs(delay_ms) => {
+                    self.step = next;
+                    return Some(MacroOutput::Delay(delay_ms));
+                }
+                MacroStep::Text(text) => {
+                    let character = match text[self.text_pos..].chars().next() {
+                        Some(character) => character,
+                        None => {
+                            self.step = next;
+                            self.text_pos = 0;
+                            continue;
+                        }
+                    };
+                    self.text_pos += character.len_utf8();
+                    if let Some(key) = settings.host_layout.layout_key(character) {
+                        self.queue_layout_key(key);
+                    }
+                }
+                MacroStep::Unicode(text) => match text[self.text_pos..].chars().next() {
+                    Some(character) => {
+                        self.text_pos += character.len_utf8();
+                        self.queue_unicode(settings.unicode_input, character);
+                    }
+                    None => {
+                        self.step = next;
+                        self.text_pos = 0;
+                    }
+                },
+            }
+        }
+    }
+
+    /// Keeps a copy of the bytes of the macro starting to play.
+    fn take_snapshot(&mut self, bytes: &[u8]) {
+        self.snapshot.clear();
+        let _ = self.snapshot.extend_from_slice(bytes);
+    }
+
+    /// Returns the next output of the macro recorded into the slot, [None] once it is over.
+    fn next_recorded_output(&mut self, slot: u8) -> Option<MacroOutput> {
+        let recorded = *self.recorder.recording(slot).get(self.step)?;
+        if recorded.delay_ms != 0 && !self.delay_done {
+            self.delay_done = true;
+            return Some(MacroOutput::Delay(recorded.delay_ms));
+        }
+        self.delay_done = false;
+        self.step += 1;
+        return Some(MacroOutput::Report(recorded.report));
+    }
+
+    /// Queues the reports typing the character, they go out before anything else.
+    fn queue_unicode(&mut self, unicode_input: UnicodeInput, character: char) {
+        for report in unicode_reports(unicode_input, character) {
+            let _ = self.typing.push_back(report);
+        }
+    }
+
+    /// Queues the reports tapping the key of the host layout on top of the held keys, followed
+    /// by a space for a dead key to type its own character.
+    fn queue_layout_key(&mut self, key: LayoutKey) {
+        let mut report = self.held_report();
+        report.add_modifiers(key.modifiers);
+        report.add_keycode(key.key);
+        let _ = self.typing.push_back(report);
+        let _ = self.typing.push_back(self.held_report());
+        if key.dead {
+            let mut report = self.held_report();
+            report.add_keycode(KeyboardUsage::KeyboardSpacebar);
+            let _ = self.typing.push_back(report);
+            let _ = self.typing.push_back(self.held_report());
+        }
+    }
+
+    /// Presses the key on top of the held ones, the release follows on the next output.
+    fn tap(&mut self, key: KeyboardUsage) -> MacroOutput {
+        let mut report = self.held_report();
+        report.add_keycode(key);
+        self.tap_pressed = true;
+        return MacroOutput::Report(report);
+    }
+
+    fn held_report(&self) -> KeyboardReportHelper {
+        let mut report = KeyboardReportHelper::new();
+        for key in self.held.iter() {
+            report.add_keycode(*key);
+        }
+        return report;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use usbd_hid::descriptor::KeyboardUsage::*;
+
+    use crate::profiles_management::{
+        keymap_store::{VIA_MACRO_BUFFER_SIZE, VIA_MACRO_BUFFER_START},
+        keymap_tester::report,
+        profiles::profile_1::profile_1::get_profile,
+    };
+
+    use super::*;
+
+    /// Plays the queued macros and returns the reports they send, the delays left out.
+    fn play(player: &mut MacroPlayer, store: &KeymapStore) -> std::vec::Vec<KeyboardReportHelper> {
+        let mut reports = std::vec::Vec::new();
+        while let Some(output) = player.next_output(&KeyboardSettings::new(), store) {
+            match output {
+                MacroOutput::Report(report) => reports.push(report),
+                MacroOutput::Delay(_) => {}
+            }
+        }
+        return reports;
+    }
+
+    #[test]
+    fn plays_stored_macros_as_they_were_when_they_started() {
+        let mut store = KeymapStore::new();
+        assert!(store.reset(&get_profile().keymap));
+        // a macro tapping A then B, past the macros of the profile
+        let offset = VIA_MACRO_BUFFER_START - 5;
+        assert!(store.write_pool(offset, &[0x03, 0x04, 0x03, 0x05, 0x00]));
+        let mut player = MacroPlayer::new();
+        player.enqueue_stored(offset as u16);
+        assert_eq!(
+            player.next_output(&KeyboardSettings::new(), &store),
+            Some(MacroOutput::Report(report(&[KeyboardAa])))
+        );
+        // B turns into C while the macro plays
+        assert!(store.write_pool(offset + 3, &[0x06]));
+        assert_eq!(
+            play(&mut player, &store),
+            [report(&[]), report(&[KeyboardBb]), report(&[])]
+        );
+        player.enqueue_stored(offset as u16);
+        assert_eq!(
+            play(&mut player, &store),
+            [
+                report(&[KeyboardAa]),
+                report(&[]),
+                report(&[KeyboardCc]),
+                report(&[]),
+            ]
+        );
+    }
+
+    #[test]
+    fn plays_via_macros_as_they_were_when_they_started() {
+        let mut store = KeymapStore::new();
+        assert!(store.reset(&get_profile().keymap));
+        // the second macro types `ab`, VIA rewriting the buffer in chunks as it plays
+        assert!(store.write_pool(VIA_MACRO_BUFFER_START, b"\0ab\0"));
+        let mut player = MacroPlayer::new();
+        player.enqueue_via(1);
+        assert_eq!(
+            player.next_output(&KeyboardSettings::new(), &store),
+            Some(MacroOutput::Report(report(&[KeyboardAa])))
+        );
+        assert!(store.write_pool(VIA_MACRO_BUFFER_START, &[0; VIA_MACRO_BUFFER_SIZE]));
+        assert_eq!(
+            play(&mut player, &store),
+            [report(&[]), report(&[KeyboardBb]), report(&[])]
+        );
+        player.enqueue_via(1);
+        assert_eq!(play(&mut player, &store), []);
+    }
+}
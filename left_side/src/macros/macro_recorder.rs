@@ -0,0 +1,117 @@
+use heapless::Vec;
+
+use crate::hid_helper::keyboard_report::KeyboardReportHelper;
+
+/// Number of RAM slots macros can be recorded into.
+pub const RECORDING_SLOTS: usize = 2;
+/// Number of reports a single slot can hold.
+const MAX_RECORDED_REPORTS: usize = 128;
+const EMPTY_SLOT: Vec<RecordedReport, MAX_RECORDED_REPORTS> = Vec::new();
+
+/// The key actions controlling the recording of macros at runtime.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum DynamicMacroAction {
+    /// Starts recording into the slot, dropping what it held before.
+    Record(u8),
+    /// Stops the recording in progress.
+    Stop,
+    /// Plays back the macro recorded into the slot.
+    Play(u8),
+}
+
+/// A report of a recorded macro, along with the time since the previous report.
+#[derive(Clone, Copy, PartialEq)]
+pub struct RecordedReport {
+    pub report: KeyboardReportHelper,
+    pub delay_ms: u16,
+}
+
+/// Records the reports sent to the host into RAM slots so they can be played back later.
+pub struct MacroRecorder {
+    slots: [Vec<RecordedReport, MAX_RECORDED_REPORTS>; RECORDING_SLOTS],
+    recording: Option<usize>,
+    /// Time of the last recorded report, [None] until the first report of the recording.
+    last_report_ms: Option<u64>,
+}
+
+impl Default for MacroRecorder {
+    fn default() -> Self {
+        return MacroRecorder::new();
+    }
+}
+
+impl MacroRecorder {
+    pub const fn new() -> MacroRecorder {
+        return MacroRecorder {
+            slots: [EMPTY_SLOT; RECORDING_SLOTS],
+            recording: None,
+            last_report_ms: None,
+        };
+    }
+
+    /// Starts recording into the slot, stopping any recording in progress. Does nothing if the
+    /// slot is already being recorded or does not exist.
+    pub fn start(&mut self, slot: u8) {
+        let slot = slot as usize;
+        if slot >= RECORDING_SLOTS || self.recording == Some(slot) {
+            return;
+        }
+        self.stop();
+        self.slots[slot].clear();
+        self.recording = Some(slot);
+        self.last_report_ms = None;
+    }
+
+    /// Stops the recording in progress. A recording always ends with all the keys released, so
+    /// that playing it back never leaves a key stuck on the host.
+    pub fn stop(&mut self) {
+        let slot = match self.recording.take() {
+            Some(slot) => slot,
+            None => return,
+        };
+        let recording = &mut self.slots[slot];
+        match recording.last() {
+            Some(last) if !last.report.is_empty() => {
+                let _ = recording.push(RecordedReport {
+                    report: KeyboardReportHelper::new(),
+                    delay_ms: 0,
+                });
+            }
+            _ => {}
+        }
+    }
+
+    /// Returns true if the slot is being recorded into.
+    pub fn is_recording(&self, slot: u8) -> bool {
+        return self.recording == Some(slot as usize);
+    }
+
+    /// Records the report sent at `now_ms`, if a recording is in progress. The recording stops by
+    /// itself once the slot is full.
+    pub fn record(&mut self, now_ms: u64, report: KeyboardReportHelper) {
+        let slot = match self.recording {
+            Some(slot) => slot,
+            None => return,
+        };
+        // the first report is played back right away, whatever the wait before it
+        let delay_ms = match self.last_report_ms {
+            Some(last_report_ms) => (now_ms - last_report_ms).min(u16::MAX as u64) as u16,
+            None => 0,
+        };
+        self.last_report_ms = Some(now_ms);
+        // keeping the last entry free for the release added when stopping
+        if self.slots[slot].len() + 1 >= MAX_RECORDED_REPORTS {
+            self.stop();
+            return;
+        }
+        let _ = self.slots[slot].push(RecordedReport { report, delay_ms });
+    }
+
+    /// Returns the reports recorded into the slot, empty if the slot does not exist.
+    pub fn recording(&self, slot: u8) -> &[RecordedReport] {
+        match self.slots.get(slot as usize) {
+            Some(recording) => return recording,
+            None => return &[],
+        }
+    }
+}
@@ -0,0 +1,23 @@
+use usbd_hid::descriptor::KeyboardUsage;
+
+/// A single step of a macro. Macros are played back one step at a time by a
+/// [MacroPlayer](super::macro_player::MacroPlayer), a key pressed by a macro stays held until
+/// it is released by a later step or the macro ends.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MacroStep<'a> {
+    /// Presses the key and keeps it held.
+    Press(KeyboardUsage),
+    /// Releases a key held by an earlier [MacroStep::Press].
+    Release(KeyboardUsage),
+    /// Presses and releases the key.
+    Tap(KeyboardUsage),
+    /// Waits for the given number of milliseconds before the next step.
+    DelayMs(u16),
+    /// Types the string, every character is tapped through the table of the host layout of the
+    /// settings, see [HostLayout](crate::hid_helper::host_layout::HostLayout). Characters the
+    /// layout cannot type are skipped.
+    Text(&'a str),
+    /// Types the string through the Unicode input method of the settings, see
+    /// [UnicodeInput](crate::hid_helper::unicode_input::UnicodeInput).
+    Unicode(&'a str),
+}
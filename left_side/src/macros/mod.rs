@@ -0,0 +1,4 @@
+pub mod macro_player;
+pub mod macro_recorder;
+pub mod macro_step;
+pub mod via_macro;
@@ -0,0 +1,110 @@
+use core::{iter, str};
+
+use usbd_hid::descriptor::KeyboardUsage;
+
+use super::macro_step::MacroStep;
+
+/// Number of macros of a VIA macro buffer.
+pub const VIA_MACRO_COUNT: u8 = 16;
+
+/// Starts a key step or a delay, any other byte up to the end of the macro is typed as text.
+const PREFIX: u8 = 0x01;
+// the steps following the prefix, a key step is followed by the key
+const TAP: u8 = 0x01;
+const PRESS: u8 = 0x02;
+const RELEASE: u8 = 0x03;
+/// Followed by the delay in milliseconds as decimal digits, ended by [DELAY_END].
+const DELAY: u8 = 0x04;
+const DELAY_END: u8 = b'|';
+
+/// Returns the steps of the macro at the index of a VIA macro buffer, the way VIA writes it: the
+/// macros one after the other, each one ended by a zero byte, in the format of the QMK
+/// `send_string`. The steps stop early at a byte the firmware cannot carry out.
+pub fn via_macro_steps(buffer: &[u8], index: u8) -> impl Iterator<Item = MacroStep<'_>> {
+    let bytes = via_macro_bytes(buffer, index);
+    let mut offset = 0;
+    return iter::from_fn(move || {
+        let (step, next) = via_macro_step_at(bytes, offset)?;
+        offset = next;
+        return Some(step);
+    });
+}
+
+/// Returns the bytes of the macro at the index of a VIA macro buffer, see [via_macro_steps],
+/// its ending zero byte left out.
+pub fn via_macro_bytes(buffer: &[u8], index: u8) -> &[u8] {
+    return buffer
+        .split(|byte| *byte == 0)
+        .nth(index as usize)
+        .unwrap_or(&[]);
+}
+
+/// Decodes the step at the offset in the bytes of a VIA macro, returning it along with the
+/// offset of the next step. Returns [None] at the end of the macro.
+pub fn via_macro_step_at(bytes: &[u8], offset: usize) -> Option<(MacroStep<'_>, usize)> {
+    let (step, size) = decode_step(bytes.get(offset..)?)?;
+    return Some((step, offset + size));
+}
+
+/// Decodes the step at the start of the bytes of a macro, returning it along with its size.
+/// Returns [None] at the end of the macro.
+fn decode_step(bytes: &[u8]) -> Option<(MacroStep<'_>, usize)> {
+    if *bytes.first()? != PREFIX {
+        // ASCII only, the text always is a valid string
+        let size = bytes
+            .iter()
+            .position(|byte| *byte == PREFIX || !byte.is_ascii());
+        let size = size.unwrap_or(bytes.len());
+        if size == 0 {
+            return None;
+        }
+        return Some((MacroStep::Text(str::from_utf8(&bytes[..size]).ok()?), size));
+    }
+    match *bytes.get(1)? {
+        TAP => return Some((MacroStep::Tap(KeyboardUsage::from(*bytes.get(2)?)), 3)),
+        PRESS => return Some((MacroStep::Press(KeyboardUsage::from(*bytes.get(2)?)), 3)),
+        RELEASE => return Some((MacroStep::Release(KeyboardUsage::from(*bytes.get(2)?)), 3)),
+        DELAY => {
+            let digits = bytes[2..].iter().position(|byte| *byte == DELAY_END)?;
+            let delay_ms = str::from_utf8(&bytes[2..2 + digits]).ok()?.parse().ok()?;
+            return Some((MacroStep::DelayMs(delay_ms), 3 + digits));
+        }
+        _ => return None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use usbd_hid::descriptor::KeyboardUsage::*;
+
+    use super::*;
+
+    #[test]
+    fn reads_the_macros_written_by_via() {
+        let buffer = b"hi\x01\x04250|\x01\x02\xE1a\x01\x03\xE1\0\0\x01\x01\x04\0";
+        let steps: Vec<MacroStep> = via_macro_steps(buffer, 0).collect();
+        assert_eq!(
+            steps,
+            [
+                MacroStep::Text("hi"),
+                MacroStep::DelayMs(250),
+                MacroStep::Press(KeyboardLeftShift),
+                MacroStep::Text("a"),
+                MacroStep::Release(KeyboardLeftShift),
+            ]
+        );
+        assert_eq!(via_macro_steps(buffer, 1).count(), 0);
+        let steps: Vec<MacroStep> = via_macro_steps(buffer, 2).collect();
+        assert_eq!(steps, [MacroStep::Tap(KeyboardAa)]);
+        assert_eq!(via_macro_steps(buffer, VIA_MACRO_COUNT - 1).count(), 0);
+    }
+
+    #[test]
+    fn stops_at_what_cannot_be_carried_out() {
+        let steps: Vec<MacroStep> = via_macro_steps(b"a\x01\x09b\0", 0).collect();
+        assert_eq!(steps, [MacroStep::Text("a")]);
+        let steps: Vec<MacroStep> = via_macro_steps(b"a\xC3\xA9\0", 0).collect();
+        assert_eq!(steps, [MacroStep::Text("a")]);
+        assert_eq!(via_macro_steps(b"\x01\x04250\0", 0).count(), 0);
+    }
+}
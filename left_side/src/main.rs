@@ -2,30 +2,53 @@
 #![no_main]
 
 mod ble_hid;
-mod hid_helper;
-mod io_management;
-mod profiles_management;
-mod report_buffer;
 mod usb_hid;
 
 use core::sync::atomic::{AtomicBool, Ordering};
 use defmt::*;
 use embassy_executor::Spawner;
-use embassy_futures::join::join;
-use embassy_rp::peripherals::{I2C0, USB};
+use embassy_futures::join::{join, join3, join4};
+use embassy_futures::select::{select, Either};
+use embassy_rp::flash::{Blocking, Flash, ERASE_SIZE, FLASH_BASE};
+#[cfg(feature = "pio-scan")]
+use embassy_rp::peripherals::PIO0;
+#[cfg(feature = "rotary-encoder")]
+use embassy_rp::peripherals::PIO1;
+use embassy_rp::peripherals::{FLASH, I2C0, USB};
+#[cfg(any(feature = "pio-scan", feature = "rotary-encoder"))]
+use embassy_rp::pio::Pio;
 use embassy_rp::usb::{Driver, InterruptHandler};
 use embassy_rp::{bind_interrupts, i2c_slave};
 use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
 use embassy_sync::mutex::Mutex;
-use embassy_time::Timer;
-use embassy_usb::class::hid::{HidReaderWriter, ReportId, RequestHandler, State};
+use embassy_sync::signal::Signal;
+#[cfg(feature = "pio-scan")]
+use embassy_time::{with_timeout, Duration};
+use embassy_time::{Instant, Timer};
+use embassy_usb::class::hid::{HidReaderWriter, ReadError, ReportId, RequestHandler, State};
 use embassy_usb::control::OutResponse;
 use embassy_usb::{Builder, Config, Handler};
-use io_management::full_keyboard_manager::FullKeyboardManager;
-use io_management::left_half_manager::{LeftIoManager, LeftReadout};
-use io_management::right_half_manager::RightReadout;
-use profiles_management::profiles::profile_1::profile_1::get_profile;
-use report_buffer::buffer::KeyboardRingBuffer;
+use keyboard_left::config_channel::protocol::{self, ConfigFollowUp};
+use keyboard_left::config_channel::via;
+use keyboard_left::io_management::debounce::{DebounceAlgorithm, Debouncer};
+use keyboard_left::io_management::full_keyboard_manager::{FullKeyboardManager, MacroPlayback};
+#[cfg(not(feature = "pio-scan"))]
+use keyboard_left::io_management::left_half_manager::LeftIoManager;
+use keyboard_left::io_management::left_half_manager::LeftReadout;
+#[cfg(feature = "rotary-encoder")]
+use keyboard_left::io_management::pio_encoder::PioRotaryEncoder;
+#[cfg(feature = "pio-scan")]
+use keyboard_left::io_management::pio_scanner::LeftPioScanner;
+use keyboard_left::io_management::right_half_manager::RightReadout;
+use keyboard_left::io_management::rotary_encoder::{split_counts, EncoderLocation};
+use keyboard_left::profiles_management::keyboard_profile::keyboard_profile::{
+    KeyboardProfile, Keymap,
+};
+use keyboard_left::profiles_management::keymap_blob::KeymapBlob;
+use keyboard_left::profiles_management::keymap_store::{KeymapStore, KEYMAP_STORE_SIZE};
+use keyboard_left::profiles_management::profiles::profile_1::profile_1::get_profile;
+use keyboard_left::report_buffer::buffer::KeyboardRingBuffer;
+use keyboard_left::settings::keyboard_settings::{KeyboardSettings, SETTINGS_SIZE};
 use usbd_hid::descriptor::{KeyboardReport, SerializedDescriptor};
 use {defmt_rtt as _, panic_probe as _};
 
@@ -34,6 +57,42 @@ bind_interrupts!(struct Irqs {
     I2C0_IRQ => embassy_rp::i2c::InterruptHandler<I2C0>;
 });
 
+#[cfg(feature = "pio-scan")]
+bind_interrupts!(struct PioIrqs {
+    PIO0_IRQ_0 => embassy_rp::pio::InterruptHandler<PIO0>;
+});
+
+#[cfg(feature = "rotary-encoder")]
+bind_interrupts!(struct EncoderIrqs {
+    PIO1_IRQ_0 => embassy_rp::pio::InterruptHandler<PIO1>;
+});
+
+const DEBOUNCE_ALGORITHM: DebounceAlgorithm = DebounceAlgorithm::DeferredPerKey;
+const DEBOUNCE_TIME_MS: u64 = 5;
+/// Time without any key down after which scanning stops until the next key press.
+#[cfg(not(feature = "pio-scan"))]
+const IDLE_TIMEOUT_MS: u64 = 500;
+/// Rate at which the PIO state machine scans the whole matrix.
+#[cfg(feature = "pio-scan")]
+const PIO_SCAN_RATE_HZ: u32 = 1000;
+/// Time the macro runner waits for the report buffer to drain before playing on.
+const MACRO_BUFFER_FULL_WAIT_MS: u64 = 5;
+/// Number of reports over which the key to report latency is summed up in the logs.
+const LATENCY_LOG_INTERVAL: u64 = 100;
+const FLASH_SIZE: usize = 2 * 1024 * 1024;
+/// The settings live in the last sector of the flash, which `memory.x` keeps out of the FLASH
+/// region the firmware is linked into.
+const SETTINGS_OFFSET: u32 = (FLASH_SIZE - ERASE_SIZE) as u32;
+/// The keymap edited over the configuration channel lives in the sector right before the
+/// settings. `memory.x` keeps it out of the FLASH region as well and checks the image ends
+/// before it.
+const KEYMAP_OFFSET: u32 = SETTINGS_OFFSET - ERASE_SIZE as u32;
+/// Time the response to a request to reboot to the bootloader is given to reach the host.
+const REBOOT_DELAY_MS: u64 = 10;
+/// Time between two checks for settings to save, which also keeps a key changing a setting over
+/// and over from wearing the flash out.
+const SETTINGS_SAVE_INTERVAL_MS: u64 = 1000;
+
 // #[embassy_executor::main]
 // async fn main(_spawner: Spawner) {
 //     let peripherals = embassy_rp::init(Default::default());
@@ -83,6 +142,8 @@ async fn oldmain(_spawner: Spawner) {
     let mut device_handler = MyDeviceHandler::new();
 
     let mut state = State::new();
+    let mut config_state = State::new();
+    let mut via_state = State::new();
 
     let mut builder = Builder::new(
         driver,
@@ -103,6 +164,31 @@ async fn oldmain(_spawner: Spawner) {
         max_packet_size: 64,
     };
     let hid = HidReaderWriter::<_, 1, 8>::new(&mut builder, &mut state, config);
+    // the configuration channel, see the protocol module
+    let config = embassy_usb::class::hid::Config {
+        report_descriptor: protocol::REPORT_DESCRIPTOR,
+        request_handler: None,
+        poll_ms: 1,
+        max_packet_size: 64,
+    };
+    let config_hid =
+        HidReaderWriter::<_, { protocol::REPORT_SIZE }, { protocol::REPORT_SIZE }>::new(
+            &mut builder,
+            &mut config_state,
+            config,
+        );
+    // the same keymap through the VIA protocol, for the VIA app
+    let config = embassy_usb::class::hid::Config {
+        report_descriptor: via::VIA_REPORT_DESCRIPTOR,
+        request_handler: None,
+        poll_ms: 1,
+        max_packet_size: 64,
+    };
+    let via_hid = HidReaderWriter::<_, { via::VIA_REPORT_SIZE }, { via::VIA_REPORT_SIZE }>::new(
+        &mut builder,
+        &mut via_state,
+        config,
+    );
 
     // Build the builder.
     let mut usb = builder.build();
@@ -118,13 +204,62 @@ async fn oldmain(_spawner: Spawner) {
 
     // let mut buffer_mutex: Mutex<ThreadModeRawMutex, KeyboardRingBuffer> =
     //     Mutex::new(KeyboardRingBuffer::new());
-    let ring_buffer = KeyboardRingBuffer::new();
-    let readout_mutex = FullKeyboardManager::new(ring_buffer);
-    let readout_mutex: Mutex<ThreadModeRawMutex, FullKeyboardManager> = Mutex::new(readout_mutex);
+    let mut ring_buffer = KeyboardRingBuffer::new();
+    let (report_producer, mut report_consumer) = ring_buffer.split();
+    let mut flash = Flash::<_, Blocking, FLASH_SIZE>::new_blocking(p.FLASH);
+    let mut readout_manager = FullKeyboardManager::new(report_producer);
+    match load_settings(&mut flash) {
+        Some(settings) => readout_manager.load_settings(settings),
+        None => info!("No settings saved, starting with the defaults"),
+    }
+    if !load_keymap(readout_manager.keymap_store_mut()) {
+        info!("No keymap saved, starting with the one of the profile");
+    }
+    let readout_mutex: Mutex<ThreadModeRawMutex, FullKeyboardManager> = Mutex::new(readout_manager);
+    // raised whenever a key triggers a macro
+    let macro_signal: Signal<ThreadModeRawMutex, ()> = Signal::new();
+    // raised whenever reports may have been put into the ring buffer
+    let report_signal: Signal<ThreadModeRawMutex, ()> = Signal::new();
+    // raised whenever keys change, the timed modes may have a new deadline
+    let timer_signal: Signal<ThreadModeRawMutex, ()> = Signal::new();
+    #[cfg(not(feature = "pio-scan"))]
     let mut left_io_manager = LeftIoManager::new(
         p.PIN_0, p.PIN_1, p.PIN_2, p.PIN_8, p.PIN_7, p.PIN_6, p.PIN_5, p.PIN_4, p.PIN_3, p.PIN_11,
         p.PIN_12, p.PIN_13,
     );
+    #[cfg(feature = "pio-scan")]
+    let Pio {
+        mut common, sm0, ..
+    } = Pio::new(p.PIO0, PioIrqs);
+    #[cfg(feature = "pio-scan")]
+    let mut left_scanner = LeftPioScanner::new(
+        &mut common,
+        sm0,
+        p.DMA_CH0,
+        p.PIN_0,
+        p.PIN_1,
+        p.PIN_2,
+        p.PIN_8,
+        p.PIN_7,
+        p.PIN_6,
+        p.PIN_5,
+        p.PIN_4,
+        p.PIN_3,
+        p.PIN_11,
+        p.PIN_12,
+        p.PIN_13,
+        PIO_SCAN_RATE_HZ,
+    );
+
+    #[cfg(feature = "rotary-encoder")]
+    let Pio {
+        common: mut encoder_common,
+        sm0: encoder_sm,
+        ..
+    } = Pio::new(p.PIO1, EncoderIrqs);
+    #[cfg(feature = "rotary-encoder")]
+    let mut left_encoder =
+        PioRotaryEncoder::new(&mut encoder_common, encoder_sm, p.PIN_18, p.PIN_19);
 
     const LEFT_KEYBOARD_ADDRESS: u16 = 0x0069;
 
@@ -138,47 +273,243 @@ async fn oldmain(_spawner: Spawner) {
     let profile = get_profile();
 
     let right_fut = async {
-        let mut i2c_buffer: [u8; 4] = [0, 0, 0, 0];
+        // [ thumb_cluster , row_3 , row_2 , row_1 , encoder_counts ]
+        let mut i2c_buffer: [u8; 5] = [0, 0, 0, 0, 0];
+        let mut previous_readout = RightReadout::default();
         loop {
             device.listen(&mut i2c_buffer).await;
+            let now = Instant::now().as_micros();
             let readout =
                 RightReadout::new(i2c_buffer[3], i2c_buffer[2], i2c_buffer[1], i2c_buffer[0]);
             let mut readout_manager = readout_mutex.lock().await;
-            readout_manager.update_right_readout(readout, &profile);
+            if readout != previous_readout {
+                previous_readout = readout;
+                debug!(
+                    "trace: {}us right {}",
+                    now,
+                    [i2c_buffer[3], i2c_buffer[2], i2c_buffer[1], i2c_buffer[0]]
+                );
+                readout_manager.update_right_readout(readout, &profile, now);
+            }
+            if i2c_buffer[4] != 0 {
+                debug!("trace: {}us encoder right {}", now, i2c_buffer[4] as i8);
+            }
+            let (direction, counts) = split_counts(i2c_buffer[4] as i8);
+            readout_manager.process_encoder(
+                EncoderLocation::Right,
+                direction,
+                counts,
+                &profile,
+                now,
+            );
+            if readout_manager.is_macro_pending() {
+                macro_signal.signal(());
+            }
+            report_signal.signal(());
+            timer_signal.signal(());
         }
     };
 
+    #[cfg(not(feature = "pio-scan"))]
     let left_fut = async {
         let mut previous_readout = LeftReadout::default();
+        let mut debouncer: Debouncer<4> = Debouncer::new(DEBOUNCE_ALGORITHM, DEBOUNCE_TIME_MS);
+        let mut last_activity = Instant::now();
         loop {
-            let readout = left_io_manager.produce_readout().await;
+            let raw_readout = left_io_manager.produce_readout().await;
+            let now = Instant::now();
+            if !raw_readout.is_empty() {
+                last_activity = now;
+            }
+            let readout = LeftReadout::from_bytes(
+                debouncer.debounce(now.as_millis(), raw_readout.as_bytes()),
+            );
             // eliminating duplicate readouts
             if readout != previous_readout {
                 // processing the readout
                 previous_readout = readout;
+                debug!("trace: {}us left {}", now.as_micros(), readout.as_bytes());
                 let mut readout_manager = readout_mutex.lock().await;
-                readout_manager.update_left_readout(previous_readout, &profile);
+                readout_manager.update_left_readout(previous_readout, &profile, now.as_micros());
+                if readout_manager.is_macro_pending() {
+                    macro_signal.signal(());
+                }
+                report_signal.signal(());
+                timer_signal.signal(());
+            }
+            // going idle, nothing has been held down for a while
+            if readout.is_empty() && (now - last_activity).as_millis() >= IDLE_TIMEOUT_MS {
+                left_io_manager.wait_for_key_press().await;
+                last_activity = Instant::now();
+                continue;
             }
             Timer::after_millis(2).await;
         }
     };
 
-    let readout_fut = join(right_fut, left_fut);
+    #[cfg(feature = "pio-scan")]
+    let left_fut = async {
+        let mut previous_readout = LeftReadout::default();
+        let mut debouncer: Debouncer<4> = Debouncer::new(DEBOUNCE_ALGORITHM, DEBOUNCE_TIME_MS);
+        loop {
+            let raw_readout = if debouncer.is_settled() {
+                left_scanner.wait_for_change().await
+            } else {
+                // still debouncing, the current readout has to be fed again even if it does not
+                // change
+                with_timeout(Duration::from_millis(1), left_scanner.wait_for_change())
+                    .await
+                    .unwrap_or_else(|_| left_scanner.readout())
+            };
+            let now = Instant::now();
+            let readout = LeftReadout::from_bytes(
+                debouncer.debounce(now.as_millis(), raw_readout.as_bytes()),
+            );
+            // eliminating duplicate readouts
+            if readout != previous_readout {
+                // processing the readout
+                previous_readout = readout;
+                debug!("trace: {}us left {}", now.as_micros(), readout.as_bytes());
+                let mut readout_manager = readout_mutex.lock().await;
+                readout_manager.update_left_readout(previous_readout, &profile, now.as_micros());
+                if readout_manager.is_macro_pending() {
+                    macro_signal.signal(());
+                }
+                report_signal.signal(());
+                timer_signal.signal(());
+            }
+        }
+    };
 
-    let in_fut = async {
+    #[cfg(feature = "rotary-encoder")]
+    let encoder_fut = async {
         loop {
+            left_encoder.wait_for_counts().await;
+            let counts = left_encoder.take_counts();
+            let now = Instant::now().as_micros();
+            debug!("trace: {}us encoder left {}", now, counts);
+            let (direction, counts) = split_counts(counts);
             let mut readout_manager = readout_mutex.lock().await;
-            let report_helper = readout_manager.get_report_helper();
-            drop(readout_manager);
-            match report_helper {
-                Some(report_helper) => {
-                    let report = report_helper.get_report();
-                    writer.ready().await;
-                    writer.write_serialize(&report).await.unwrap();
+            readout_manager.process_encoder(
+                EncoderLocation::Left,
+                direction,
+                counts,
+                &profile,
+                now,
+            );
+            if readout_manager.is_macro_pending() {
+                macro_signal.signal(());
+            }
+            report_signal.signal(());
+            timer_signal.signal(());
+        }
+    };
+    #[cfg(not(feature = "rotary-encoder"))]
+    let encoder_fut = async {};
+
+    let readout_fut = join3(right_fut, left_fut, encoder_fut);
+
+    let in_fut = async {
+        let mut overflows = 0;
+        let mut high_water_mark = 0;
+        let mut latency_count: u64 = 0;
+        let mut latency_sum_us: u64 = 0;
+        let mut latency_max_us: u64 = 0;
+        loop {
+            let queued = match report_consumer.take_report() {
+                Some(queued) => queued,
+                None => {
+                    // drained, letting in the report that did not fit (if any)
+                    readout_mutex.lock().await.flush_reports();
+                    match report_consumer.take_report() {
+                        Some(queued) => queued,
+                        None => {
+                            report_signal.wait().await;
+                            continue;
+                        }
+                    }
                 }
-                None => {}
             };
-            Timer::after_millis(5).await;
+            let report = queued.report.get_report();
+            writer.ready().await;
+            writer.write_serialize(&report).await.unwrap();
+
+            let latency_us = Instant::now().as_micros() - queued.timestamp_us;
+            debug!("Key to report latency: {} us", latency_us);
+            latency_count += 1;
+            latency_sum_us += latency_us;
+            latency_max_us = latency_max_us.max(latency_us);
+            if latency_count == LATENCY_LOG_INTERVAL {
+                info!(
+                    "Key to report latency over {} reports: {} us average, {} us max",
+                    latency_count,
+                    latency_sum_us / latency_count,
+                    latency_max_us
+                );
+                latency_count = 0;
+                latency_sum_us = 0;
+                latency_max_us = 0;
+            }
+
+            if report_consumer.overflows() != overflows {
+                overflows = report_consumer.overflows();
+                warn!(
+                    "Report buffer overflowed, {} reports coalesced so far",
+                    overflows
+                );
+            }
+            if report_consumer.high_water_mark() != high_water_mark {
+                high_water_mark = report_consumer.high_water_mark();
+                info!("Report buffer high water mark: {}", high_water_mark);
+            }
+        }
+    };
+
+    // plays the triggered macros, sleeping through their delays without holding up the keys
+    let macro_fut = async {
+        loop {
+            macro_signal.wait().await;
+            loop {
+                let mut readout_manager = readout_mutex.lock().await;
+                let playback = readout_manager.play_macro(Instant::now().as_micros());
+                drop(readout_manager);
+                report_signal.signal(());
+                match playback {
+                    MacroPlayback::Finished => break,
+                    MacroPlayback::Delay(delay_ms) => Timer::after_millis(delay_ms as u64).await,
+                    MacroPlayback::BufferFull => {
+                        Timer::after_millis(MACRO_BUFFER_FULL_WAIT_MS).await
+                    }
+                }
+            }
+        }
+    };
+
+    // lets the timed modes (e.g. Caps Word) expire when no key change comes first
+    let timer_fut = async {
+        loop {
+            let deadline_us = readout_mutex.lock().await.next_deadline_us();
+            match deadline_us {
+                Some(deadline_us) => {
+                    let deadline = Instant::from_micros(deadline_us);
+                    match select(Timer::at(deadline), timer_signal.wait()).await {
+                        Either::First(_) => {}
+                        Either::Second(_) => continue,
+                    }
+                }
+                None => {
+                    timer_signal.wait().await;
+                    continue;
+                }
+            }
+            let mut readout_manager = readout_mutex.lock().await;
+            readout_manager.tick(&profile, Instant::now().as_micros());
+            // a leader sequence may have timed out into a replay
+            if readout_manager.is_macro_pending() {
+                macro_signal.signal(());
+            }
+            drop(readout_manager);
+            report_signal.signal(());
         }
     };
 
@@ -186,13 +517,121 @@ async fn oldmain(_spawner: Spawner) {
         reader.run(false, &mut request_handler).await;
     };
 
-    let io_fut = join(out_fut, in_fut);
-    let keyboard_fut = join(io_fut, readout_fut);
+    // saves the settings once key actions changed them, and the keymap once asked to
+    let settings_fut = async {
+        loop {
+            Timer::after_millis(SETTINGS_SAVE_INTERVAL_MS).await;
+            let mut readout_manager = readout_mutex.lock().await;
+            if let Some(settings) = readout_manager.take_changed_settings() {
+                save_settings(&mut flash, &settings);
+            }
+            // the keys wait for the keymap to be written, which only happens on request
+            if readout_manager.keymap_store_mut().take_save_request() {
+                save_keymap(&mut flash, readout_manager.keymap_store().blob());
+            }
+        }
+    };
+
+    let config_fut = serve_config(config_hid, &readout_mutex, &profile, protocol::dispatch);
+    let via_fut = serve_config(via_hid, &readout_mutex, &profile, via::dispatch);
+
+    let io_fut = join4(out_fut, in_fut, macro_fut, timer_fut);
+    let config_fut = join(config_fut, via_fut);
+    let keyboard_fut = join4(io_fut, readout_fut, settings_fut, config_fut);
     // Run everything concurrently.
     // If we had made everything `'static` above instead, we could do this using separate tasks instead.
     join(usb_fut, keyboard_fut).await;
 }
 
+fn load_settings(flash: &mut Flash<'_, FLASH, Blocking, FLASH_SIZE>) -> Option<KeyboardSettings> {
+    let mut bytes = [0; SETTINGS_SIZE];
+    match flash.blocking_read(SETTINGS_OFFSET, &mut bytes) {
+        Ok(()) => return KeyboardSettings::from_bytes(&bytes),
+        Err(error) => {
+            warn!("Cannot read the settings: {}", error);
+            return None;
+        }
+    }
+}
+
+fn save_settings(flash: &mut Flash<'_, FLASH, Blocking, FLASH_SIZE>, settings: &KeyboardSettings) {
+    let result = flash
+        .blocking_erase(SETTINGS_OFFSET, SETTINGS_OFFSET + ERASE_SIZE as u32)
+        .and_then(|_| flash.blocking_write(SETTINGS_OFFSET, &settings.to_bytes()));
+    match result {
+        Ok(()) => info!("Settings saved"),
+        Err(error) => warn!("Cannot save the settings: {}", error),
+    }
+}
+
+/// Answers the requests of a configuration interface with `dispatch`, on the keymap store of the
+/// manager.
+async fn serve_config<const N: usize>(
+    hid: HidReaderWriter<'_, Driver<'_, USB>, N, N>,
+    manager: &Mutex<ThreadModeRawMutex, FullKeyboardManager<'_>>,
+    profile: &KeyboardProfile,
+    dispatch: fn(&[u8], &mut KeymapStore, &Keymap) -> ([u8; N], ConfigFollowUp),
+) {
+    let (mut reader, mut writer) = hid.split();
+    let mut request = [0; N];
+    loop {
+        let size = match reader.read(&mut request).await {
+            Ok(size) => size,
+            Err(ReadError::Disabled) => {
+                reader.ready().await;
+                continue;
+            }
+            Err(error) => {
+                warn!("Cannot read a configuration request: {}", error);
+                continue;
+            }
+        };
+        let mut readout_manager = manager.lock().await;
+        let store = readout_manager.keymap_store_mut();
+        let (response, follow_up) = dispatch(&request[..size], store, &profile.keymap);
+        drop(readout_manager);
+        match writer.write(&response).await {
+            Ok(()) => {}
+            Err(error) => warn!("Cannot answer a configuration request: {}", error),
+        }
+        match follow_up {
+            ConfigFollowUp::Nothing => {}
+            ConfigFollowUp::RebootToBootloader => {
+                info!("Rebooting to the bootloader");
+                Timer::after_millis(REBOOT_DELAY_MS).await;
+                embassy_rp::rom_data::reset_to_usb_boot(0, 0);
+            }
+        }
+    }
+}
+
+/// Loads the keymap saved by [save_keymap] into the store, read in place from the flash
+/// through XIP. Returns false if there is none.
+fn load_keymap(store: &mut KeymapStore) -> bool {
+    // the sector is out of the image, and only written by [save_keymap] from the copy the store
+    // makes of the keymap once it is edited
+    let bytes = unsafe {
+        core::slice::from_raw_parts(
+            (FLASH_BASE as usize + KEYMAP_OFFSET as usize) as *const u8,
+            KEYMAP_STORE_SIZE,
+        )
+    };
+    match KeymapBlob::new(bytes) {
+        Some(blob) => return store.load(blob),
+        None => return false,
+    }
+}
+
+fn save_keymap(flash: &mut Flash<'_, FLASH, Blocking, FLASH_SIZE>, blob: &[u8]) {
+    let result = flash
+        .blocking_erase(KEYMAP_OFFSET, KEYMAP_OFFSET + ERASE_SIZE as u32)
+        .and_then(|_| flash.blocking_write(KEYMAP_OFFSET, blob));
+    match result {
+        Ok(()) => info!("Keymap saved"),
+        Err(error) => warn!("Cannot save the keymap: {}", error),
+    }
+}
+
 struct MyRequestHandler {}
 
 impl RequestHandler for MyRequestHandler {
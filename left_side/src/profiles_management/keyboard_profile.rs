@@ -1,390 +1,289 @@
 pub mod keyboard_profile {
 
     use crate::{
-        hid_helper::keyboard_report::KeyboardReportHelper,
+        hid_helper::{
+            host_layout::HostLayout, keyboard_report::KeyboardReportHelper, modifiers::Modifiers,
+            unicode_input::UnicodeInput,
+        },
         io_management::{
+            key_position::{KeyPosition, POSITIONS},
             left_half_manager::{LeftKeyLocation, LeftReadout},
             right_half_manager::{RightKeyLocation, RightReadout},
+            rotary_encoder::{EncoderDirection, EncoderLocation},
+        },
+        key_features::{
+            auto_shift::AutoShiftConfig,
+            key_override::KeyOverride,
+            key_state::KeyState,
+            leader::LeaderConfig,
+            one_shot::OneShotConfig,
+            swap_hands::{self, SwapHandsMode},
+        },
+        macros::{macro_recorder::DynamicMacroAction, macro_step::MacroStep},
+        profiles_management::{
+            keymap_blob::{EncodedMacro, KeymapBlob},
+            layers::{resolve_layer, ConditionalLayer, LAYER_COUNT},
         },
-        report_buffer::buffer::KeyboardRingBuffer,
     };
-    use heapless::Vec;
     use usbd_hid::descriptor::KeyboardUsage;
 
+    /// The actions of every key on one layer, indexed by [KeyPosition].
+    pub type Layer = [KeyAction; POSITIONS];
+
+    /// The actions of the keys on each of the [LAYER_COUNT] layers, layer 0 being the base layer.
+    pub enum Keymap {
+        /// Tables of actions, e.g. the consts of a profile.
+        Layers(&'static [Layer; LAYER_COUNT as usize]),
+        /// A blob read in place, see [KeymapBlob].
+        Blob(KeymapBlob),
+    }
+
+    impl Keymap {
+        /// Returns the action of the key on the layer, a dead key past the last layer.
+        pub fn action(&self, layer: u8, position: KeyPosition) -> KeyAction {
+            match self {
+                Keymap::Layers(layers) => match layers.get(layer as usize) {
+                    Some(actions) => return actions[position.index()],
+                    None => return KeyAction::DeadKey,
+                },
+                Keymap::Blob(blob) => return blob.action(layer, position),
+            }
+        }
+    }
+
     pub struct KeyboardProfile {
-        pub layer_key_1: UniversalKey,
-        pub layer_key_2: UniversalKey,
-        pub c1_r1: KeyActionSet,
-        pub c2_r1: KeyActionSet,
-        pub c3_r1: KeyActionSet,
-        pub c4_r1: KeyActionSet,
-        pub c5_r1: KeyActionSet,
-        pub c6_r1: KeyActionSet,
-        pub c7_r1: KeyActionSet,
-        pub c8_r1: KeyActionSet,
-        pub c9_r1: KeyActionSet,
-        pub c10_r1: KeyActionSet,
-        pub c11_r1: KeyActionSet,
-        pub c12_r1: KeyActionSet,
-        pub c1_r2: KeyActionSet,
-        pub c2_r2: KeyActionSet,
-        pub c3_r2: KeyActionSet,
-        pub c4_r2: KeyActionSet,
-        pub c5_r2: KeyActionSet,
-        pub c6_r2: KeyActionSet,
-        pub c7_r2: KeyActionSet,
-        pub c8_r2: KeyActionSet,
-        pub c9_r2: KeyActionSet,
-        pub c10_r2: KeyActionSet,
-        pub c11_r2: KeyActionSet,
-        pub c12_r2: KeyActionSet,
-        pub c1_r3: KeyActionSet,
-        pub c2_r3: KeyActionSet,
-        pub c3_r3: KeyActionSet,
-        pub c4_r3: KeyActionSet,
-        pub c5_r3: KeyActionSet,
-        pub c6_r3: KeyActionSet,
-        pub c7_r3: KeyActionSet,
-        pub c8_r3: KeyActionSet,
-        pub c9_r3: KeyActionSet,
-        pub c10_r3: KeyActionSet,
-        pub c11_r3: KeyActionSet,
-        pub c12_r3: KeyActionSet,
-        pub lt_1: KeyActionSet,
-        pub lt_2: KeyActionSet,
-        pub lt_3: KeyActionSet,
-        pub rt_1: KeyActionSet,
-        pub rt_2: KeyActionSet,
-        pub rt_3: KeyActionSet,
+        pub layer_key_1: KeyPosition,
+        pub layer_key_2: KeyPosition,
+        pub keymap: Keymap,
+        pub left_encoder: EncoderActionSet,
+        pub right_encoder: EncoderActionSet,
+        pub auto_shift: AutoShiftConfig,
+        /// Checked in order, see [KeyOverride].
+        pub key_overrides: &'static [KeyOverride],
+        pub one_shot: OneShotConfig,
+        pub leader: LeaderConfig,
+        /// Checked in order, see [ConditionalLayer].
+        pub conditional_layers: &'static [ConditionalLayer],
     }
 
     impl KeyboardProfile {
+        /// Returns the report to send for the readouts. The macros started by the keys go to the
+        /// macro player, on a stream of their own, and leave the other keys in the report.
         pub fn process_readout(
             &self,
             left_readout: &LeftReadout,
             right_readout: &RightReadout,
-            buffer: &mut KeyboardRingBuffer,
-        ) {
-            let layer_one = match &self.layer_key_1 {
-                UniversalKey::RightKey(right_key_location) => {
-                    right_readout.is_pressed(&right_key_location)
-                }
-                UniversalKey::LeftKey(left_key_location) => {
-                    left_readout.is_pressed(&left_key_location)
-                }
-            };
-            let layer_two = match &self.layer_key_2 {
-                UniversalKey::RightKey(right_key_location) => {
-                    right_readout.is_pressed(&right_key_location)
-                }
-                UniversalKey::LeftKey(left_key_location) => {
-                    left_readout.is_pressed(&left_key_location)
-                }
+            state: &mut KeyState,
+        ) -> KeyboardReportHelper {
+            let layer = self.active_layer(left_readout, right_readout, state);
+            return self.held_keys_report(left_readout, right_readout, layer, state);
+        }
+
+        /// Taps the action bound to the given encoder direction once, on top of the keys that
+        /// are currently held. Returns the reports pressing and releasing the action.
+        pub fn process_encoder(
+            &self,
+            encoder: EncoderLocation,
+            direction: EncoderDirection,
+            left_readout: &LeftReadout,
+            right_readout: &RightReadout,
+            state: &mut KeyState,
+        ) -> (KeyboardReportHelper, KeyboardReportHelper) {
+            let encoder_actions = self.encoder_actions(encoder);
+            let actions = match direction {
+                EncoderDirection::Clockwise => &encoder_actions.clockwise,
+                EncoderDirection::CounterClockwise => &encoder_actions.counter_clockwise,
             };
-            let layer = LayerLevel::new(layer_one, layer_two);
-            let mut report = KeyboardReportHelper::new();
+            let layer = self.active_layer(left_readout, right_readout, state);
+            let held_report = self.held_keys_report(left_readout, right_readout, layer, state);
+            let mut report = held_report;
+            if let Some(action) = actions.get(layer as usize) {
+                // every step is a fresh press
+                action.add_to_buffer(state, &mut report, true);
+            }
+            return (report, held_report);
+        }
 
-            if left_readout.is_pressed(&LeftKeyLocation::C1R1) {
-                if self.c1_r1.process_key(&layer, buffer, &mut report) {
-                    return;
-                }
-            }
-            if left_readout.is_pressed(&LeftKeyLocation::C1R2) {
-                if self.c1_r2.process_key(&layer, buffer, &mut report) {
-                    return;
-                }
-            }
-            if left_readout.is_pressed(&LeftKeyLocation::C1R3) {
-                if self.c1_r3.process_key(&layer, buffer, &mut report) {
-                    return;
-                }
-            }
-            if left_readout.is_pressed(&LeftKeyLocation::C2R1) {
-                if self.c2_r1.process_key(&layer, buffer, &mut report) {
-                    return;
-                }
-            }
-            if left_readout.is_pressed(&LeftKeyLocation::C2R2) {
-                if self.c2_r2.process_key(&layer, buffer, &mut report) {
-                    return;
-                }
-            }
-            if left_readout.is_pressed(&LeftKeyLocation::C2R3) {
-                if self.c2_r3.process_key(&layer, buffer, &mut report) {
-                    return;
-                }
-            }
-            if left_readout.is_pressed(&LeftKeyLocation::C3R1) {
-                if self.c3_r1.process_key(&layer, buffer, &mut report) {
-                    return;
-                }
-            }
-            if left_readout.is_pressed(&LeftKeyLocation::C3R2) {
-                if self.c3_r2.process_key(&layer, buffer, &mut report) {
-                    return;
-                }
-            }
-            if left_readout.is_pressed(&LeftKeyLocation::C3R3) {
-                if self.c3_r3.process_key(&layer, buffer, &mut report) {
-                    return;
-                }
-            }
-            if left_readout.is_pressed(&LeftKeyLocation::C4R1) {
-                if self.c4_r1.process_key(&layer, buffer, &mut report) {
-                    return;
-                }
-            }
-            if left_readout.is_pressed(&LeftKeyLocation::C4R2) {
-                if self.c4_r2.process_key(&layer, buffer, &mut report) {
-                    return;
-                }
-            }
-            if left_readout.is_pressed(&LeftKeyLocation::C4R3) {
-                if self.c4_r3.process_key(&layer, buffer, &mut report) {
-                    return;
-                }
-            }
-            if left_readout.is_pressed(&LeftKeyLocation::C5R1) {
-                if self.c5_r1.process_key(&layer, buffer, &mut report) {
-                    return;
-                }
-            }
-            if left_readout.is_pressed(&LeftKeyLocation::C5R2) {
-                if self.c5_r2.process_key(&layer, buffer, &mut report) {
-                    return;
-                }
-            }
-            if left_readout.is_pressed(&LeftKeyLocation::C5R3) {
-                if self.c5_r3.process_key(&layer, buffer, &mut report) {
-                    return;
-                }
-            }
-            if left_readout.is_pressed(&LeftKeyLocation::C6R1) {
-                if self.c6_r1.process_key(&layer, buffer, &mut report) {
-                    return;
-                }
-            }
-            if left_readout.is_pressed(&LeftKeyLocation::C6R2) {
-                if self.c6_r2.process_key(&layer, buffer, &mut report) {
-                    return;
-                }
-            }
-            if left_readout.is_pressed(&LeftKeyLocation::C6R3) {
-                if self.c6_r3.process_key(&layer, buffer, &mut report) {
-                    return;
-                }
-            }
-            if left_readout.is_pressed(&LeftKeyLocation::LT1) {
-                if self.lt_1.process_key(&layer, buffer, &mut report) {
-                    return;
-                }
-            }
-            if left_readout.is_pressed(&LeftKeyLocation::LT2) {
-                if self.lt_2.process_key(&layer, buffer, &mut report) {
-                    return;
-                }
-            }
-            if left_readout.is_pressed(&LeftKeyLocation::LT3) {
-                if self.lt_3.process_key(&layer, buffer, &mut report) {
-                    return;
-                }
-            }
-            if right_readout.is_pressed(&RightKeyLocation::C7R1) {
-                if self.c7_r1.process_key(&layer, buffer, &mut report) {
-                    return;
-                }
-            }
-            if right_readout.is_pressed(&RightKeyLocation::C7R2) {
-                if self.c7_r2.process_key(&layer, buffer, &mut report) {
-                    return;
-                }
-            }
-            if right_readout.is_pressed(&RightKeyLocation::C7R3) {
-                if self.c7_r3.process_key(&layer, buffer, &mut report) {
-                    return;
-                }
-            }
-            if right_readout.is_pressed(&RightKeyLocation::C8R1) {
-                if self.c8_r1.process_key(&layer, buffer, &mut report) {
-                    return;
-                }
-            }
-            if right_readout.is_pressed(&RightKeyLocation::C8R2) {
-                if self.c8_r2.process_key(&layer, buffer, &mut report) {
-                    return;
-                }
-            }
-            if right_readout.is_pressed(&RightKeyLocation::C8R3) {
-                if self.c8_r3.process_key(&layer, buffer, &mut report) {
-                    return;
-                }
-            }
-            if right_readout.is_pressed(&RightKeyLocation::C9R1) {
-                if self.c9_r1.process_key(&layer, buffer, &mut report) {
-                    return;
-                }
+        /// Returns the actions of the encoder, along with its resolution.
+        pub fn encoder_actions(&self, encoder: EncoderLocation) -> &EncoderActionSet {
+            match encoder {
+                EncoderLocation::Left => return &self.left_encoder,
+                EncoderLocation::Right => return &self.right_encoder,
             }
-            if right_readout.is_pressed(&RightKeyLocation::C9R2) {
-                if self.c9_r2.process_key(&layer, buffer, &mut report) {
-                    return;
-                }
-            }
-            if right_readout.is_pressed(&RightKeyLocation::C9R3) {
-                if self.c9_r3.process_key(&layer, buffer, &mut report) {
-                    return;
-                }
-            }
-            if right_readout.is_pressed(&RightKeyLocation::C10R1) {
-                if self.c10_r1.process_key(&layer, buffer, &mut report) {
-                    return;
-                }
-            }
-            if right_readout.is_pressed(&RightKeyLocation::C10R2) {
-                if self.c10_r2.process_key(&layer, buffer, &mut report) {
-                    return;
-                }
-            }
-            if right_readout.is_pressed(&RightKeyLocation::C10R3) {
-                if self.c10_r3.process_key(&layer, buffer, &mut report) {
-                    return;
-                }
-            }
-            if right_readout.is_pressed(&RightKeyLocation::C11R1) {
-                if self.c11_r1.process_key(&layer, buffer, &mut report) {
-                    return;
-                }
-            }
-            if right_readout.is_pressed(&RightKeyLocation::C11R2) {
-                if self.c11_r2.process_key(&layer, buffer, &mut report) {
-                    return;
-                }
-            }
-            if right_readout.is_pressed(&RightKeyLocation::C11R3) {
-                if self.c11_r3.process_key(&layer, buffer, &mut report) {
-                    return;
-                }
-            }
-            if right_readout.is_pressed(&RightKeyLocation::C12R1) {
-                if self.c12_r1.process_key(&layer, buffer, &mut report) {
-                    return;
-                }
-            }
-            if right_readout.is_pressed(&RightKeyLocation::C12R2) {
-                if self.c12_r2.process_key(&layer, buffer, &mut report) {
-                    return;
-                }
+        }
+
+        /// Returns the layer the keys are looked up on, turned on by the layer keys held in the
+        /// readouts and the conditional layers, see [resolve_layer].
+        pub fn active_layer(
+            &self,
+            left_readout: &LeftReadout,
+            right_readout: &RightReadout,
+            state: &KeyState,
+        ) -> u8 {
+            let mut active = 0;
+            if self.layer_key_1.is_pressed(left_readout, right_readout) {
+                active |= 1 << 1;
             }
-            if right_readout.is_pressed(&RightKeyLocation::C12R3) {
-                if self.c12_r3.process_key(&layer, buffer, &mut report) {
-                    return;
-                }
+            if self.layer_key_2.is_pressed(left_readout, right_readout) {
+                active |= 1 << 2;
             }
-            if right_readout.is_pressed(&RightKeyLocation::RT1) {
-                if self.rt_1.process_key(&layer, buffer, &mut report) {
-                    return;
-                }
+            let default_layer = state.settings().default_layer;
+            return resolve_layer(active, self.conditional_layers, default_layer);
+        }
+
+        /// Returns the action of the key on the layer, looked up in the keymap store of the state
+        /// once a keymap has been loaded into it.
+        pub fn action(&self, state: &KeyState, layer: u8, position: KeyPosition) -> KeyAction {
+            match state.keymap_store.action(layer, position) {
+                Some(action) => return action,
+                None => return self.keymap.action(layer, position),
             }
-            if right_readout.is_pressed(&RightKeyLocation::RT2) {
-                if self.rt_2.process_key(&layer, buffer, &mut report) {
-                    return;
+        }
+
+        /// Builds the report of all the keys that are currently held. Every held key goes
+        /// through its action, whatever the others do.
+        fn held_keys_report(
+            &self,
+            left_readout: &LeftReadout,
+            right_readout: &RightReadout,
+            layer: u8,
+            state: &mut KeyState,
+        ) -> KeyboardReportHelper {
+            let mut report = KeyboardReportHelper::new();
+            let swapped = state.swap_hands.is_active();
+            for position in KeyPosition::all() {
+                if !position.is_pressed(left_readout, right_readout) {
+                    continue;
                 }
-            }
-            if right_readout.is_pressed(&RightKeyLocation::RT3) {
-                if self.rt_3.process_key(&layer, buffer, &mut report) {
-                    return;
+                let newly_pressed = state.press_position(position.index());
+                let mut action = self.action(state, layer, position);
+                if swapped && !action.swaps_hands() {
+                    action = self.action(state, layer, swap_hands::mirrored(position));
                 }
+                action.add_to_buffer(state, &mut report, newly_pressed);
             }
-            buffer.put_report(report);
+            return report;
         }
     }
 
+    #[derive(Clone, Copy, PartialEq, Debug)]
     pub enum UniversalKey {
         RightKey(RightKeyLocation),
         LeftKey(LeftKeyLocation),
     }
 
-    pub struct KeyActionSet {
-        pub(crate) base_action: KeyAction,
-        pub(crate) layer_one_action: KeyAction,
-        pub(crate) layer_two_action: KeyAction,
-        pub(crate) layer_one_and_two_action: KeyAction,
-    }
-
-    impl KeyActionSet {
-        fn process_key(
-            &self,
-            layer: &LayerLevel,
-            buffer: &mut KeyboardRingBuffer,
-            current_report: &mut KeyboardReportHelper,
-        ) -> bool {
-            match layer {
-                LayerLevel::NoLayer => self.base_action.add_to_buffer(buffer, current_report),
-                LayerLevel::LayerOne => self.layer_one_action.add_to_buffer(buffer, current_report),
-                LayerLevel::LayerTwo => self.layer_two_action.add_to_buffer(buffer, current_report),
-                LayerLevel::LayerOneAndTwo => self
-                    .layer_one_and_two_action
-                    .add_to_buffer(buffer, current_report),
-            }
-        }
-    }
-
-    enum LayerLevel {
-        NoLayer,
-        LayerOne,
-        LayerTwo,
-        LayerOneAndTwo,
-    }
-    impl LayerLevel {
-        fn new(layer_one: bool, layer_two: bool) -> LayerLevel {
-            if layer_one && layer_two {
-                return LayerLevel::LayerOneAndTwo;
-            }
-            if layer_one {
-                return LayerLevel::LayerOne;
-            }
-            if layer_two {
-                return LayerLevel::LayerTwo;
-            }
-            return LayerLevel::NoLayer;
-        }
+    /// The actions of an encoder, each direction carries an action per layer that is tapped
+    /// once per step.
+    pub struct EncoderActionSet {
+        /// Number of encoder counts that make up one step, the resolution of the encoder.
+        pub(crate) counts_per_step: u8,
+        pub(crate) clockwise: [KeyAction; LAYER_COUNT as usize],
+        pub(crate) counter_clockwise: [KeyAction; LAYER_COUNT as usize],
     }
 
     /// An enum that carries the action that needs to be carried out when the corresponding key is pressed
     /// and the associated data.  
+    #[derive(Clone, Copy, Debug)]
     pub enum KeyAction {
         DeadKey,
         HidKey(KeyboardUsage),
-        HidReport(Vec<KeyboardReportHelper, 30>),
+        /// Plays the macro, see [MacroStep].
+        Macro(&'static [MacroStep<'static>]),
+        /// Plays a macro of the macro pool of a [KeymapBlob].
+        EncodedMacro(EncodedMacro),
+        /// Plays the macro at the offset in the macro pool of the
+        /// [KeymapStore](crate::profiles_management::keymap_store::KeymapStore).
+        StoredMacro(u16),
+        /// Plays the macro at the index of the VIA macro buffer of the
+        /// [KeymapStore](crate::profiles_management::keymap_store::KeymapStore), see
+        /// [via_macro_steps](crate::macros::via_macro::via_macro_steps).
+        ViaMacro(u8),
+        /// Records or plays back a macro at runtime.
+        DynamicMacro(DynamicMacroAction),
+        /// Turns Caps Word on or off, see [CapsWord](crate::key_features::caps_word::CapsWord).
+        CapsWord,
+        /// Applies the modifiers to the next key only, see
+        /// [OneShot](crate::key_features::one_shot::OneShot).
+        OneShotMod(Modifiers),
+        /// Types the character through the Unicode input method of the settings.
+        Unicode(char),
+        /// Switches the Unicode input method to the one of the host, see [UnicodeInput].
+        SetUnicodeInput(UnicodeInput),
+        /// Switches the layout string macros are typed for to the one of the host, see
+        /// [HostLayout].
+        SetHostLayout(HostLayout),
+        /// Makes the layer the one the keys are looked up on while no layer key is held, kept
+        /// in the settings.
+        SetDefaultLayer(u8),
+        /// Starts a leader sequence, see [Leader](crate::key_features::leader::Leader).
+        Leader,
+        /// Swaps the halves of the keymap, see
+        /// [SwapHands](crate::key_features::swap_hands::SwapHands).
+        SwapHands(SwapHandsMode),
         BoardAction,
     }
 
     impl KeyAction {
-        /// Returns true if the popultion process is complete and the hid report is added to the buffer
-        /// (no need to check for any more keys). Returns false otherwise.
+        fn swaps_hands(&self) -> bool {
+            match self {
+                KeyAction::SwapHands(_) => return true,
+                _ => return false,
+            }
+        }
+
+        /// Carries out the action of a key held in the report being built. The actions handing
+        /// over to the macro player only do so when the key has just been pressed, not for every
+        /// report it stays held in.
         pub fn add_to_buffer(
             &self,
-            buffer: &mut KeyboardRingBuffer,
+            state: &mut KeyState,
             report: &mut KeyboardReportHelper,
-        ) -> bool {
+            newly_pressed: bool,
+        ) {
             match self {
-                KeyAction::DeadKey => {
-                    return false;
+                KeyAction::DeadKey => {}
+                KeyAction::HidKey(key) => state.add_key(report, *key),
+                KeyAction::Macro(steps) => {
+                    if newly_pressed {
+                        state.macros.enqueue(steps);
+                    }
                 }
-                KeyAction::HidKey(key) => {
-                    report.add_keycode(key.clone());
-                    return false;
+                KeyAction::EncodedMacro(encoded) => {
+                    if newly_pressed {
+                        state.macros.enqueue_encoded(*encoded);
+                    }
+                }
+                KeyAction::StoredMacro(offset) => {
+                    if newly_pressed {
+                        state.macros.enqueue_stored(*offset);
+                    }
+                }
+                KeyAction::ViaMacro(index) => {
+                    if newly_pressed {
+                        state.macros.enqueue_via(*index);
+                    }
+                }
+                KeyAction::DynamicMacro(action) => {
+                    if newly_pressed {
+                        state.macros.dynamic_macro(*action);
+                    }
                 }
-                KeyAction::HidReport(reports) => {
-                    for i in reports {
-                        if i.is_empty() {
-                            return true;
-                        }
-                        buffer.put_report(i.clone());
+                KeyAction::CapsWord => state.caps_word.toggle(),
+                KeyAction::OneShotMod(modifiers) => state.one_shot.hold(*modifiers),
+                KeyAction::Unicode(character) => {
+                    if newly_pressed {
+                        state.macros.type_character(*character);
                     }
-                    return true;
                 }
+                KeyAction::SetUnicodeInput(unicode_input) => {
+                    state.set_unicode_input(*unicode_input)
+                }
+                KeyAction::SetHostLayout(host_layout) => state.set_host_layout(*host_layout),
+                KeyAction::SetDefaultLayer(layer) => state.set_default_layer(*layer),
+                KeyAction::Leader => state.leader.press(),
+                KeyAction::SwapHands(mode) => state.swap_hands.press(*mode),
                 // TODO: handle the various key actions needed to be performed
-                KeyAction::BoardAction => true,
+                KeyAction::BoardAction => {}
             }
         }
     }
@@ -0,0 +1,474 @@
+use core::{fmt, iter, str};
+
+use usbd_hid::descriptor::KeyboardUsage;
+
+use crate::{
+    hid_helper::{host_layout::HostLayout, modifiers::Modifiers, unicode_input::UnicodeInput},
+    io_management::key_position::{KeyPosition, POSITIONS},
+    key_features::swap_hands::SwapHandsMode,
+    macros::{
+        macro_recorder::DynamicMacroAction, macro_step::MacroStep, via_macro::VIA_MACRO_COUNT,
+    },
+};
+
+use super::{
+    keyboard_profile::keyboard_profile::{KeyAction, Keymap},
+    layers::LAYER_COUNT,
+};
+
+/// Starts a keymap blob, telling it apart from erased or foreign flash.
+const MAGIC: [u8; 4] = *b"KMAP";
+const VERSION: u8 = 1;
+/// Magic, version, layer count, position count, a reserved byte and the macro pool size.
+const HEADER_SIZE: usize = 10;
+/// Number of bytes an action takes: its tag and a payload of two bytes.
+pub const ACTION_SIZE: usize = 3;
+/// Offset of the macro pool, right after the actions.
+pub(crate) const POOL_START: usize = HEADER_SIZE + LAYER_COUNT as usize * POSITIONS * ACTION_SIZE;
+/// Longest text of a [MacroStep::Text] or [MacroStep::Unicode] step, in bytes.
+pub const MAX_TEXT_SIZE: usize = u8::MAX as usize;
+
+// action tags, the payload of the action follows
+const DEAD_KEY: u8 = 0x00;
+const HID_KEY: u8 = 0x01;
+/// Payload: offset of the macro in the macro pool.
+const MACRO: u8 = 0x02;
+/// Payload: the kind of action, 0 to record, 1 to stop, 2 to play, then the slot.
+const DYNAMIC_MACRO: u8 = 0x03;
+const CAPS_WORD: u8 = 0x04;
+const ONE_SHOT_MOD: u8 = 0x05;
+const SET_UNICODE_INPUT: u8 = 0x06;
+const SET_HOST_LAYOUT: u8 = 0x07;
+const SET_DEFAULT_LAYER: u8 = 0x08;
+const LEADER: u8 = 0x09;
+/// Payload: 0 for momentary, 1 for toggle.
+const SWAP_HANDS: u8 = 0x0A;
+const BOARD_ACTION: u8 = 0x0B;
+/// Payload: index of the macro in the VIA macro buffer.
+const VIA_MACRO: u8 = 0x0C;
+/// Unicode characters take the tags from here to [LAST_UNICODE], the tag carrying the plane of
+/// the character and the payload its place in the plane.
+const UNICODE: u8 = 0x20;
+const LAST_UNICODE: u8 = UNICODE + 0x10;
+
+// macro step tags, a macro ends with an END tag
+const END: u8 = 0x00;
+const PRESS: u8 = 0x01;
+const RELEASE: u8 = 0x02;
+const TAP: u8 = 0x03;
+/// Followed by the delay as two bytes.
+const DELAY: u8 = 0x04;
+/// Followed by the size of the text in bytes, then the text.
+const TEXT: u8 = 0x05;
+const UNICODE_TEXT: u8 = 0x06;
+
+/// A keymap laid out to be read in place from its bytes, e.g. ones included in the firmware or
+/// loaded by the simulator, rather than built into layers. The keymap edited at runtime is held
+/// in the same format, see [KeymapStore](super::keymap_store::KeymapStore):
+///
+/// - a header: `KMAP`, the version, the number of layers and of positions, a reserved byte and
+///   the size of the macro pool as two little endian bytes,
+/// - the actions of every layer in turn, each one in [KeyPosition] order, [ACTION_SIZE] bytes
+///   an action: a tag and a little endian payload,
+/// - the macro pool, holding the steps of the macros of the actions one after the other.
+///
+/// The actions are decoded as the keys are looked up, the macros as they are played.
+#[derive(Clone, Copy)]
+pub struct KeymapBlob {
+    bytes: &'static [u8],
+}
+
+impl KeymapBlob {
+    /// Checks the blob written by [encode] at the start of the bytes. Returns [None] if they do
+    /// not hold one, or one with an action or a macro this firmware cannot carry out.
+    pub fn new(bytes: &'static [u8]) -> Option<KeymapBlob> {
+        let size = check(bytes)?;
+        return Some(KeymapBlob {
+            bytes: &bytes[..size],
+        });
+    }
+
+    /// Returns the bytes of the blob, the ones past its end left out.
+    pub fn bytes(&self) -> &'static [u8] {
+        return self.bytes;
+    }
+
+    /// Returns the action of the key on the layer, a dead key past the last layer.
+    pub fn action(&self, layer: u8, position: KeyPosition) -> KeyAction {
+        match decode(self.bytes, layer, position) {
+            Some(KeyAction::StoredMacro(offset)) => {
+                let bytes = &self.bytes[POOL_START + offset as usize..];
+                return KeyAction::EncodedMacro(EncodedMacro { bytes });
+            }
+            Some(action) => return action,
+            None => return KeyAction::DeadKey,
+        }
+    }
+}
+
+/// Checks the blob at the start of the bytes, see [KeymapBlob::new]. Returns its size.
+pub(crate) fn check(bytes: &[u8]) -> Option<usize> {
+    if bytes.len() < HEADER_SIZE
+        || bytes[..4] != MAGIC
+        || bytes[4] != VERSION
+        || bytes[5] != LAYER_COUNT
+        || bytes[6] as usize != POSITIONS
+    {
+        return None;
+    }
+    let bytes = bytes.get(..POOL_START + pool_size(bytes))?;
+    for layer in 0..LAYER_COUNT {
+        for position in KeyPosition::all() {
+            match decode(bytes, layer, position)? {
+                KeyAction::StoredMacro(offset)
+                    if !is_valid_macro(&bytes[POOL_START + offset as usize..]) =>
+                {
+                    return None;
+                }
+                _ => {}
+            }
+        }
+    }
+    return Some(bytes.len());
+}
+
+/// Decodes the action of the key on the layer from the blob, the macros coming back as a
+/// [KeyAction::StoredMacro] at their offset in the pool. Returns [None] past the last layer or
+/// for an action this firmware cannot carry out.
+pub(crate) fn decode(bytes: &[u8], layer: u8, position: KeyPosition) -> Option<KeyAction> {
+    let offset = action_offset(layer, position)?;
+    let pool_size = bytes.len().checked_sub(POOL_START)?;
+    return decode_action(bytes.get(offset..offset + ACTION_SIZE)?, pool_size);
+}
+
+/// Returns the offset of the action of the key on the layer in a blob, [None] past the last
+/// layer.
+pub(crate) fn action_offset(layer: u8, position: KeyPosition) -> Option<usize> {
+    if layer >= LAYER_COUNT {
+        return None;
+    }
+    return Some(HEADER_SIZE + (layer as usize * POSITIONS + position.index()) * ACTION_SIZE);
+}
+
+/// Returns the size of the macro pool of the blob starting the bytes, which have to hold a
+/// header.
+pub(crate) fn pool_size(bytes: &[u8]) -> usize {
+    return u16::from_le_bytes([bytes[8], bytes[9]]) as usize;
+}
+
+pub(crate) fn set_pool_size(bytes: &mut [u8], size: u16) {
+    bytes[8..HEADER_SIZE].copy_from_slice(&size.to_le_bytes());
+}
+
+/// Decodes an action from its [ACTION_SIZE] bytes, a macro having to start in the pool of the
+/// given size.
+pub(crate) fn decode_action(action: &[u8], pool_size: usize) -> Option<KeyAction> {
+    let (tag, low, high) = (action[0], action[1], action[2]);
+    let payload = u16::from_le_bytes([low, high]);
+    match tag {
+        DEAD_KEY => return Some(KeyAction::DeadKey),
+        HID_KEY => return Some(KeyAction::HidKey(KeyboardUsage::from(low))),
+        MACRO => {
+            if payload as usize >= pool_size {
+                return None;
+            }
+            return Some(KeyAction::StoredMacro(payload));
+        }
+        DYNAMIC_MACRO => match low {
+            0 => return Some(KeyAction::DynamicMacro(DynamicMacroAction::Record(high))),
+            1 => return Some(KeyAction::DynamicMacro(DynamicMacroAction::Stop)),
+            2 => return Some(KeyAction::DynamicMacro(DynamicMacroAction::Play(high))),
+            _ => return None,
+        },
+        CAPS_WORD => return Some(KeyAction::CapsWord),
+        ONE_SHOT_MOD => return Some(KeyAction::OneShotMod(Modifiers::from_bits(low))),
+        SET_UNICODE_INPUT => {
+            return Some(KeyAction::SetUnicodeInput(UnicodeInput::from_byte(low)?));
+        }
+        SET_HOST_LAYOUT => return Some(KeyAction::SetHostLayout(HostLayout::from_byte(low)?)),
+        SET_DEFAULT_LAYER => {
+            if low >= LAYER_COUNT {
+                return None;
+            }
+            return Some(KeyAction::SetDefaultLayer(low));
+        }
+        LEADER => return Some(KeyAction::Leader),
+        SWAP_HANDS => match low {
+            0 => return Some(KeyAction::SwapHands(SwapHandsMode::Momentary)),
+            1 => return Some(KeyAction::SwapHands(SwapHandsMode::Toggle)),
+            _ => return None,
+        },
+        BOARD_ACTION => return Some(KeyAction::BoardAction),
+        VIA_MACRO => {
+            if payload >= VIA_MACRO_COUNT as u16 {
+                return None;
+            }
+            return Some(KeyAction::ViaMacro(low));
+        }
+        UNICODE..=LAST_UNICODE => {
+            let code_point = ((tag - UNICODE) as u32) << 16 | payload as u32;
+            return Some(KeyAction::Unicode(char::from_u32(code_point)?));
+        }
+        _ => return None,
+    }
+}
+
+/// A macro of the macro pool of a [KeymapBlob], its steps are decoded as it is played.
+#[derive(Clone, Copy)]
+pub struct EncodedMacro {
+    /// The pool from the first step of the macro on.
+    bytes: &'static [u8],
+}
+
+impl EncodedMacro {
+    /// Returns the step at the byte offset in the macro along with the offset of the next one,
+    /// [None] past the last one. The first step is at offset 0.
+    pub fn step(&self, offset: usize) -> Option<(MacroStep<'static>, usize)> {
+        return macro_step_at(self.bytes, offset);
+    }
+
+    pub fn steps(&self) -> impl Iterator<Item = MacroStep<'static>> {
+        return macro_steps(self.bytes);
+    }
+}
+
+impl fmt::Debug for EncodedMacro {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return f.debug_list().entries(self.steps()).finish();
+    }
+}
+
+/// Returns the steps of the macro starting the bytes, up to its end.
+pub fn macro_steps(bytes: &[u8]) -> impl Iterator<Item = MacroStep<'_>> {
+    let mut bytes = bytes;
+    return iter::from_fn(move || {
+        let (step, size) = decode_step(bytes)?;
+        bytes = &bytes[size..];
+        return Some(step);
+    });
+}
+
+/// Decodes the step at the offset in the macro starting the bytes, see [EncodedMacro::step].
+pub fn macro_step_at(bytes: &[u8], offset: usize) -> Option<(MacroStep<'_>, usize)> {
+    let (step, size) = decode_step(bytes.get(offset..)?)?;
+    return Some((step, offset + size));
+}
+
+/// Returns true if every step of the macro starting the bytes decodes, up to its end.
+pub(crate) fn is_valid_macro(bytes: &[u8]) -> bool {
+    return bytes.get(macro_size(bytes)) == Some(&END);
+}
+
+/// Returns the size of the steps of the macro starting the bytes, up to its end or the first
+/// step that does not decode.
+pub(crate) fn macro_size(bytes: &[u8]) -> usize {
+    let mut offset = 0;
+    while let Some((_, size)) = decode_step(&bytes[offset..]) {
+        offset += size;
+    }
+    return offset;
+}
+
+/// Decodes the step at the start of the bytes, returning it along with its size. Returns
+/// [None] at the end of the macro.
+fn decode_step(bytes: &[u8]) -> Option<(MacroStep<'_>, usize)> {
+    match *bytes.first()? {
+        PRESS => return Some((MacroStep::Press(KeyboardUsage::from(*bytes.get(1)?)), 2)),
+        RELEASE => return Some((MacroStep::Release(KeyboardUsage::from(*bytes.get(1)?)), 2)),
+        TAP => return Some((MacroStep::Tap(KeyboardUsage::from(*bytes.get(1)?)), 2)),
+        DELAY => {
+            let delay_ms = u16::from_le_bytes([*bytes.get(1)?, *bytes.get(2)?]);
+            return Some((MacroStep::DelayMs(delay_ms), 3));
+        }
+        TEXT | UNICODE_TEXT => {
+            let size = *bytes.get(1)? as usize;
+            let text = str::from_utf8(bytes.get(2..2 + size)?).ok()?;
+            if bytes[0] == TEXT {
+                return Some((MacroStep::Text(text), 2 + size));
+            }
+            return Some((MacroStep::Unicode(text), 2 + size));
+        }
+        _ => return None,
+    }
+}
+
+/// Writes the blob of the keymap into `out`, see [KeymapBlob]. Returns the size of the blob,
+/// [None] if it does not fit, its macro pool is over 64 KiB or a text of a macro is longer than
+/// [MAX_TEXT_SIZE].
+pub fn encode(keymap: &Keymap, out: &mut [u8]) -> Option<usize> {
+    let header = out.get_mut(..HEADER_SIZE)?;
+    header[..4].copy_from_slice(&MAGIC);
+    header[4] = VERSION;
+    header[5] = LAYER_COUNT;
+    header[6] = POSITIONS as u8;
+    header[7] = 0;
+    let mut pool_end = POOL_START;
+    for layer in 0..LAYER_COUNT {
+        for position in KeyPosition::all() {
+            let action = keymap.action(layer, position);
+            let (tag, payload) = match action {
+                KeyAction::Macro(steps) => {
+                    let offset = pool_end - POOL_START;
+                    pool_end = encode_steps(steps.iter().copied(), out, pool_end)?;
+                    (MACRO, u16::try_from(offset).ok()?)
+                }
+                KeyAction::EncodedMacro(encoded) => {
+                    let offset = pool_end - POOL_START;
+                    pool_end = encode_steps(encoded.steps(), out, pool_end)?;
+                    (MACRO, u16::try_from(offset).ok()?)
+                }
+                _ => encode_action(action),
+            };
+            let offset = action_offset(layer, position)?;
+            let cell = out.get_mut(offset..offset + ACTION_SIZE)?;
+            cell[0] = tag;
+            cell[1..].copy_from_slice(&payload.to_le_bytes());
+        }
+    }
+    set_pool_size(out, u16::try_from(pool_end - POOL_START).ok()?);
+    return Some(pool_end);
+}
+
+/// Returns the [ACTION_SIZE] bytes of an action that is not a macro, the macros being left out
+/// as dead keys.
+pub(crate) fn encode_cell(action: KeyAction) -> [u8; ACTION_SIZE] {
+    let (tag, payload) = encode_action(action);
+    let [low, high] = payload.to_le_bytes();
+    return [tag, low, high];
+}
+
+/// Returns the tag and the payload of an action that is not a macro.
+fn encode_action(action: KeyAction) -> (u8, u16) {
+    match action {
+        KeyAction::HidKey(key) => return (HID_KEY, key as u16),
+        KeyAction::DynamicMacro(DynamicMacroAction::Record(slot)) => {
+            return (DYNAMIC_MACRO, u16::from_le_bytes([0, slot]));
+        }
+        KeyAction::DynamicMacro(DynamicMacroAction::Stop) => return (DYNAMIC_MACRO, 1),
+        KeyAction::DynamicMacro(DynamicMacroAction::Play(slot)) => {
+            return (DYNAMIC_MACRO, u16::from_le_bytes([2, slot]));
+        }
+        KeyAction::CapsWord => return (CAPS_WORD, 0),
+        KeyAction::OneShotMod(modifiers) => return (ONE_SHOT_MOD, modifiers.bits() as u16),
+        KeyAction::Unicode(character) => {
+            let code_point = character as u32;
+            return (UNICODE + (code_point >> 16) as u8, code_point as u16);
+        }
+        KeyAction::SetUnicodeInput(input) => return (SET_UNICODE_INPUT, input.to_byte() as u16),
+        KeyAction::SetHostLayout(layout) => return (SET_HOST_LAYOUT, layout.to_byte() as u16),
+        KeyAction::SetDefaultLayer(layer) => return (SET_DEFAULT_LAYER, layer as u16),
+        KeyAction::Leader => return (LEADER, 0),
+        KeyAction::SwapHands(SwapHandsMode::Momentary) => return (SWAP_HANDS, 0),
+        KeyAction::SwapHands(SwapHandsMode::Toggle) => return (SWAP_HANDS, 1),
+        KeyAction::BoardAction => return (BOARD_ACTION, 0),
+        KeyAction::ViaMacro(index) => return (VIA_MACRO, index as u16),
+        // stored macros are left out, their pool is not at hand
+        KeyAction::DeadKey
+        | KeyAction::Macro(_)
+        | KeyAction::EncodedMacro(_)
+        | KeyAction::StoredMacro(_) => return (DEAD_KEY, 0),
+    }
+}
+
+/// Writes the steps and the end of the macro at `offset`, returning the offset right after.
+fn encode_steps<'a>(
+    steps: impl Iterator<Item = MacroStep<'a>>,
+    out: &mut [u8],
+    offset: usize,
+) -> Option<usize> {
+    let mut offset = offset;
+    for step in steps {
+        offset = match step {
+            MacroStep::Press(key) => write(out, offset, &[PRESS, key as u8])?,
+            MacroStep::Release(key) => write(out, offset, &[RELEASE, key as u8])?,
+            MacroStep::Tap(key) => write(out, offset, &[TAP, key as u8])?,
+            MacroStep::DelayMs(delay_ms) => {
+                let [low, high] = delay_ms.to_le_bytes();
+                write(out, offset, &[DELAY, low, high])?
+            }
+            MacroStep::Text(text) => write_text(out, offset, TEXT, text)?,
+            MacroStep::Unicode(text) => write_text(out, offset, UNICODE_TEXT, text)?,
+        };
+    }
+    return write(out, offset, &[END]);
+}
+
+fn write_text(out: &mut [u8], offset: usize, tag: u8, text: &str) -> Option<usize> {
+    if text.len() > MAX_TEXT_SIZE {
+        return None;
+    }
+    let offset = write(out, offset, &[tag, text.len() as u8])?;
+    return write(out, offset, text.as_bytes());
+}
+
+/// Copies the bytes into `out` at `offset`, returning the offset right after them.
+fn write(out: &mut [u8], offset: usize, bytes: &[u8]) -> Option<usize> {
+    let end = offset + bytes.len();
+    out.get_mut(offset..end)?.copy_from_slice(bytes);
+    return Some(end);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        io_management::left_half_manager::LeftKeyLocation,
+        profiles_management::profiles::profile_1::profile_1::get_profile,
+    };
+
+    use super::*;
+
+    /// Encodes the keymap into bytes living as long as the blob reading them.
+    fn encode_to_static(keymap: &Keymap) -> &'static [u8] {
+        let mut out = vec![0; 4096];
+        let size = encode(keymap, &mut out).unwrap();
+        out.truncate(size);
+        return out.leak();
+    }
+
+    #[test]
+    fn reads_back_every_action_of_the_keymap() {
+        let keymap = get_profile().keymap;
+        let bytes = encode_to_static(&keymap);
+        let blob = KeymapBlob::new(bytes).unwrap();
+        assert_eq!(blob.bytes(), bytes);
+        // encoding the actions read back from the blob gives the blob again
+        assert_eq!(encode_to_static(&Keymap::Blob(blob)), bytes);
+
+        let launcher = KeyPosition::left(LeftKeyLocation::C5R2);
+        let (KeyAction::Macro(steps), KeyAction::EncodedMacro(encoded)) =
+            (keymap.action(1, launcher), blob.action(1, launcher))
+        else {
+            panic!("the launcher key does not play a macro");
+        };
+        assert!(encoded.steps().eq(steps.iter().copied()));
+        let mut offset = 0;
+        for step in steps {
+            let (decoded, next) = encoded.step(offset).unwrap();
+            assert_eq!(decoded, *step);
+            offset = next;
+        }
+        assert!(encoded.step(offset).is_none());
+    }
+
+    #[test]
+    fn reads_nothing_from_erased_or_damaged_bytes() {
+        assert!(KeymapBlob::new(&[0xFF; POOL_START]).is_none());
+        let bytes = encode_to_static(&get_profile().keymap);
+        assert!(KeymapBlob::new(&bytes[..bytes.len() - 1]).is_none());
+
+        let mut damaged = bytes.to_vec();
+        damaged[HEADER_SIZE] = 0xFF;
+        assert!(KeymapBlob::new(damaged.leak()).is_none());
+        // the last byte ends the last macro of the pool
+        let mut damaged = bytes.to_vec();
+        *damaged.last_mut().unwrap() = TEXT;
+        assert!(KeymapBlob::new(damaged.leak()).is_none());
+    }
+
+    #[test]
+    fn cannot_encode_into_a_short_buffer() {
+        let keymap = get_profile().keymap;
+        let mut out = [0; POOL_START];
+        assert_eq!(encode(&keymap, &mut out), None);
+    }
+}
@@ -0,0 +1,291 @@
+use crate::io_management::key_position::KeyPosition;
+
+use super::{
+    keyboard_profile::keyboard_profile::{KeyAction, Keymap},
+    keymap_blob::{self, KeymapBlob, ACTION_SIZE, POOL_START},
+};
+
+/// Number of bytes of the store, one erase sector of the flash of the firmware.
+pub const KEYMAP_STORE_SIZE: usize = 4096;
+/// Number of bytes of the macro pool of the store.
+pub const POOL_CAPACITY: usize = KEYMAP_STORE_SIZE - POOL_START;
+/// Number of bytes of the VIA macro buffer, see [KeyAction::ViaMacro].
+pub const VIA_MACRO_BUFFER_SIZE: usize = 1024;
+/// Offset of the VIA macro buffer in the macro pool, which it ends.
+pub const VIA_MACRO_BUFFER_START: usize = POOL_CAPACITY - VIA_MACRO_BUFFER_SIZE;
+
+/// The keymap as it is edited at runtime, e.g. over the configuration channel: the bytes of a
+/// [KeymapBlob], its macro pool taking up the rest of the store. The macros of its actions are
+/// played from the pool at their offset, see [KeyAction::StoredMacro].
+///
+/// The saved keymap is read in place, e.g. from flash, and copied to RAM when it is first
+/// edited. The store is empty until a keymap is loaded into it, the keys are looked up in the
+/// keymap of the profile meanwhile.
+pub struct KeymapStore {
+    /// Keymap read in place until it is edited.
+    saved: Option<KeymapBlob>,
+    bytes: [u8; KEYMAP_STORE_SIZE],
+    /// The keymap is held in `bytes`, it is the one edited.
+    copied: bool,
+    /// Set once the keymap is to be saved, until the firmware takes the request.
+    save_requested: bool,
+}
+
+impl Default for KeymapStore {
+    fn default() -> Self {
+        return KeymapStore::new();
+    }
+}
+
+impl KeymapStore {
+    pub const fn new() -> KeymapStore {
+        return KeymapStore {
+            saved: None,
+            bytes: [0; KEYMAP_STORE_SIZE],
+            copied: false,
+            save_requested: false,
+        };
+    }
+
+    pub fn is_loaded(&self) -> bool {
+        return self.keymap().is_some();
+    }
+
+    /// Loads the blob, e.g. the one saved to flash, to be read in place until it is edited.
+    /// Returns false, leaving the store as it was, if it does not fit in the store.
+    pub fn load(&mut self, blob: KeymapBlob) -> bool {
+        if blob.bytes().len() > KEYMAP_STORE_SIZE {
+            return false;
+        }
+        self.saved = Some(blob);
+        self.copied = false;
+        return true;
+    }
+
+    /// Loads the keymap, e.g. the one of the profile, dropping the changes made to the store.
+    /// Returns false, leaving the store empty, if the keymap does not fit.
+    pub fn reset(&mut self, keymap: &Keymap) -> bool {
+        self.saved = None;
+        match keymap_blob::encode(keymap, &mut self.bytes) {
+            Some(size) => self.fill_pool(size),
+            None => self.copied = false,
+        }
+        return self.copied;
+    }
+
+    /// Returns the blob held by the store, to be saved. Empty while nothing is loaded.
+    pub fn blob(&self) -> &[u8] {
+        return self.keymap().unwrap_or(&[]);
+    }
+
+    /// Returns true if the store holds a blob that loads back, the macros of the actions being
+    /// complete.
+    pub fn is_valid(&self) -> bool {
+        match self.keymap() {
+            Some(bytes) => return keymap_blob::check(bytes).is_some(),
+            None => return false,
+        }
+    }
+
+    /// Returns the action of the key on the layer, [None] while the store is empty.
+    pub fn action(&self, layer: u8, position: KeyPosition) -> Option<KeyAction> {
+        let bytes = self.keymap()?;
+        match keymap_blob::decode(bytes, layer, position) {
+            Some(action) => return Some(action),
+            None => return Some(KeyAction::DeadKey),
+        }
+    }
+
+    /// Returns the action of the key on the layer as it is encoded in the blob, [None] while
+    /// the store is empty or past the last layer.
+    pub fn encoded_action(&self, layer: u8, position: KeyPosition) -> Option<[u8; ACTION_SIZE]> {
+        let bytes = self.keymap()?;
+        let offset = keymap_blob::action_offset(layer, position)?;
+        let mut action = [0; ACTION_SIZE];
+        action.copy_from_slice(&bytes[offset..offset + ACTION_SIZE]);
+        return Some(action);
+    }
+
+    /// Replaces the action of the key on the layer with an encoded one. Returns false, leaving
+    /// the action as it was, if the store is empty or the firmware cannot carry the action out.
+    pub fn set_encoded_action(
+        &mut self,
+        layer: u8,
+        position: KeyPosition,
+        action: [u8; ACTION_SIZE],
+    ) -> bool {
+        if !self.edit() {
+            return false;
+        }
+        let offset = match keymap_blob::action_offset(layer, position) {
+            Some(offset) => offset,
+            None => return false,
+        };
+        match keymap_blob::decode_action(&action, POOL_CAPACITY) {
+            Some(KeyAction::StoredMacro(macro_offset)) => {
+                if !keymap_blob::is_valid_macro(&self.pool()[macro_offset as usize..]) {
+                    return false;
+                }
+            }
+            Some(_) => {}
+            None => return false,
+        }
+        self.bytes[offset..offset + ACTION_SIZE].copy_from_slice(&action);
+        return true;
+    }
+
+    /// Replaces the action of the key on the layer, see [KeymapStore::set_encoded_action]. The
+    /// actions playing the macros of a profile or a blob cannot be set, they end up dead keys.
+    pub fn set_action(&mut self, layer: u8, position: KeyPosition, action: KeyAction) -> bool {
+        return self.set_encoded_action(layer, position, keymap_blob::encode_cell(action));
+    }
+
+    /// Returns the macro pool, empty while nothing is loaded.
+    pub fn pool(&self) -> &[u8] {
+        match self.keymap() {
+            Some(bytes) => return &bytes[POOL_START..],
+            None => return &[],
+        }
+    }
+
+    /// Returns the VIA macro buffer, the end of the macro pool, empty while nothing is loaded.
+    pub fn via_macro_buffer(&self) -> &[u8] {
+        return self.pool().get(VIA_MACRO_BUFFER_START..).unwrap_or(&[]);
+    }
+
+    /// Asks for the keymap to be saved. Returns false, asking nothing, if it would not load
+    /// back, see [KeymapStore::is_valid].
+    pub fn request_save(&mut self) -> bool {
+        if !self.is_valid() {
+            return false;
+        }
+        self.save_requested = true;
+        return true;
+    }
+
+    /// Returns true if the keymap is to be saved since the last call, for it to be written to
+    /// flash. A keymap changed since the request so that it would not load back is not saved,
+    /// and neither is one read in place, which is saved already.
+    pub fn take_save_request(&mut self) -> bool {
+        let requested = self.save_requested;
+        self.save_requested = false;
+        return requested && self.copied && self.is_valid();
+    }
+
+    /// Writes the bytes into the macro pool at the offset. Returns false, leaving the pool as it
+    /// was, if the store is empty or they do not fit. The macros of the actions are not checked,
+    /// see [KeymapStore::is_valid].
+    pub fn write_pool(&mut self, offset: usize, bytes: &[u8]) -> bool {
+        if !self.edit() {
+            return false;
+        }
+        match self.bytes[POOL_START..].get_mut(offset..offset + bytes.len()) {
+            Some(pool) => pool.copy_from_slice(bytes),
+            None => return false,
+        }
+        return true;
+    }
+
+    /// Returns the bytes of the keymap, [None] while the store is empty.
+    fn keymap(&self) -> Option<&[u8]> {
+        if self.copied {
+            return Some(&self.bytes);
+        }
+        return self.saved.map(|blob| blob.bytes());
+    }
+
+    /// Copies the keymap read in place to RAM for it to be edited, if it is not there yet.
+    /// Returns false if the store is empty.
+    fn edit(&mut self) -> bool {
+        if self.copied {
+            return true;
+        }
+        match self.saved {
+            Some(blob) => {
+                let size = blob.bytes().len();
+                self.bytes[..size].copy_from_slice(blob.bytes());
+                self.fill_pool(size);
+                return true;
+            }
+            None => return false,
+        }
+    }
+
+    /// Makes the pool take up the store from its end on, the bytes past the blob of `size`
+    /// bytes ending empty macros.
+    fn fill_pool(&mut self, size: usize) {
+        self.bytes[size..].fill(0);
+        keymap_blob::set_pool_size(&mut self.bytes, POOL_CAPACITY as u16);
+        self.copied = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        io_management::left_half_manager::LeftKeyLocation,
+        profiles_management::profiles::profile_1::profile_1::get_profile,
+    };
+
+    use super::*;
+
+    #[test]
+    fn reads_back_the_saved_keymap_with_its_changes() {
+        let position = KeyPosition::left(LeftKeyLocation::C2R1);
+        let mut store = KeymapStore::new();
+        assert!(store.action(0, position).is_none());
+        assert!(!store.set_encoded_action(0, position, [0, 0, 0]));
+
+        assert!(store.reset(&get_profile().keymap));
+        // a macro tapping Z, then a key playing it
+        assert!(store.write_pool(POOL_CAPACITY - 3, &[0x03, 0x1D, 0x00]));
+        let offset = (POOL_CAPACITY as u16 - 3).to_le_bytes();
+        assert!(store.set_encoded_action(0, position, [0x02, offset[0], offset[1]]));
+        assert!(store.is_valid());
+
+        let mut loaded = KeymapStore::new();
+        let saved = KeymapBlob::new(store.blob().to_vec().leak()).unwrap();
+        assert!(loaded.load(saved));
+        assert_eq!(loaded.blob(), store.blob());
+        match loaded.action(0, position) {
+            Some(KeyAction::StoredMacro(offset)) => assert_eq!(offset as usize, POOL_CAPACITY - 3),
+            _ => panic!("the key does not play the stored macro"),
+        }
+    }
+
+    #[test]
+    fn reads_the_saved_keymap_in_place_until_it_is_edited() {
+        let position = KeyPosition::left(LeftKeyLocation::C2R1);
+        let mut store = KeymapStore::new();
+        assert!(store.reset(&get_profile().keymap));
+        let saved = KeymapBlob::new(store.blob().to_vec().leak()).unwrap();
+
+        let mut loaded = KeymapStore::new();
+        assert!(loaded.load(saved));
+        assert_eq!(loaded.blob().as_ptr(), saved.bytes().as_ptr());
+        assert!(loaded.request_save());
+        assert!(!loaded.take_save_request());
+
+        assert!(loaded.set_encoded_action(0, position, [0, 0, 0]));
+        assert_ne!(loaded.blob().as_ptr(), saved.bytes().as_ptr());
+        assert_eq!(saved.bytes(), store.blob());
+        assert_eq!(loaded.encoded_action(0, position), Some([0, 0, 0]));
+        assert!(loaded.request_save());
+        assert!(loaded.take_save_request());
+    }
+
+    #[test]
+    fn refuses_actions_the_firmware_cannot_carry_out() {
+        let position = KeyPosition::left(LeftKeyLocation::C2R1);
+        let mut store = KeymapStore::new();
+        assert!(store.reset(&get_profile().keymap));
+        let action = store.encoded_action(0, position);
+        assert!(!store.set_encoded_action(0, position, [0xFF, 0, 0]));
+        // a macro running past the end of the pool
+        assert!(store.write_pool(POOL_CAPACITY - 1, &[0x03]));
+        let offset = (POOL_CAPACITY as u16 - 1).to_le_bytes();
+        assert!(!store.set_encoded_action(0, position, [0x02, offset[0], offset[1]]));
+        assert_eq!(store.encoded_action(0, position), action);
+        assert!(!store.write_pool(POOL_CAPACITY, &[0]));
+    }
+}
@@ -0,0 +1,263 @@
+//! Drives a [KeyboardProfile] through the key processing of the firmware on a virtual clock, so
+//! that keymaps can be tested as a list of key presses and the reports they should send:
+//! ```ignore
+//! let mut keyboard = KeymapTester::new(get_profile());
+//! keyboard.run(&[Press(LeftKey(LT1)), Tap(LeftKey(C2R1)), Release(LeftKey(LT1))]);
+//! keyboard.expect_reports(&[&[KeyboardUsage::KeyboardQq], &[]]);
+//! ```
+use usbd_hid::descriptor::KeyboardUsage;
+
+use crate::{
+    hid_helper::keyboard_report::KeyboardReportHelper,
+    io_management::{
+        full_keyboard_manager::FullKeyboardManager,
+        key_position::{KeyPosition, POSITIONS},
+        left_half_manager::{LeftKeyLocation, LeftReadout},
+        right_half_manager::{RightKeyLocation, RightReadout},
+        rotary_encoder::{split_counts, EncoderLocation},
+        virtual_keyboard::VirtualKeyboard,
+    },
+    key_features::{auto_shift::AutoShiftConfig, leader::LeaderConfig, one_shot::OneShotConfig},
+    report_buffer::buffer::{KeyboardRingBuffer, QueuedReport},
+};
+
+use super::{
+    keyboard_profile::keyboard_profile::{
+        EncoderActionSet, KeyAction, KeyboardProfile, Keymap, Layer, UniversalKey,
+    },
+    layers::LAYER_COUNT,
+};
+
+/// Time a [Step::Tap] holds the key down for.
+pub const TAP_MS: u64 = 20;
+
+/// Something done to the keyboard.
+#[derive(Clone, Copy)]
+pub enum Step {
+    Press(UniversalKey),
+    Release(UniversalKey),
+    /// Presses the key and releases it [TAP_MS] later.
+    Tap(UniversalKey),
+    /// Lets the given number of milliseconds pass.
+    Wait(u64),
+    /// Turns the encoder by the given number of counts, negative counts being counter-clockwise.
+    Turn(EncoderLocation, i8),
+}
+
+/// A report sent to the host, along with the time the key change behind it has been seen.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct SentReport {
+    pub time_ms: u64,
+    pub report: KeyboardReportHelper,
+}
+
+/// Plays the readouts of a [KeyboardProfile] through a [VirtualKeyboard], which stands for the
+/// rest of the firmware. Time only moves forward through [Step::Tap] and [Step::Wait].
+pub struct KeymapTester {
+    profile: KeyboardProfile,
+    keyboard: VirtualKeyboard<'static>,
+    /// Readout bytes in [ row_1 , row_2 , row_3 , thumb_cluster ] order.
+    left_bytes: [u8; 4],
+    right_bytes: [u8; 4],
+    sent: Vec<SentReport>,
+}
+
+impl KeymapTester {
+    pub fn new(profile: KeyboardProfile) -> KeymapTester {
+        // the manager borrows the buffer for as long as the test runs
+        let (producer, consumer) = Box::leak(Box::new(KeyboardRingBuffer::new())).split();
+        return KeymapTester {
+            profile,
+            keyboard: VirtualKeyboard::new(FullKeyboardManager::new(producer), consumer),
+            left_bytes: [0; 4],
+            right_bytes: [0; 4],
+            sent: Vec::new(),
+        };
+    }
+
+    pub fn run(&mut self, steps: &[Step]) {
+        for step in steps {
+            match *step {
+                Step::Press(key) => self.set_key(key, true),
+                Step::Release(key) => self.set_key(key, false),
+                Step::Tap(key) => {
+                    self.set_key(key, true);
+                    self.wait(TAP_MS);
+                    self.set_key(key, false);
+                }
+                Step::Wait(ms) => self.wait(ms),
+                Step::Turn(location, counts) => {
+                    let (direction, counts) = split_counts(counts);
+                    self.change_keys(|manager, profile, now_us| {
+                        manager.process_encoder(location, direction, counts, profile, now_us)
+                    });
+                }
+            }
+        }
+    }
+
+    /// Plays the keys through the profile from now on, see
+    /// [FullKeyboardManager::switch_profile].
+    pub fn switch_profile(&mut self, profile: KeyboardProfile) {
+        self.profile = profile;
+        self.change_keys(|manager, _, now_us| manager.switch_profile(now_us));
+    }
+
+    /// Returns the time on the virtual clock.
+    pub fn now_ms(&self) -> u64 {
+        return self.keyboard.now_us() / 1000;
+    }
+
+    pub fn manager(&self) -> &FullKeyboardManager<'static> {
+        return self.keyboard.manager();
+    }
+
+    pub fn manager_mut(&mut self) -> &mut FullKeyboardManager<'static> {
+        return self.keyboard.manager_mut();
+    }
+
+    /// Returns the reports sent since the last call.
+    pub fn take_reports(&mut self) -> Vec<SentReport> {
+        return std::mem::take(&mut self.sent);
+    }
+
+    /// Checks the reports sent since the last call, each one given as the keys it holds in the
+    /// order they appear in the report.
+    #[track_caller]
+    pub fn expect_reports(&mut self, expected: &[&[KeyboardUsage]]) {
+        let sent: Vec<KeyboardReportHelper> =
+            self.take_reports().iter().map(|sent| sent.report).collect();
+        let expected: Vec<KeyboardReportHelper> =
+            expected.iter().map(|keys| report(keys)).collect();
+        assert_eq!(sent, expected);
+    }
+
+    fn set_key(&mut self, key: UniversalKey, pressed: bool) {
+        match key {
+            UniversalKey::LeftKey(location) => {
+                let (byte, mask) =
+                    key_bit(|bytes| LeftReadout::from_bytes(bytes).is_pressed(&location));
+                set_bit(&mut self.left_bytes[byte], mask, pressed);
+                let readout = LeftReadout::from_bytes(self.left_bytes);
+                self.change_keys(|manager, profile, now_us| {
+                    manager.update_left_readout(readout, profile, now_us)
+                });
+            }
+            UniversalKey::RightKey(location) => {
+                let (byte, mask) = key_bit(|bytes| right_readout(bytes).is_pressed(&location));
+                set_bit(&mut self.right_bytes[byte], mask, pressed);
+                let readout = right_readout(self.right_bytes);
+                self.change_keys(|manager, profile, now_us| {
+                    manager.update_right_readout(readout, profile, now_us)
+                });
+            }
+        }
+    }
+
+    /// Hands a key change over to the keyboard, see [VirtualKeyboard::change_keys].
+    fn change_keys(
+        &mut self,
+        change: impl FnOnce(&mut FullKeyboardManager<'static>, &KeyboardProfile, u64),
+    ) {
+        let (profile, sent) = (&self.profile, &mut self.sent);
+        self.keyboard.change_keys(
+            profile,
+            |manager, now_us| change(manager, profile, now_us),
+            &mut |queued| sent.push(SentReport::from(queued)),
+        );
+    }
+
+    /// Lets the time pass, waking up the macro runner and the timed modes when they ask for it.
+    fn wait(&mut self, ms: u64) {
+        let until_us = self.keyboard.now_us() + ms * 1000;
+        let sent = &mut self.sent;
+        self.keyboard
+            .run_until(until_us, &self.profile, &mut |queued| {
+                sent.push(SentReport::from(queued))
+            });
+    }
+}
+
+impl From<QueuedReport> for SentReport {
+    fn from(queued: QueuedReport) -> SentReport {
+        return SentReport {
+            time_ms: queued.timestamp_us / 1000,
+            report: queued.report,
+        };
+    }
+}
+
+/// Returns a keymap with the actions on the base layer and dead keys everywhere else, for a
+/// [test_profile].
+pub const fn base_layer_keymap(
+    bindings: &[(KeyPosition, KeyAction)],
+) -> [Layer; LAYER_COUNT as usize] {
+    let mut keymap = [[KeyAction::DeadKey; POSITIONS]; LAYER_COUNT as usize];
+    let mut index = 0;
+    while index < bindings.len() {
+        keymap[0][bindings[index].0.index()] = bindings[index].1;
+        index += 1;
+    }
+    return keymap;
+}
+
+/// A profile made of the keymap alone, for tests of the key processing that do not depend on a
+/// real profile: LT1 and RT1 are the layer keys, the encoders do nothing, auto-shift is off and
+/// there are no key overrides, leader sequences or conditional layers.
+pub fn test_profile(keymap: &'static [Layer; LAYER_COUNT as usize]) -> KeyboardProfile {
+    const NO_ENCODER: EncoderActionSet = EncoderActionSet {
+        counts_per_step: 1,
+        clockwise: [KeyAction::DeadKey; LAYER_COUNT as usize],
+        counter_clockwise: [KeyAction::DeadKey; LAYER_COUNT as usize],
+    };
+    return KeyboardProfile {
+        layer_key_1: KeyPosition::left(LeftKeyLocation::LT1),
+        layer_key_2: KeyPosition::right(RightKeyLocation::RT1),
+        keymap: Keymap::Layers(keymap),
+        left_encoder: NO_ENCODER,
+        right_encoder: NO_ENCODER,
+        auto_shift: AutoShiftConfig::DISABLED,
+        key_overrides: &[],
+        one_shot: OneShotConfig {
+            tap_timeout_ms: 200,
+            idle_timeout_ms: 0,
+        },
+        leader: LeaderConfig::DISABLED,
+        conditional_layers: &[],
+    };
+}
+
+/// Builds the report holding the keys, in the given order.
+pub fn report(keys: &[KeyboardUsage]) -> KeyboardReportHelper {
+    let mut report = KeyboardReportHelper::new();
+    for key in keys {
+        report.add_keycode(*key);
+    }
+    return report;
+}
+
+/// Returns the byte and mask of the single readout bit `is_pressed` tells the key from.
+fn key_bit(is_pressed: impl Fn([u8; 4]) -> bool) -> (usize, u8) {
+    for byte in 0..4 {
+        for bit in 0..8 {
+            let mut bytes = [0; 4];
+            bytes[byte] = 1 << bit;
+            if is_pressed(bytes) {
+                return (byte, 1 << bit);
+            }
+        }
+    }
+    panic!("the key is not in the readout");
+}
+
+fn set_bit(byte: &mut u8, mask: u8, set: bool) {
+    if set {
+        *byte |= mask;
+    } else {
+        *byte &= !mask;
+    }
+}
+
+fn right_readout(bytes: [u8; 4]) -> RightReadout {
+    return RightReadout::new(bytes[0], bytes[1], bytes[2], bytes[3]);
+}
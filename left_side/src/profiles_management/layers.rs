@@ -0,0 +1,99 @@
+/// Number of layers a key has an action on, layer 0 being the base layer.
+pub const LAYER_COUNT: u8 = 4;
+
+/// A layer turned on by other layers being on at the same time, e.g. the tri-layer: layer 3
+/// while layers 1 and 2 are both on.
+#[derive(Clone, Copy)]
+pub struct ConditionalLayer {
+    /// Layers that all have to be on.
+    pub if_active: &'static [u8],
+    pub then: u8,
+}
+
+/// Returns true if the rules only name existing layers. Meant to be checked at compile time:
+/// ```ignore
+/// const _: () = assert!(layers::is_valid(CONDITIONAL_LAYERS));
+/// ```
+pub const fn is_valid(rules: &[ConditionalLayer]) -> bool {
+    let mut index = 0;
+    while index < rules.len() {
+        let rule = rules[index];
+        if rule.then >= LAYER_COUNT || rule.if_active.is_empty() {
+            return false;
+        }
+        let mut layer = 0;
+        while layer < rule.if_active.len() {
+            if rule.if_active[layer] >= LAYER_COUNT {
+                return false;
+            }
+            layer += 1;
+        }
+        index += 1;
+    }
+    return true;
+}
+
+/// Returns the layer the keys are looked up on: the highest of the layers turned on by the
+/// layer keys, `active` having a bit per layer, and by the rules, checked in order so that a
+/// rule sees the layers the earlier ones turned on. The default layer stands in for the base
+/// layer while no layer is on.
+pub fn resolve_layer(active: u8, rules: &[ConditionalLayer], default_layer: u8) -> u8 {
+    let mut active = active;
+    for rule in rules {
+        if rule
+            .if_active
+            .iter()
+            .all(|layer| active & (1 << layer) != 0)
+        {
+            active |= 1 << rule.then;
+        }
+    }
+    if active == 0 {
+        return default_layer;
+    }
+    return 7 - active.leading_zeros() as u8;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRI_LAYER: &[ConditionalLayer] = &[ConditionalLayer {
+        if_active: &[1, 2],
+        then: 3,
+    }];
+
+    #[test]
+    fn turns_on_the_highest_layer() {
+        assert_eq!(resolve_layer(0b0000, TRI_LAYER, 0), 0);
+        assert_eq!(resolve_layer(0b0010, TRI_LAYER, 0), 1);
+        assert_eq!(resolve_layer(0b0100, TRI_LAYER, 0), 2);
+        assert_eq!(resolve_layer(0b0110, TRI_LAYER, 0), 3);
+    }
+
+    #[test]
+    fn chains_the_rules_in_order() {
+        const CHAINED: &[ConditionalLayer] = &[
+            ConditionalLayer {
+                if_active: &[1],
+                then: 2,
+            },
+            ConditionalLayer {
+                if_active: &[2],
+                then: 3,
+            },
+        ];
+        assert_eq!(resolve_layer(0b0010, CHAINED, 0), 3);
+        assert!(is_valid(CHAINED));
+        assert!(!is_valid(&[ConditionalLayer {
+            if_active: &[1],
+            then: LAYER_COUNT,
+        }]));
+    }
+
+    #[test]
+    fn falls_back_to_the_default_layer() {
+        assert_eq!(resolve_layer(0b0000, TRI_LAYER, 2), 2);
+        assert_eq!(resolve_layer(0b0010, TRI_LAYER, 2), 1);
+    }
+}
@@ -1,2 +1,7 @@
 pub mod keyboard_profile;
+pub mod keymap_blob;
+pub mod keymap_store;
+#[cfg(test)]
+pub mod keymap_tester;
+pub mod layers;
 pub mod profiles;
@@ -1,358 +1,920 @@
 pub mod profile_1 {
 
-    use heapless::Vec;
     use usbd_hid::descriptor::KeyboardUsage;
 
-    // LeftCtrl    = 0b00000001,
-    // LeftShift   = 0b00000010,
-    // LeftAlt     = 0b00000100,
-    // LeftGui     = 0b00001000,
-    // RightCtrl   = 0b00010000,
-    // RightShift  = 0b00100000,
-    // RightAlt    = 0b01000000,
-    // RightGui    = 0x10000000,
-
     use crate::{
-        hid_helper::keyboard_report::KeyboardReportHelper,
-        io_management::{left_half_manager::LeftKeyLocation, right_half_manager::RightKeyLocation},
-        profiles_management::keyboard_profile::keyboard_profile::{
-            KeyAction, KeyActionSet, KeyboardProfile, UniversalKey,
+        hid_helper::{host_layout::HostLayout, modifiers::Modifiers, unicode_input::UnicodeInput},
+        io_management::{
+            key_position::KeyPosition, left_half_manager::LeftKeyLocation,
+            right_half_manager::RightKeyLocation,
+        },
+        key_features::{
+            auto_shift::AutoShiftConfig,
+            key_override::KeyOverride,
+            leader::{self, LeaderAction, LeaderConfig, LeaderNext, LeaderNode},
+            one_shot::OneShotConfig,
+            swap_hands::SwapHandsMode,
+        },
+        macros::{macro_recorder::DynamicMacroAction, macro_step::MacroStep},
+        profiles_management::{
+            keyboard_profile::keyboard_profile::{
+                EncoderActionSet, KeyAction, KeyboardProfile, Keymap, Layer,
+            },
+            layers::{self, ConditionalLayer},
         },
     };
 
+    /// Typed after the leader key: `g s` and `g c` for git, `m` to play back the first recorded
+    /// macro.
+    const LEADER_SEQUENCES: &[LeaderNode] = &[
+        LeaderNode {
+            key: KeyboardUsage::KeyboardGg,
+            next: LeaderNext::Keys(&[
+                LeaderNode {
+                    key: KeyboardUsage::KeyboardSs,
+                    next: LeaderNext::Action(LeaderAction::Macro(&[MacroStep::Text(
+                        "git status\n",
+                    )])),
+                },
+                LeaderNode {
+                    key: KeyboardUsage::KeyboardCc,
+                    next: LeaderNext::Action(LeaderAction::Macro(&[MacroStep::Text(
+                        "git commit ",
+                    )])),
+                },
+            ]),
+        },
+        LeaderNode {
+            key: KeyboardUsage::KeyboardMm,
+            next: LeaderNext::Action(LeaderAction::DynamicMacro(DynamicMacroAction::Play(0))),
+        },
+    ];
+    const _: () = assert!(leader::is_valid(LEADER_SEQUENCES));
+
+    /// The tri-layer: both layer keys held turn layer 3 on.
+    const CONDITIONAL_LAYERS: &[ConditionalLayer] = &[ConditionalLayer {
+        if_active: &[1, 2],
+        then: 3,
+    }];
+    const _: () = assert!(layers::is_valid(CONDITIONAL_LAYERS));
+
+    /// The base layer.
+    const BASE_LAYER: Layer = [
+        // row 1
+        KeyAction::HidKey(KeyboardUsage::KeyboardTab),
+        KeyAction::HidKey(KeyboardUsage::KeyboardQq),
+        KeyAction::HidKey(KeyboardUsage::KeyboardWw),
+        KeyAction::HidKey(KeyboardUsage::KeyboardEe),
+        KeyAction::HidKey(KeyboardUsage::KeyboardRr),
+        KeyAction::HidKey(KeyboardUsage::KeyboardTt),
+        KeyAction::HidKey(KeyboardUsage::KeyboardYy),
+        KeyAction::HidKey(KeyboardUsage::KeyboardUu),
+        KeyAction::HidKey(KeyboardUsage::KeyboardIi),
+        KeyAction::HidKey(KeyboardUsage::KeyboardOo),
+        KeyAction::HidKey(KeyboardUsage::KeyboardPp),
+        KeyAction::HidKey(KeyboardUsage::KeyboardBackslashBar),
+        // row 2
+        KeyAction::HidKey(KeyboardUsage::KeyboardLeftControl),
+        KeyAction::HidKey(KeyboardUsage::KeyboardAa),
+        KeyAction::HidKey(KeyboardUsage::KeyboardSs),
+        KeyAction::HidKey(KeyboardUsage::KeyboardDd),
+        KeyAction::HidKey(KeyboardUsage::KeyboardFf),
+        KeyAction::HidKey(KeyboardUsage::KeyboardGg),
+        KeyAction::HidKey(KeyboardUsage::KeyboardHh),
+        KeyAction::HidKey(KeyboardUsage::KeyboardJj),
+        KeyAction::HidKey(KeyboardUsage::KeyboardKk),
+        KeyAction::HidKey(KeyboardUsage::KeyboardLl),
+        KeyAction::HidKey(KeyboardUsage::KeyboardSemiColon),
+        KeyAction::HidKey(KeyboardUsage::KeyboardEnter),
+        // row 3
+        KeyAction::HidKey(KeyboardUsage::KeyboardLeftShift),
+        KeyAction::HidKey(KeyboardUsage::KeyboardZz),
+        KeyAction::HidKey(KeyboardUsage::KeyboardXx),
+        KeyAction::HidKey(KeyboardUsage::KeyboardCc),
+        KeyAction::HidKey(KeyboardUsage::KeyboardVv),
+        KeyAction::HidKey(KeyboardUsage::KeyboardBb),
+        KeyAction::HidKey(KeyboardUsage::KeyboardNn),
+        KeyAction::HidKey(KeyboardUsage::KeyboardMm),
+        KeyAction::HidKey(KeyboardUsage::KeyboardCommaLess),
+        KeyAction::HidKey(KeyboardUsage::KeyboardPeriodGreater),
+        KeyAction::HidKey(KeyboardUsage::KeyboardSlashQuestion),
+        KeyAction::HidKey(KeyboardUsage::KeyboardRightShift),
+        // left thumb keys
+        KeyAction::DeadKey,
+        KeyAction::HidKey(KeyboardUsage::KeyboardLeftGUI),
+        KeyAction::HidKey(KeyboardUsage::KeyboardLeftAlt),
+        // right thumb keys
+        KeyAction::DeadKey,
+        KeyAction::HidKey(KeyboardUsage::KeyboardBackspace),
+        KeyAction::HidKey(KeyboardUsage::KeyboardSpacebar),
+    ];
+
+    /// Layer 1, held on the first layer key.
+    const LAYER_ONE: Layer = [
+        // row 1
+        KeyAction::HidKey(KeyboardUsage::KeyboardEscape),
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        // row 2
+        KeyAction::OneShotMod(Modifiers::LEFT_CTRL),
+        KeyAction::OneShotMod(Modifiers::LEFT_SHIFT),
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        KeyAction::Macro(&[
+            MacroStep::Tap(KeyboardUsage::KeyboardLeftGUI),
+            // giving the launcher time to open
+            MacroStep::DelayMs(200),
+            MacroStep::Text("firefox\n"),
+        ]),
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        // row 3
+        KeyAction::CapsWord,
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        KeyAction::SwapHands(SwapHandsMode::Toggle),
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        // left thumb keys
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        // right thumb keys
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        KeyAction::Leader,
+    ];
+
+    /// Layer 2, held on the second layer key.
+    const LAYER_TWO: Layer = [
+        // row 1
+        KeyAction::DeadKey,
+        KeyAction::DynamicMacro(DynamicMacroAction::Record(0)),
+        KeyAction::DynamicMacro(DynamicMacroAction::Record(1)),
+        KeyAction::DynamicMacro(DynamicMacroAction::Stop),
+        KeyAction::Unicode('€'),
+        KeyAction::DeadKey,
+        KeyAction::Macro(&[MacroStep::Unicode("¯\\_(ツ)_/¯")]),
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        // row 2
+        KeyAction::DeadKey,
+        KeyAction::DynamicMacro(DynamicMacroAction::Play(0)),
+        KeyAction::DynamicMacro(DynamicMacroAction::Play(1)),
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        // row 3
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        // left thumb keys
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        // right thumb keys
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+    ];
+
+    /// Layer 3, the tri-layer turned on by both layer keys.
+    const LAYER_THREE: Layer = [
+        // row 1
+        KeyAction::SetUnicodeInput(UnicodeInput::Linux),
+        KeyAction::SetUnicodeInput(UnicodeInput::WinCompose),
+        KeyAction::SetUnicodeInput(UnicodeInput::WindowsAltCodes),
+        KeyAction::SetUnicodeInput(UnicodeInput::MacOs),
+        KeyAction::SetDefaultLayer(0),
+        KeyAction::SetDefaultLayer(1),
+        KeyAction::SetHostLayout(HostLayout::Us),
+        KeyAction::SetHostLayout(HostLayout::Uk),
+        KeyAction::SetHostLayout(HostLayout::German),
+        KeyAction::SetHostLayout(HostLayout::French),
+        KeyAction::SetHostLayout(HostLayout::Nordic),
+        KeyAction::SetHostLayout(HostLayout::Dvorak),
+        // row 2
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        // row 3
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        // left thumb keys
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        // right thumb keys
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+        KeyAction::DeadKey,
+    ];
+
     pub fn get_profile() -> KeyboardProfile {
         return KeyboardProfile {
-            layer_key_1: UniversalKey::LeftKey(LeftKeyLocation::LT1),
-            layer_key_2: UniversalKey::RightKey(RightKeyLocation::RT1),
-            c1_r1: KeyActionSet {
-                base_action: KeyAction::HidKey(KeyboardUsage::KeyboardTab),
-                layer_one_action: KeyAction::DeadKey,
-                layer_two_action: KeyAction::DeadKey,
-                layer_one_and_two_action: KeyAction::DeadKey,
-            },
-            c2_r1: KeyActionSet {
-                base_action: KeyAction::HidKey(KeyboardUsage::KeyboardQq),
-                layer_one_action: KeyAction::DeadKey,
-                layer_two_action: KeyAction::DeadKey,
-                layer_one_and_two_action: KeyAction::DeadKey,
-            },
-            c3_r1: KeyActionSet {
-                base_action: KeyAction::HidKey(KeyboardUsage::KeyboardWw),
-                layer_one_action: KeyAction::DeadKey,
-                layer_two_action: KeyAction::DeadKey,
-                layer_one_and_two_action: KeyAction::DeadKey,
-            },
-            c4_r1: KeyActionSet {
-                base_action: KeyAction::HidKey(KeyboardUsage::KeyboardEe),
-                layer_one_action: KeyAction::DeadKey,
-                layer_two_action: KeyAction::DeadKey,
-                layer_one_and_two_action: KeyAction::DeadKey,
-            },
-            c5_r1: KeyActionSet {
-                base_action: KeyAction::HidKey(KeyboardUsage::KeyboardRr),
-                layer_one_action: KeyAction::DeadKey,
-                layer_two_action: KeyAction::DeadKey,
-                layer_one_and_two_action: KeyAction::DeadKey,
-            },
-            c6_r1: KeyActionSet {
-                base_action: KeyAction::HidKey(KeyboardUsage::KeyboardTt),
-                layer_one_action: KeyAction::DeadKey,
-                layer_two_action: KeyAction::DeadKey,
-                layer_one_and_two_action: KeyAction::DeadKey,
-            },
-            c7_r1: KeyActionSet {
-                base_action: KeyAction::HidKey(KeyboardUsage::KeyboardYy),
-                layer_one_action: KeyAction::DeadKey,
-                layer_two_action: KeyAction::DeadKey,
-                layer_one_and_two_action: KeyAction::DeadKey,
-            },
-            c8_r1: KeyActionSet {
-                base_action: KeyAction::HidKey(KeyboardUsage::KeyboardUu),
-                layer_one_action: KeyAction::DeadKey,
-                layer_two_action: KeyAction::DeadKey,
-                layer_one_and_two_action: KeyAction::DeadKey,
-            },
-            c9_r1: KeyActionSet {
-                base_action: KeyAction::HidKey(KeyboardUsage::KeyboardIi),
-                layer_one_action: KeyAction::DeadKey,
-                layer_two_action: KeyAction::DeadKey,
-                layer_one_and_two_action: KeyAction::DeadKey,
-            },
-            c10_r1: KeyActionSet {
-                base_action: KeyAction::HidKey(KeyboardUsage::KeyboardOo),
-                layer_one_action: KeyAction::DeadKey,
-                layer_two_action: KeyAction::DeadKey,
-                layer_one_and_two_action: KeyAction::DeadKey,
-            },
-            c11_r1: KeyActionSet {
-                base_action: KeyAction::HidKey(KeyboardUsage::KeyboardPp),
-                layer_one_action: KeyAction::DeadKey,
-                layer_two_action: KeyAction::DeadKey,
-                layer_one_and_two_action: KeyAction::DeadKey,
-            },
-            c12_r1: KeyActionSet {
-                base_action: KeyAction::HidKey(KeyboardUsage::KeyboardBackslashBar),
-                layer_one_action: KeyAction::DeadKey,
-                layer_two_action: KeyAction::DeadKey,
-                layer_one_and_two_action: KeyAction::DeadKey,
-            },
-            c1_r2: KeyActionSet {
-                base_action: KeyAction::HidKey(KeyboardUsage::KeyboardLeftControl),
-                layer_one_action: KeyAction::DeadKey,
-                layer_two_action: KeyAction::DeadKey,
-                layer_one_and_two_action: KeyAction::DeadKey,
-            },
-            c2_r2: KeyActionSet {
-                base_action: KeyAction::HidKey(KeyboardUsage::KeyboardAa),
-                layer_one_action: KeyAction::DeadKey,
-                layer_two_action: KeyAction::DeadKey,
-                layer_one_and_two_action: KeyAction::DeadKey,
-            },
-            c3_r2: KeyActionSet {
-                base_action: KeyAction::HidKey(KeyboardUsage::KeyboardSs),
-                layer_one_action: KeyAction::DeadKey,
-                layer_two_action: KeyAction::DeadKey,
-                layer_one_and_two_action: KeyAction::DeadKey,
-            },
-            c4_r2: KeyActionSet {
-                base_action: KeyAction::HidKey(KeyboardUsage::KeyboardDd),
-                layer_one_action: KeyAction::DeadKey,
-                layer_two_action: KeyAction::DeadKey,
-                layer_one_and_two_action: KeyAction::DeadKey,
-            },
-            c5_r2: KeyActionSet {
-                base_action: KeyAction::HidKey(KeyboardUsage::KeyboardFf),
-                layer_one_action: KeyAction::HidReport(
-                    Vec::from_slice(&[
-                        KeyboardReportHelper::from_values(0b00001000, 0, 0, 0, 0, 0, 0),
-                        KeyboardReportHelper::from_values(
-                            0,
-                            KeyboardUsage::KeyboardFf as u8,
-                            0,
-                            0,
-                            0,
-                            0,
-                            0,
-                        ),
-                        KeyboardReportHelper::from_values(
-                            0,
-                            KeyboardUsage::KeyboardIi as u8,
-                            0,
-                            0,
-                            0,
-                            0,
-                            0,
-                        ),
-                        KeyboardReportHelper::from_values(
-                            0,
-                            KeyboardUsage::KeyboardRr as u8,
-                            0,
-                            0,
-                            0,
-                            0,
-                            0,
-                        ),
-                        KeyboardReportHelper::from_values(
-                            0,
-                            KeyboardUsage::KeyboardEe as u8,
-                            0,
-                            0,
-                            0,
-                            0,
-                            0,
-                        ),
-                        KeyboardReportHelper::from_values(
-                            0,
-                            KeyboardUsage::KeyboardFf as u8,
-                            0,
-                            0,
-                            0,
-                            0,
-                            0,
-                        ),
-                        KeyboardReportHelper::from_values(
-                            0,
-                            KeyboardUsage::KeyboardOo as u8,
-                            0,
-                            0,
-                            0,
-                            0,
-                            0,
-                        ),
-                        KeyboardReportHelper::from_values(
-                            0,
-                            KeyboardUsage::KeyboardXx as u8,
-                            0,
-                            0,
-                            0,
-                            0,
-                            0,
-                        ),
-                        KeyboardReportHelper::from_values(
-                            0,
-                            KeyboardUsage::KeyboardEnter as u8,
-                            0,
-                            0,
-                            0,
-                            0,
-                            0,
-                        ),
-                    ])
-                    .unwrap(),
-                ),
-                layer_two_action: KeyAction::DeadKey,
-                layer_one_and_two_action: KeyAction::DeadKey,
-            },
-            c6_r2: KeyActionSet {
-                base_action: KeyAction::HidKey(KeyboardUsage::KeyboardGg),
-                layer_one_action: KeyAction::DeadKey,
-                layer_two_action: KeyAction::DeadKey,
-                layer_one_and_two_action: KeyAction::DeadKey,
-            },
-            c7_r2: KeyActionSet {
-                base_action: KeyAction::HidKey(KeyboardUsage::KeyboardHh),
-                layer_one_action: KeyAction::DeadKey,
-                layer_two_action: KeyAction::DeadKey,
-                layer_one_and_two_action: KeyAction::DeadKey,
-            },
-            c8_r2: KeyActionSet {
-                base_action: KeyAction::HidKey(KeyboardUsage::KeyboardJj),
-                layer_one_action: KeyAction::DeadKey,
-                layer_two_action: KeyAction::DeadKey,
-                layer_one_and_two_action: KeyAction::DeadKey,
-            },
-            c9_r2: KeyActionSet {
-                base_action: KeyAction::HidKey(KeyboardUsage::KeyboardKk),
-                layer_one_action: KeyAction::DeadKey,
-                layer_two_action: KeyAction::DeadKey,
-                layer_one_and_two_action: KeyAction::DeadKey,
-            },
-            c10_r2: KeyActionSet {
-                base_action: KeyAction::HidKey(KeyboardUsage::KeyboardLl),
-                layer_one_action: KeyAction::DeadKey,
-                layer_two_action: KeyAction::DeadKey,
-                layer_one_and_two_action: KeyAction::DeadKey,
-            },
-            c11_r2: KeyActionSet {
-                base_action: KeyAction::HidKey(KeyboardUsage::KeyboardSemiColon),
-                layer_one_action: KeyAction::DeadKey,
-                layer_two_action: KeyAction::DeadKey,
-                layer_one_and_two_action: KeyAction::DeadKey,
-            },
-            c12_r2: KeyActionSet {
-                base_action: KeyAction::HidKey(KeyboardUsage::KeyboardEnter),
-                layer_one_action: KeyAction::DeadKey,
-                layer_two_action: KeyAction::DeadKey,
-                layer_one_and_two_action: KeyAction::DeadKey,
-            },
-            c1_r3: KeyActionSet {
-                base_action: KeyAction::HidKey(KeyboardUsage::KeyboardLeftShift),
-                layer_one_action: KeyAction::DeadKey,
-                layer_two_action: KeyAction::DeadKey,
-                layer_one_and_two_action: KeyAction::DeadKey,
-            },
-            c2_r3: KeyActionSet {
-                base_action: KeyAction::HidKey(KeyboardUsage::KeyboardZz),
-                layer_one_action: KeyAction::DeadKey,
-                layer_two_action: KeyAction::DeadKey,
-                layer_one_and_two_action: KeyAction::DeadKey,
-            },
-            c3_r3: KeyActionSet {
-                base_action: KeyAction::HidKey(KeyboardUsage::KeyboardXx),
-                layer_one_action: KeyAction::DeadKey,
-                layer_two_action: KeyAction::DeadKey,
-                layer_one_and_two_action: KeyAction::DeadKey,
-            },
-            c4_r3: KeyActionSet {
-                base_action: KeyAction::HidKey(KeyboardUsage::KeyboardCc),
-                layer_one_action: KeyAction::DeadKey,
-                layer_two_action: KeyAction::DeadKey,
-                layer_one_and_two_action: KeyAction::DeadKey,
-            },
-            c5_r3: KeyActionSet {
-                base_action: KeyAction::HidKey(KeyboardUsage::KeyboardVv),
-                layer_one_action: KeyAction::DeadKey,
-                layer_two_action: KeyAction::DeadKey,
-                layer_one_and_two_action: KeyAction::DeadKey,
-            },
-            c6_r3: KeyActionSet {
-                base_action: KeyAction::HidKey(KeyboardUsage::KeyboardBb),
-                layer_one_action: KeyAction::DeadKey,
-                layer_two_action: KeyAction::DeadKey,
-                layer_one_and_two_action: KeyAction::DeadKey,
-            },
-            c7_r3: KeyActionSet {
-                base_action: KeyAction::HidKey(KeyboardUsage::KeyboardNn),
-                layer_one_action: KeyAction::DeadKey,
-                layer_two_action: KeyAction::DeadKey,
-                layer_one_and_two_action: KeyAction::DeadKey,
-            },
-            c8_r3: KeyActionSet {
-                base_action: KeyAction::HidKey(KeyboardUsage::KeyboardMm),
-                layer_one_action: KeyAction::DeadKey,
-                layer_two_action: KeyAction::DeadKey,
-                layer_one_and_two_action: KeyAction::DeadKey,
-            },
-            c9_r3: KeyActionSet {
-                base_action: KeyAction::HidKey(KeyboardUsage::KeyboardCommaLess),
-                layer_one_action: KeyAction::DeadKey,
-                layer_two_action: KeyAction::DeadKey,
-                layer_one_and_two_action: KeyAction::DeadKey,
-            },
-            c10_r3: KeyActionSet {
-                base_action: KeyAction::HidKey(KeyboardUsage::KeyboardPeriodGreater),
-                layer_one_action: KeyAction::DeadKey,
-                layer_two_action: KeyAction::DeadKey,
-                layer_one_and_two_action: KeyAction::DeadKey,
-            },
-            c11_r3: KeyActionSet {
-                base_action: KeyAction::HidKey(KeyboardUsage::KeyboardSlashQuestion),
-                layer_one_action: KeyAction::DeadKey,
-                layer_two_action: KeyAction::DeadKey,
-                layer_one_and_two_action: KeyAction::DeadKey,
-            },
-            c12_r3: KeyActionSet {
-                base_action: KeyAction::HidKey(KeyboardUsage::KeyboardRightShift),
-                layer_one_action: KeyAction::DeadKey,
-                layer_two_action: KeyAction::DeadKey,
-                layer_one_and_two_action: KeyAction::DeadKey,
-            },
-            lt_1: KeyActionSet {
-                base_action: KeyAction::DeadKey,
-                layer_one_action: KeyAction::DeadKey,
-                layer_two_action: KeyAction::DeadKey,
-                layer_one_and_two_action: KeyAction::DeadKey,
-            },
-            lt_2: KeyActionSet {
-                base_action: KeyAction::HidKey(KeyboardUsage::KeyboardLeftGUI),
-                layer_one_action: KeyAction::DeadKey,
-                layer_two_action: KeyAction::DeadKey,
-                layer_one_and_two_action: KeyAction::DeadKey,
-            },
-            lt_3: KeyActionSet {
-                base_action: KeyAction::HidKey(KeyboardUsage::KeyboardLeftAlt),
-                layer_one_action: KeyAction::DeadKey,
-                layer_two_action: KeyAction::DeadKey,
-                layer_one_and_two_action: KeyAction::DeadKey,
-            },
-            rt_1: KeyActionSet {
-                base_action: KeyAction::DeadKey,
-                layer_one_action: KeyAction::DeadKey,
-                layer_two_action: KeyAction::DeadKey,
-                layer_one_and_two_action: KeyAction::DeadKey,
-            },
-            rt_2: KeyActionSet {
-                base_action: KeyAction::HidKey(KeyboardUsage::KeyboardBackspace),
-                layer_one_action: KeyAction::DeadKey,
-                layer_two_action: KeyAction::DeadKey,
-                layer_one_and_two_action: KeyAction::DeadKey,
-            },
-            rt_3: KeyActionSet {
-                base_action: KeyAction::HidKey(KeyboardUsage::KeyboardSpacebar),
-                layer_one_action: KeyAction::DeadKey,
-                layer_two_action: KeyAction::DeadKey,
-                layer_one_and_two_action: KeyAction::DeadKey,
+            layer_key_1: KeyPosition::left(LeftKeyLocation::LT1),
+            layer_key_2: KeyPosition::right(RightKeyLocation::RT1),
+            keymap: Keymap::Layers(&[BASE_LAYER, LAYER_ONE, LAYER_TWO, LAYER_THREE]),
+            left_encoder: EncoderActionSet {
+                counts_per_step: 1,
+                clockwise: [
+                    KeyAction::HidKey(KeyboardUsage::KeyboardVolumeUp),
+                    KeyAction::DeadKey,
+                    KeyAction::DeadKey,
+                    KeyAction::DeadKey,
+                ],
+                counter_clockwise: [
+                    KeyAction::HidKey(KeyboardUsage::KeyboardVolumeDown),
+                    KeyAction::DeadKey,
+                    KeyAction::DeadKey,
+                    KeyAction::DeadKey,
+                ],
+            },
+            right_encoder: EncoderActionSet {
+                counts_per_step: 1,
+                clockwise: [
+                    KeyAction::HidKey(KeyboardUsage::KeyboardDownArrow),
+                    KeyAction::HidKey(KeyboardUsage::KeyboardPageDown),
+                    KeyAction::DeadKey,
+                    KeyAction::DeadKey,
+                ],
+                counter_clockwise: [
+                    KeyAction::HidKey(KeyboardUsage::KeyboardUpArrow),
+                    KeyAction::HidKey(KeyboardUsage::KeyboardPageUp),
+                    KeyAction::DeadKey,
+                    KeyAction::DeadKey,
+                ],
+            },
+            // hold a key past the timeout to get its shifted variant, off in this profile
+            auto_shift: AutoShiftConfig {
+                timeout_ms: 175,
+                letters: false,
+                numbers: false,
+                symbols: false,
+            },
+            key_overrides: &[KeyOverride {
+                modifiers: Modifiers::SHIFT,
+                trigger: KeyboardUsage::KeyboardBackspace,
+                replacement: KeyboardUsage::KeyboardDelete,
+                suppress_modifiers: true,
+            }],
+            one_shot: OneShotConfig {
+                tap_timeout_ms: 200,
+                idle_timeout_ms: 3000,
+            },
+            leader: LeaderConfig {
+                timeout_ms: 1000,
+                replay_unmatched: true,
+                sequences: LEADER_SEQUENCES,
+            },
+            conditional_layers: CONDITIONAL_LAYERS,
+        };
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use usbd_hid::descriptor::KeyboardUsage::*;
+
+        use crate::{
+            config_channel::protocol,
+            hid_helper::{
+                host_layout::HostLayout,
+                keyboard_report::KeyboardReportHelper,
+                unicode_input::{unicode_reports, UnicodeInput},
+            },
+            io_management::{
+                key_position::KeyPosition, left_half_manager::LeftKeyLocation::*,
+                right_half_manager::RightKeyLocation::*, rotary_encoder::EncoderLocation,
+            },
+            key_features::caps_word::CAPS_WORD_IDLE_TIMEOUT_MS,
+            profiles_management::{
+                keyboard_profile::keyboard_profile::{
+                    Keymap,
+                    UniversalKey::{LeftKey, RightKey},
+                },
+                keymap_blob::{self, KeymapBlob},
+                keymap_tester::{report, KeymapTester, Step::*, TAP_MS},
             },
         };
+
+        use super::get_profile;
+
+        fn keyboard() -> KeymapTester {
+            return KeymapTester::new(get_profile());
+        }
+
+        #[test]
+        fn taps_keys_on_both_halves() {
+            let mut keyboard = keyboard();
+            keyboard.run(&[Tap(LeftKey(C2R1)), Tap(RightKey(C7R2)), Tap(RightKey(RT3))]);
+            keyboard.expect_reports(&[
+                &[KeyboardQq],
+                &[],
+                &[KeyboardHh],
+                &[],
+                &[KeyboardSpacebar],
+                &[],
+            ]);
+        }
+
+        #[test]
+        fn reports_keys_in_position_order() {
+            let mut keyboard = keyboard();
+            keyboard.run(&[
+                Press(RightKey(C7R1)),
+                Press(LeftKey(C2R2)),
+                Press(LeftKey(C2R1)),
+                Release(RightKey(C7R1)),
+            ]);
+            // row by row across both halves, in key position order, whatever the press order
+            keyboard.expect_reports(&[
+                &[KeyboardYy],
+                &[KeyboardYy, KeyboardAa],
+                &[KeyboardQq, KeyboardYy, KeyboardAa],
+                &[KeyboardQq, KeyboardAa],
+            ]);
+        }
+
+        #[test]
+        fn holds_modifiers_over_keys() {
+            let mut keyboard = keyboard();
+            keyboard.run(&[
+                Press(LeftKey(C1R3)),
+                Tap(LeftKey(C4R3)),
+                Release(LeftKey(C1R3)),
+                Press(LeftKey(LT2)),
+                Press(RightKey(C12R3)),
+                Release(LeftKey(LT2)),
+                Release(RightKey(C12R3)),
+            ]);
+            keyboard.expect_reports(&[
+                &[KeyboardLeftShift],
+                &[KeyboardLeftShift, KeyboardCc],
+                &[KeyboardLeftShift],
+                &[],
+                &[KeyboardLeftGUI],
+                &[KeyboardLeftGUI, KeyboardRightShift],
+                &[KeyboardRightShift],
+                &[],
+            ]);
+        }
+
+        #[test]
+        fn layer_keys_send_nothing() {
+            let mut keyboard = keyboard();
+            keyboard.run(&[Tap(LeftKey(LT1)), Tap(RightKey(RT1))]);
+            keyboard.run(&[
+                Press(LeftKey(LT1)),
+                Tap(RightKey(RT1)),
+                Release(LeftKey(LT1)),
+            ]);
+            keyboard.expect_reports(&[]);
+        }
+
+        #[test]
+        fn dead_keys_of_a_layer_send_nothing() {
+            let mut keyboard = keyboard();
+            keyboard.run(&[
+                Press(LeftKey(LT1)),
+                Tap(LeftKey(C2R1)),
+                Release(LeftKey(LT1)),
+            ]);
+            keyboard.run(&[
+                Press(RightKey(RT1)),
+                Tap(LeftKey(C6R1)),
+                Release(RightKey(RT1)),
+            ]);
+            keyboard.run(&[
+                Press(LeftKey(LT1)),
+                Press(RightKey(RT1)),
+                Tap(LeftKey(C5R2)),
+                Release(RightKey(RT1)),
+                Release(LeftKey(LT1)),
+            ]);
+            keyboard.expect_reports(&[]);
+        }
+
+        #[test]
+        fn releases_held_keys_when_the_layer_changes() {
+            let mut keyboard = keyboard();
+            keyboard.run(&[Press(LeftKey(C2R1)), Press(LeftKey(LT1))]);
+            keyboard.expect_reports(&[&[KeyboardQq], &[]]);
+            // the key held across the change is resolved on the new layer
+            keyboard.run(&[Release(LeftKey(LT1))]);
+            keyboard.expect_reports(&[&[KeyboardQq]]);
+            keyboard.run(&[Release(LeftKey(C2R1))]);
+            keyboard.expect_reports(&[&[]]);
+        }
+
+        #[test]
+        fn releases_held_keys_when_the_profile_switches() {
+            let mut keyboard = keyboard();
+            keyboard.run(&[Press(LeftKey(C2R1))]);
+            keyboard.switch_profile(get_profile());
+            keyboard.run(&[Press(LeftKey(C3R1)), Release(LeftKey(C2R1))]);
+            keyboard.expect_reports(&[
+                &[KeyboardQq],
+                &[],
+                &[KeyboardQq, KeyboardWw],
+                &[KeyboardWw],
+            ]);
+        }
+
+        #[test]
+        fn plays_the_launcher_macro_on_layer_one() {
+            let mut keyboard = keyboard();
+            keyboard.run(&[
+                Press(LeftKey(LT1)),
+                Tap(LeftKey(C5R2)),
+                Release(LeftKey(LT1)),
+            ]);
+            let start_ms = keyboard.now_ms() - TAP_MS;
+            keyboard.run(&[Wait(500)]);
+            let sent = keyboard.take_reports();
+            assert_eq!(sent[0].report, report(&[KeyboardLeftGUI]));
+            assert_eq!(sent[1].report, report(&[]));
+            assert_eq!(sent[0].time_ms, start_ms);
+            // the text is typed once the launcher had time to open
+            let text: Vec<_> = sent[2..].iter().map(|sent| sent.report).collect();
+            let typed = [
+                KeyboardFf,
+                KeyboardIi,
+                KeyboardRr,
+                KeyboardEe,
+                KeyboardFf,
+                KeyboardOo,
+                KeyboardXx,
+                KeyboardEnter,
+            ];
+            let expected: Vec<_> = typed
+                .iter()
+                .flat_map(|key| [report(&[*key]), report(&[])])
+                .collect();
+            assert_eq!(text, expected);
+            assert!(sent[2..].iter().all(|sent| sent.time_ms == start_ms + 200));
+        }
+
+        #[test]
+        fn types_text_for_the_host_layout_of_the_settings() {
+            let mut keyboard = keyboard();
+            keyboard.run(&[
+                Press(LeftKey(LT1)),
+                Press(RightKey(RT1)),
+                Tap(RightKey(C12R1)),
+                Release(RightKey(RT1)),
+            ]);
+            assert_eq!(
+                keyboard.manager().settings().host_layout,
+                HostLayout::Dvorak
+            );
+            keyboard.take_reports();
+            keyboard.run(&[Tap(LeftKey(C5R2)), Release(LeftKey(LT1)), Wait(500)]);
+            let sent = keyboard.take_reports();
+            let text: Vec<_> = sent[2..].iter().map(|sent| sent.report).collect();
+            // `firefox` on the keys a Dvorak host reads it from
+            let typed = [
+                KeyboardYy,
+                KeyboardGg,
+                KeyboardOo,
+                KeyboardDd,
+                KeyboardYy,
+                KeyboardSs,
+                KeyboardBb,
+                KeyboardEnter,
+            ];
+            let expected: Vec<_> = typed
+                .iter()
+                .flat_map(|key| [report(&[*key]), report(&[])])
+                .collect();
+            assert_eq!(text, expected);
+        }
+
+        #[test]
+        fn plays_the_same_from_a_keymap_blob() {
+            let steps = [
+                Tap(LeftKey(C2R1)),
+                Press(LeftKey(LT1)),
+                Tap(LeftKey(C5R2)),
+                Release(LeftKey(LT1)),
+                Wait(500),
+                Press(RightKey(RT1)),
+                Tap(LeftKey(C5R1)),
+                Release(RightKey(RT1)),
+            ];
+            let mut keyboard = keyboard();
+            keyboard.run(&steps);
+
+            let mut profile = get_profile();
+            let mut out = vec![0; 4096];
+            let size = keymap_blob::encode(&profile.keymap, &mut out).unwrap();
+            out.truncate(size);
+            profile.keymap = Keymap::Blob(KeymapBlob::new(out.leak()).unwrap());
+            let mut blob_keyboard = KeymapTester::new(profile);
+            blob_keyboard.run(&steps);
+
+            let sent = keyboard.take_reports();
+            assert!(sent.len() > 10);
+            assert_eq!(blob_keyboard.take_reports(), sent);
+        }
+
+        #[test]
+        fn plays_keys_remapped_over_the_configuration_channel() {
+            let profile = get_profile();
+            let mut keyboard = keyboard();
+            let store = keyboard.manager_mut().keymap_store_mut();
+            let q = KeyPosition::left(C2R1).index() as u8;
+            let w = KeyPosition::left(C3R1).index() as u8;
+            let mut request = [0; protocol::REPORT_SIZE];
+            // Q types Z
+            request[..6].copy_from_slice(&[0x04, 0, q, 0x01, KeyboardZz as u8, 0]);
+            protocol::dispatch(&request, store, &profile.keymap);
+            // W taps A then B, from a macro at the start of the macro buffer
+            request[..11].copy_from_slice(&[0x07, 0, 0, 5, 0x03, 0x04, 0x03, 0x05, 0x00, 0, 0]);
+            protocol::dispatch(&request, store, &profile.keymap);
+            request[..6].copy_from_slice(&[0x04, 0, w, 0x02, 0, 0]);
+            protocol::dispatch(&request, store, &profile.keymap);
+
+            keyboard.run(&[Tap(LeftKey(C2R1)), Tap(LeftKey(C3R1)), Tap(LeftKey(C4R1))]);
+            keyboard.expect_reports(&[
+                &[KeyboardZz],
+                &[],
+                &[KeyboardAa],
+                &[],
+                &[KeyboardBb],
+                &[],
+                &[KeyboardEe],
+                &[],
+            ]);
+        }
+
+        #[test]
+        fn records_and_plays_back_a_macro() {
+            let mut keyboard = keyboard();
+            keyboard.run(&[
+                Press(RightKey(RT1)),
+                Tap(LeftKey(C2R1)),
+                Release(RightKey(RT1)),
+                Tap(LeftKey(C2R1)),
+                Wait(100),
+                Tap(LeftKey(C3R1)),
+                Press(RightKey(RT1)),
+                Tap(LeftKey(C4R1)),
+                Release(RightKey(RT1)),
+            ]);
+            keyboard.take_reports();
+            keyboard.run(&[
+                Press(RightKey(RT1)),
+                Tap(LeftKey(C2R2)),
+                Release(RightKey(RT1)),
+            ]);
+            keyboard.run(&[Wait(500)]);
+            let sent = keyboard.take_reports();
+            let reports: Vec<_> = sent.iter().map(|sent| sent.report).collect();
+            assert_eq!(
+                reports,
+                [
+                    report(&[KeyboardQq]),
+                    report(&[]),
+                    report(&[KeyboardWw]),
+                    report(&[]),
+                ]
+            );
+            // played back with the timing it was recorded with
+            assert_eq!(sent[1].time_ms - sent[0].time_ms, TAP_MS);
+            assert_eq!(sent[2].time_ms - sent[1].time_ms, 100);
+        }
+
+        #[test]
+        fn caps_word_shifts_letters_until_a_word_ends() {
+            let mut keyboard = keyboard();
+            keyboard.run(&[
+                Press(LeftKey(LT1)),
+                Tap(LeftKey(C1R3)),
+                Release(LeftKey(LT1)),
+            ]);
+            assert!(keyboard.manager().is_caps_word_active());
+            keyboard.run(&[
+                Tap(LeftKey(C2R1)),
+                Tap(RightKey(RT2)),
+                Tap(LeftKey(C3R1)),
+                Tap(RightKey(RT3)),
+                Tap(LeftKey(C4R1)),
+            ]);
+            keyboard.expect_reports(&[
+                &[KeyboardLeftShift, KeyboardQq],
+                &[],
+                &[KeyboardBackspace],
+                &[],
+                &[KeyboardLeftShift, KeyboardWw],
+                &[],
+                // space ends the word
+                &[KeyboardSpacebar],
+                &[],
+                &[KeyboardEe],
+                &[],
+            ]);
+            assert!(!keyboard.manager().is_caps_word_active());
+        }
+
+        #[test]
+        fn caps_word_toggles_off_and_times_out() {
+            let mut keyboard = keyboard();
+            let toggle = [
+                Press(LeftKey(LT1)),
+                Tap(LeftKey(C1R3)),
+                Release(LeftKey(LT1)),
+            ];
+            keyboard.run(&toggle);
+            keyboard.run(&toggle);
+            assert!(!keyboard.manager().is_caps_word_active());
+            keyboard.run(&toggle);
+            keyboard.run(&[Tap(LeftKey(C2R1)), Wait(CAPS_WORD_IDLE_TIMEOUT_MS)]);
+            assert!(!keyboard.manager().is_caps_word_active());
+            keyboard.run(&[Tap(LeftKey(C2R1))]);
+            keyboard.expect_reports(&[&[KeyboardLeftShift, KeyboardQq], &[], &[KeyboardQq], &[]]);
+        }
+
+        #[test]
+        fn shift_backspace_sends_delete() {
+            let mut keyboard = keyboard();
+            keyboard.run(&[
+                Press(RightKey(C12R3)),
+                Tap(RightKey(RT2)),
+                Release(RightKey(C12R3)),
+            ]);
+            keyboard.expect_reports(&[
+                &[KeyboardRightShift],
+                &[KeyboardDelete],
+                &[KeyboardRightShift],
+                &[],
+            ]);
+        }
+
+        #[test]
+        fn key_overrides_last_until_the_trigger_is_released() {
+            let mut keyboard = keyboard();
+            keyboard.run(&[
+                Press(LeftKey(C1R3)),
+                Press(RightKey(RT2)),
+                Release(LeftKey(C1R3)),
+                Release(RightKey(RT2)),
+            ]);
+            keyboard.expect_reports(&[&[KeyboardLeftShift], &[KeyboardDelete], &[]]);
+        }
+
+        #[test]
+        fn one_shot_modifiers_apply_to_the_next_key_only() {
+            let mut keyboard = keyboard();
+            keyboard.run(&[
+                Press(LeftKey(LT1)),
+                Tap(LeftKey(C2R2)),
+                Release(LeftKey(LT1)),
+                Tap(LeftKey(C2R1)),
+                Tap(LeftKey(C3R1)),
+            ]);
+            keyboard.expect_reports(&[
+                &[KeyboardLeftShift],
+                &[],
+                &[KeyboardLeftShift, KeyboardQq],
+                &[],
+                &[KeyboardWw],
+                &[],
+            ]);
+        }
+
+        #[test]
+        fn escape_and_the_idle_timeout_drop_one_shot_modifiers() {
+            let mut keyboard = keyboard();
+            keyboard.run(&[
+                Press(LeftKey(LT1)),
+                Tap(LeftKey(C2R2)),
+                Tap(LeftKey(C1R1)),
+                Release(LeftKey(LT1)),
+                Tap(LeftKey(C2R1)),
+            ]);
+            keyboard.expect_reports(&[
+                &[KeyboardLeftShift],
+                &[],
+                &[KeyboardEscape],
+                &[],
+                &[KeyboardQq],
+                &[],
+            ]);
+            keyboard.run(&[
+                Press(LeftKey(LT1)),
+                Tap(LeftKey(C2R2)),
+                Release(LeftKey(LT1)),
+                Wait(3000),
+                Tap(LeftKey(C2R1)),
+            ]);
+            keyboard.expect_reports(&[&[KeyboardLeftShift], &[], &[KeyboardQq], &[]]);
+        }
+
+        #[test]
+        fn unmatched_leader_sequences_are_replayed() {
+            let mut keyboard = keyboard();
+            keyboard.run(&[
+                Press(LeftKey(LT1)),
+                Tap(RightKey(RT3)),
+                Release(LeftKey(LT1)),
+                Tap(LeftKey(C6R2)),
+                Tap(LeftKey(C3R3)),
+            ]);
+            keyboard.expect_reports(&[&[KeyboardGg], &[], &[KeyboardXx], &[]]);
+            // a sequence left unfinished is replayed once it times out
+            keyboard.run(&[
+                Press(LeftKey(LT1)),
+                Tap(RightKey(RT3)),
+                Release(LeftKey(LT1)),
+                Tap(LeftKey(C6R2)),
+                Wait(900),
+            ]);
+            keyboard.expect_reports(&[]);
+            keyboard.run(&[Wait(100)]);
+            keyboard.expect_reports(&[&[KeyboardGg], &[]]);
+            assert!(!keyboard.manager().is_leader_active());
+        }
+
+        #[test]
+        fn types_unicode_through_the_input_method_of_the_settings() {
+            let mut keyboard = keyboard();
+            keyboard.run(&[
+                Press(RightKey(RT1)),
+                Tap(LeftKey(C5R1)),
+                Release(RightKey(RT1)),
+            ]);
+            let sent: Vec<KeyboardReportHelper> = keyboard
+                .take_reports()
+                .iter()
+                .map(|sent| sent.report)
+                .collect();
+            assert_eq!(sent, unicode_reports(UnicodeInput::Linux, '€').as_slice());
+
+            keyboard.run(&[
+                Press(LeftKey(LT1)),
+                Press(RightKey(RT1)),
+                Tap(LeftKey(C4R1)),
+                Release(RightKey(RT1)),
+                Release(LeftKey(LT1)),
+            ]);
+            assert_eq!(
+                keyboard.manager().settings().unicode_input,
+                UnicodeInput::MacOs
+            );
+            keyboard.take_reports();
+            keyboard.run(&[
+                Press(RightKey(RT1)),
+                Tap(LeftKey(C5R1)),
+                Release(RightKey(RT1)),
+            ]);
+            let sent: Vec<KeyboardReportHelper> = keyboard
+                .take_reports()
+                .iter()
+                .map(|sent| sent.report)
+                .collect();
+            assert_eq!(sent, unicode_reports(UnicodeInput::MacOs, '€').as_slice());
+        }
+
+        #[test]
+        fn toggles_swap_hands() {
+            let mut keyboard = keyboard();
+            keyboard.run(&[
+                Press(LeftKey(LT1)),
+                Tap(LeftKey(C6R3)),
+                Release(LeftKey(LT1)),
+                Tap(LeftKey(C2R1)),
+                Tap(RightKey(C11R1)),
+            ]);
+            keyboard.expect_reports(&[&[KeyboardPp], &[], &[KeyboardQq], &[]]);
+            // the swap-hands key stays where it is
+            keyboard.run(&[
+                Press(LeftKey(LT1)),
+                Tap(LeftKey(C6R3)),
+                Release(LeftKey(LT1)),
+                Tap(LeftKey(C2R1)),
+            ]);
+            keyboard.expect_reports(&[&[KeyboardQq], &[]]);
+        }
+
+        #[test]
+        fn looks_keys_up_on_the_default_layer_of_the_settings() {
+            let mut keyboard = keyboard();
+            keyboard.run(&[
+                Press(LeftKey(LT1)),
+                Press(RightKey(RT1)),
+                Tap(LeftKey(C6R1)),
+                Release(RightKey(RT1)),
+                Release(LeftKey(LT1)),
+            ]);
+            assert_eq!(keyboard.manager().settings().default_layer, 1);
+            keyboard.take_reports();
+            keyboard.run(&[Tap(LeftKey(C1R1))]);
+            keyboard.expect_reports(&[&[KeyboardEscape], &[]]);
+            keyboard.run(&[
+                Press(LeftKey(LT1)),
+                Press(RightKey(RT1)),
+                Tap(LeftKey(C5R1)),
+                Release(RightKey(RT1)),
+                Release(LeftKey(LT1)),
+                Tap(LeftKey(C1R1)),
+            ]);
+            keyboard.expect_reports(&[&[KeyboardTab], &[]]);
+        }
+
+        #[test]
+        fn taps_encoder_actions_per_layer() {
+            let mut keyboard = keyboard();
+            keyboard.run(&[
+                Turn(EncoderLocation::Right, 2),
+                Turn(EncoderLocation::Left, -1),
+            ]);
+            keyboard.expect_reports(&[
+                &[KeyboardDownArrow],
+                &[],
+                &[KeyboardDownArrow],
+                &[],
+                &[KeyboardVolumeDown],
+                &[],
+            ]);
+            keyboard.run(&[
+                Press(LeftKey(LT1)),
+                Turn(EncoderLocation::Right, -1),
+                Release(LeftKey(LT1)),
+            ]);
+            keyboard.expect_reports(&[&[KeyboardPageUp], &[]]);
+        }
+
+        #[test]
+        fn taps_encoder_actions_over_held_keys() {
+            let mut keyboard = keyboard();
+            keyboard.run(&[
+                Press(LeftKey(C1R2)),
+                Turn(EncoderLocation::Right, 1),
+                Release(LeftKey(C1R2)),
+            ]);
+            keyboard.expect_reports(&[
+                &[KeyboardLeftControl],
+                &[KeyboardLeftControl, KeyboardDownArrow],
+                &[KeyboardLeftControl],
+                &[],
+            ]);
+        }
     }
 }
@@ -1,44 +1,311 @@
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
 use crate::hid_helper::keyboard_report::KeyboardReportHelper;
 
-const BUFFER_SIZE: u8 = 100;
+/// Must be a power of two, the positions wrap around along with the counters.
+const BUFFER_SIZE: usize = 128;
+/// Slots only reports releasing keys can take, so that a release still fits when pressing
+/// reports have filled up the rest of the buffer.
+const RELEASE_RESERVE: usize = 16;
+
+/// A report waiting in the [KeyboardRingBuffer], along with the time the key change behind it
+/// has been seen.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct QueuedReport {
+    pub report: KeyboardReportHelper,
+    pub timestamp_us: u64,
+}
 
-pub(crate) struct KeyboardRingBuffer {
-    the_buffer: [KeyboardReportHelper; BUFFER_SIZE as usize],
-    entry_pos: usize,
-    exit_pos: usize,
-    count: u8,
+/// A lock-free single producer single consumer queue of reports, the producer being the key
+/// processing and the consumer the HID writer. [KeyboardRingBuffer::split] hands out the two ends.
+///
+/// Reports are never dropped silently. Once the buffer is full, the producer coalesces the
+/// reports that do not fit into a single pending one holding the latest state, which moves into
+/// the buffer as soon as there is room. A report releasing keys is only coalesced once even the
+/// release reserve is used up (e.g. the host stopped reading), and since the latest state always
+/// gets through, no key is left stuck on the host.
+pub struct KeyboardRingBuffer {
+    the_buffer: [UnsafeCell<QueuedReport>; BUFFER_SIZE],
+    /// Number of reports put so far, only written by the producer.
+    entry_pos: AtomicUsize,
+    /// Number of reports taken so far, only written by the consumer.
+    exit_pos: AtomicUsize,
+    /// Number of reports coalesced away before reaching the buffer, only written by the producer.
+    overflows: AtomicUsize,
+    /// Highest number of reports waiting at once, only written by the producer.
+    high_water_mark: AtomicUsize,
 }
 
+// SAFETY: a slot is only written by the producer while it is free and only read by the consumer
+// once it has been published through `entry_pos`, the split ends cannot be duplicated.
+unsafe impl Sync for KeyboardRingBuffer {}
+
 impl KeyboardRingBuffer {
     pub const fn new() -> KeyboardRingBuffer {
         return KeyboardRingBuffer {
-            the_buffer: [KeyboardReportHelper::new(); BUFFER_SIZE as usize],
-            entry_pos: 0,
-            exit_pos: 0,
-            count: 0,
+            the_buffer: [const {
+                UnsafeCell::new(QueuedReport {
+                    report: KeyboardReportHelper::new(),
+                    timestamp_us: 0,
+                })
+            }; BUFFER_SIZE],
+            entry_pos: AtomicUsize::new(0),
+            exit_pos: AtomicUsize::new(0),
+            overflows: AtomicUsize::new(0),
+            high_water_mark: AtomicUsize::new(0),
+        };
+    }
+
+    /// Splits the buffer into its producer and consumer ends.
+    pub fn split(&mut self) -> (ReportProducer<'_>, ReportConsumer<'_>) {
+        let buffer: &KeyboardRingBuffer = self;
+        return (
+            ReportProducer {
+                buffer,
+                pending: None,
+                last_queued: KeyboardReportHelper::new(),
+            },
+            ReportConsumer { buffer },
+        );
+    }
+
+    fn len(&self) -> usize {
+        let entry_pos = self.entry_pos.load(Ordering::Acquire);
+        let exit_pos = self.exit_pos.load(Ordering::Acquire);
+        return entry_pos.wrapping_sub(exit_pos);
+    }
+}
+
+/// The key processing end of a [KeyboardRingBuffer].
+pub struct ReportProducer<'a> {
+    buffer: &'a KeyboardRingBuffer,
+    /// Latest report that did not fit into the buffer.
+    pending: Option<QueuedReport>,
+    last_queued: KeyboardReportHelper,
+}
+
+impl ReportProducer<'_> {
+    /// Puts the provided [KeyboardReportHelper] into the [KeyboardRingBuffer], behind the
+    /// pending report if there is one. If there is no room left, it takes the place of the
+    /// pending report. `timestamp_us` is the time the key change behind the report has been seen.
+    pub fn put_report(&mut self, report: KeyboardReportHelper, timestamp_us: u64) {
+        let report = QueuedReport {
+            report,
+            timestamp_us,
+        };
+        if self.flush() && self.push(report) {
+            return;
+        }
+        if self.pending.is_some() {
+            self.buffer.overflows.store(
+                self.buffer.overflows.load(Ordering::Relaxed) + 1,
+                Ordering::Relaxed,
+            );
+        }
+        self.pending = Some(report);
+        // the report replacing the pending one may release keys and fit into the reserve
+        self.flush();
+    }
+
+    /// Moves the pending report into the buffer if there is room for it now. Returns true if
+    /// no report is left pending.
+    pub fn flush(&mut self) -> bool {
+        let pending = match self.pending {
+            Some(pending) => pending,
+            None => return true,
         };
+        if !self.push(pending) {
+            return false;
+        }
+        self.pending = None;
+        return true;
+    }
+
+    /// Returns the number of reports waiting in the buffer, the pending one aside.
+    pub fn len(&self) -> usize {
+        return self.buffer.len();
+    }
+
+    /// Returns true if no report is waiting in the buffer.
+    pub fn is_empty(&self) -> bool {
+        return self.len() == 0;
+    }
+
+    fn push(&mut self, report: QueuedReport) -> bool {
+        let limit = if report.report.releases_keys_of(&self.last_queued) {
+            BUFFER_SIZE
+        } else {
+            BUFFER_SIZE - RELEASE_RESERVE
+        };
+        let len = self.buffer.len();
+        if len >= limit {
+            return false;
+        }
+        let entry_pos = self.buffer.entry_pos.load(Ordering::Relaxed);
+        // SAFETY: the slot is free, the consumer does not read it before `entry_pos` moves past it
+        unsafe {
+            *self.buffer.the_buffer[entry_pos % BUFFER_SIZE].get() = report;
+        }
+        self.buffer
+            .entry_pos
+            .store(entry_pos.wrapping_add(1), Ordering::Release);
+        if len + 1 > self.buffer.high_water_mark.load(Ordering::Relaxed) {
+            self.buffer
+                .high_water_mark
+                .store(len + 1, Ordering::Relaxed);
+        }
+        self.last_queued = report.report;
+        return true;
     }
+}
+
+/// The HID writer end of a [KeyboardRingBuffer].
+pub struct ReportConsumer<'a> {
+    buffer: &'a KeyboardRingBuffer,
+}
 
-    pub fn get_report_helper(&mut self) -> Option<KeyboardReportHelper> {
-        if self.entry_pos == self.exit_pos && self.count == 0 {
+impl ReportConsumer<'_> {
+    pub fn take_report(&mut self) -> Option<QueuedReport> {
+        let exit_pos = self.buffer.exit_pos.load(Ordering::Relaxed);
+        if self.buffer.entry_pos.load(Ordering::Acquire) == exit_pos {
             return None;
         }
-        let report = self.the_buffer[self.exit_pos];
-        self.exit_pos += 1;
-        self.count -= 1;
-        self.exit_pos = self.exit_pos % BUFFER_SIZE as usize;
+        // SAFETY: the slot has been published by the producer, which does not write it again
+        // before `exit_pos` moves past it
+        let report = unsafe { *self.buffer.the_buffer[exit_pos % BUFFER_SIZE].get() };
+        self.buffer
+            .exit_pos
+            .store(exit_pos.wrapping_add(1), Ordering::Release);
         return Some(report);
     }
 
-    /// Puts the provided [KeyboardReportHelper] into the [KeyboardRingBuffer],
-    /// if the report is the same as the previously entered report, then it is ignored.
-    pub fn put_report(&mut self, report: KeyboardReportHelper) {
-        if self.entry_pos == self.exit_pos && self.count != 0 {
-            return;
+    /// Returns the number of reports coalesced away so far because the buffer was full.
+    pub fn overflows(&self) -> usize {
+        return self.buffer.overflows.load(Ordering::Relaxed);
+    }
+
+    /// Returns the highest number of reports that have been waiting in the buffer at once.
+    pub fn high_water_mark(&self) -> usize {
+        return self.buffer.high_water_mark.load(Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+    use usbd_hid::descriptor::KeyboardUsage;
+
+    use super::*;
+
+    const KEYS: [KeyboardUsage; 4] = [
+        KeyboardUsage::KeyboardAa,
+        KeyboardUsage::KeyboardBb,
+        KeyboardUsage::KeyboardCc,
+        KeyboardUsage::KeyboardLeftShift,
+    ];
+
+    #[derive(Clone, Debug)]
+    enum Operation {
+        /// Puts the report holding the keys set in the mask.
+        Put(u8),
+        Take,
+    }
+
+    fn report(mask: u8) -> KeyboardReportHelper {
+        let mut report = KeyboardReportHelper::new();
+        for (bit, key) in KEYS.iter().enumerate() {
+            if mask & (1 << bit) != 0 {
+                report.add_keycode(*key);
+            }
+        }
+        return report;
+    }
+
+    fn operations() -> impl Strategy<Value = Vec<Operation>> {
+        let operation = prop_oneof![
+            3 => (0u8..16).prop_map(Operation::Put),
+            1 => Just(Operation::Take),
+        ];
+        return prop::collection::vec(operation, 0..1000);
+    }
+
+    /// Runs the operations, then drains the buffer the way the HID writer does. Returns the
+    /// reports put and the reports taken.
+    fn run(
+        operations: &[Operation],
+    ) -> (Vec<KeyboardReportHelper>, Vec<KeyboardReportHelper>, usize) {
+        let mut buffer = KeyboardRingBuffer::new();
+        let (mut producer, mut consumer) = buffer.split();
+        let mut put = Vec::new();
+        let mut taken = Vec::new();
+        for operation in operations {
+            match operation {
+                Operation::Put(mask) => {
+                    put.push(report(*mask));
+                    producer.put_report(report(*mask), 0);
+                }
+                Operation::Take => taken.extend(consumer.take_report().map(|queued| queued.report)),
+            }
+            assert!(producer.len() <= BUFFER_SIZE);
+        }
+        loop {
+            match consumer.take_report() {
+                Some(queued) => taken.push(queued.report),
+                None => {
+                    if producer.flush() && producer.is_empty() {
+                        break;
+                    }
+                }
+            }
+        }
+        return (put, taken, consumer.overflows());
+    }
+
+    proptest! {
+        #[test]
+        fn keeps_order_and_never_invents_reports(operations in operations()) {
+            let (put, taken, _) = run(&operations);
+            let mut put = put.iter();
+            for report in taken.iter() {
+                prop_assert!(put.any(|put_report| put_report == report));
+            }
+        }
+
+        #[test]
+        fn latest_state_always_gets_through(operations in operations()) {
+            let (put, taken, _) = run(&operations);
+            prop_assert_eq!(put.last(), taken.last());
+        }
+
+        #[test]
+        fn loses_nothing_without_overflow(operations in operations()) {
+            let (put, taken, overflows) = run(&operations);
+            if overflows == 0 {
+                prop_assert_eq!(put, taken);
+            }
+        }
+
+        #[test]
+        fn releases_fit_into_the_reserve(presses in BUFFER_SIZE..2 * BUFFER_SIZE) {
+            let mut buffer = KeyboardRingBuffer::new();
+            let (mut producer, mut consumer) = buffer.split();
+            // the host does not read while a key is held, the buffer fills up with its reports
+            for timestamp_us in 0..presses {
+                producer.put_report(report(0b0001), timestamp_us as u64);
+            }
+            producer.put_report(report(0), presses as u64);
+            let mut taken = Vec::new();
+            while let Some(queued) = consumer.take_report() {
+                taken.push(queued);
+            }
+            // the coalesced reports are gone, the release is the last one put
+            prop_assert_eq!(taken.last().map(|queued| queued.timestamp_us), Some(presses as u64));
+            let taken: Vec<KeyboardReportHelper> = taken.iter().map(|queued| queued.report).collect();
+            prop_assert_eq!(taken.last(), Some(&report(0)));
+            prop_assert_eq!(taken.len(), BUFFER_SIZE - RELEASE_RESERVE + 1);
+            prop_assert_eq!(consumer.overflows(), presses - (BUFFER_SIZE - RELEASE_RESERVE));
+            prop_assert_eq!(consumer.high_water_mark(), taken.len());
         }
-        self.the_buffer[self.entry_pos] = report;
-        self.entry_pos += 1;
-        self.entry_pos = self.entry_pos % BUFFER_SIZE as usize;
-        self.count += 1;
     }
 }
@@ -0,0 +1,24 @@
+/// Keeps track of the last report of a report type that has been let through to the host, so
+/// that the same state is never sent twice in a row.
+pub struct ReportTracker<R> {
+    last_report: R,
+}
+
+impl<R: PartialEq + Copy> ReportTracker<R> {
+    /// `initial` is the state the host starts from, i.e. the report releasing everything.
+    pub const fn new(initial: R) -> ReportTracker<R> {
+        return ReportTracker {
+            last_report: initial,
+        };
+    }
+
+    /// Returns true if the report changes the state of the host and has to be sent, in which case
+    /// it becomes the last report.
+    pub fn track(&mut self, report: R) -> bool {
+        if report == self.last_report {
+            return false;
+        }
+        self.last_report = report;
+        return true;
+    }
+}
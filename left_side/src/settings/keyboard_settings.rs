@@ -0,0 +1,94 @@
+use crate::{
+    hid_helper::{host_layout::HostLayout, unicode_input::UnicodeInput},
+    profiles_management::layers::LAYER_COUNT,
+};
+
+/// Number of bytes the settings take once stored.
+pub const SETTINGS_SIZE: usize = 16;
+/// Starts the stored settings, telling them apart from erased or foreign flash.
+const MAGIC: [u8; 2] = *b"KS";
+const VERSION: u8 = 1;
+
+/// The settings key actions change at runtime, kept across restarts by the firmware.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct KeyboardSettings {
+    pub unicode_input: UnicodeInput,
+    pub host_layout: HostLayout,
+    /// The layer the keys are looked up on while no layer key is held.
+    pub default_layer: u8,
+}
+
+impl Default for KeyboardSettings {
+    fn default() -> Self {
+        return KeyboardSettings::new();
+    }
+}
+
+impl KeyboardSettings {
+    pub const fn new() -> KeyboardSettings {
+        return KeyboardSettings {
+            unicode_input: UnicodeInput::Linux,
+            host_layout: HostLayout::Us,
+            default_layer: 0,
+        };
+    }
+
+    /// Returns the bytes to store the settings as, the bytes left over are zero.
+    pub fn to_bytes(&self) -> [u8; SETTINGS_SIZE] {
+        let mut bytes = [0; SETTINGS_SIZE];
+        bytes[..2].copy_from_slice(&MAGIC);
+        bytes[2] = VERSION;
+        bytes[3] = self.unicode_input.to_byte();
+        bytes[4] = self.host_layout.to_byte();
+        bytes[5] = self.default_layer;
+        return bytes;
+    }
+
+    /// Reads settings stored by [KeyboardSettings::to_bytes]. Returns [None] if the bytes do not
+    /// hold any, e.g. erased flash.
+    pub fn from_bytes(bytes: &[u8; SETTINGS_SIZE]) -> Option<KeyboardSettings> {
+        if bytes[..2] != MAGIC || bytes[2] != VERSION || bytes[5] >= LAYER_COUNT {
+            return None;
+        }
+        return Some(KeyboardSettings {
+            unicode_input: UnicodeInput::from_byte(bytes[3])?,
+            host_layout: HostLayout::from_byte(bytes[4])?,
+            default_layer: bytes[5],
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_back_the_stored_settings() {
+        let settings = KeyboardSettings {
+            unicode_input: UnicodeInput::MacOs,
+            host_layout: HostLayout::Nordic,
+            default_layer: 2,
+        };
+        assert_eq!(
+            KeyboardSettings::from_bytes(&settings.to_bytes()),
+            Some(settings)
+        );
+    }
+
+    #[test]
+    fn reads_nothing_from_erased_or_foreign_bytes() {
+        assert_eq!(KeyboardSettings::from_bytes(&[0xFF; SETTINGS_SIZE]), None);
+        let mut bytes = KeyboardSettings::new().to_bytes();
+        bytes[2] = VERSION + 1;
+        assert_eq!(KeyboardSettings::from_bytes(&bytes), None);
+        let mut bytes = KeyboardSettings::new().to_bytes();
+        bytes[3] = 0xFF;
+        assert_eq!(KeyboardSettings::from_bytes(&bytes), None);
+        let mut bytes = KeyboardSettings::new().to_bytes();
+        bytes[4] = 0xFF;
+        assert_eq!(KeyboardSettings::from_bytes(&bytes), None);
+        let mut bytes = KeyboardSettings::new().to_bytes();
+        bytes[5] = LAYER_COUNT;
+        assert_eq!(KeyboardSettings::from_bytes(&bytes), None);
+    }
+}
@@ -1,3 +1,4 @@
+use embassy_futures::select::select_array;
 use embassy_rp::{
     gpio::{Input, Output, Pin},
     Peripheral,
@@ -130,6 +131,41 @@ impl<'d> RightIoKeyManager<'d> {
             thumb_cluster: left_thumb_cluster_readout,
         }
     }
+
+    /// Drives all the rows low and waits until any column or thumb cluster input goes low,
+    /// i.e. until any key is pressed. The core is free to sleep while waiting. The rows are
+    /// released again before returning, so the next [RightIoKeyManager::produce_readout] works
+    /// as usual.
+    pub async fn wait_for_key_press(&mut self) {
+        self.row_1.set_low();
+        self.row_2.set_low();
+        self.row_3.set_low();
+        Timer::after_micros(100).await;
+
+        select_array([
+            self.column_7.wait_for_low(),
+            self.column_8.wait_for_low(),
+            self.column_9.wait_for_low(),
+            self.column_10.wait_for_low(),
+            self.column_11.wait_for_low(),
+            self.column_12.wait_for_low(),
+            self.rt_1.wait_for_low(),
+            self.rt_2.wait_for_low(),
+            self.rt_3.wait_for_low(),
+        ])
+        .await;
+
+        self.release_rows();
+        Timer::after_micros(100).await;
+    }
+
+    /// Drives all the rows high again, as [RightIoKeyManager::produce_readout] expects them.
+    /// Needed when a [RightIoKeyManager::wait_for_key_press] is cancelled.
+    pub fn release_rows(&mut self) {
+        self.row_1.set_high();
+        self.row_2.set_high();
+        self.row_3.set_high();
+    }
 }
 
 #[derive(PartialEq, Eq)]
@@ -152,6 +188,26 @@ impl Default for RightKeyReadout {
 }
 
 impl RightKeyReadout {
+    /// Builds a readout from bytes in [ row_1 , row_2 , row_3 , thumb_cluster ] order
+    pub fn from_bytes(bytes: [u8; 4]) -> Self {
+        return RightKeyReadout {
+            row_1: bytes[0],
+            row_2: bytes[1],
+            row_3: bytes[2],
+            thumb_cluster: bytes[3],
+        };
+    }
+
+    /// Returns bytes in [ row_1 , row_2 , row_3 , thumb_cluster ] order
+    pub fn as_bytes(&self) -> [u8; 4] {
+        return [self.row_1, self.row_2, self.row_3, self.thumb_cluster];
+    }
+
+    /// Returns true if no key is pressed.
+    pub fn is_empty(&self) -> bool {
+        return self.as_bytes() == [0; 4];
+    }
+
     /// Returns byte in [ row_1 , row_2 , row_3 , thumb_cluster ] order
     pub fn as_ne_bytes(&self) -> [u8; 4] {
         let ret = self.row_1 as u32;
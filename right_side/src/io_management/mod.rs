@@ -1 +1,9 @@
-pub mod key_manager;
\ No newline at end of file
+// shared with the left half, whose library is where the tests run
+#[path = "../../../left_side/src/io_management/debounce.rs"]
+pub mod debounce;
+pub mod key_manager;
+#[cfg(feature = "rotary-encoder")]
+#[path = "../../../left_side/src/io_management/pio_encoder.rs"]
+pub mod pio_encoder;
+#[cfg(feature = "pio-scan")]
+pub mod pio_scanner;
@@ -0,0 +1,181 @@
+use embassy_futures::select::{select, select_array};
+use embassy_rp::{
+    dma::{AnyChannel, Channel},
+    gpio::{Input, Level, Pin},
+    peripherals::PIO0,
+    pio::{Common, Config, Direction, FifoJoin, PioPin, ShiftDirection, StateMachine},
+    Peripheral, PeripheralRef,
+};
+use fixed::traits::ToFixed;
+
+use super::key_manager::RightKeyReadout;
+
+/// Number of state machine cycles the scanning program takes for one unchanged scan of the
+/// matrix, used to derive the clock divider from the requested scan rate.
+const CYCLES_PER_SCAN: u32 = 103;
+
+/// Value written into the DMA buffer before a transfer, the program never pushes it.
+const NO_SCAN: u32 = u32::MAX;
+
+/// Scans the key matrix on a PIO state machine instead of the CPU.
+///
+/// The state machine strobes the rows and samples the columns at a fixed rate, and only pushes
+/// a scan into its FIFO when it differs from the previous one. The scan is moved into memory by
+/// DMA, so the CPU only wakes up when the matrix changes. The thumb cluster is not part of the
+/// matrix and is watched through GPIO edge interrupts instead.
+pub struct RightPioScanner<'a> {
+    sm: StateMachine<'a, PIO0, 0>,
+    dma: PeripheralRef<'a, AnyChannel>,
+    rt_1: Input<'a>,
+    rt_2: Input<'a>,
+    rt_3: Input<'a>,
+    /// DMA buffer, holds [NO_SCAN] until a scan lands.
+    scan: [u32; 1],
+    /// Last matrix scan, bit set means pressed.
+    matrix: u32,
+}
+
+impl<'d> RightPioScanner<'d> {
+    pub fn new(
+        common: &mut Common<'d, PIO0>,
+        mut sm: StateMachine<'d, PIO0, 0>,
+        dma: impl Peripheral<P = impl Channel> + 'd,
+        row_1: impl Peripheral<P = impl PioPin + 'd> + 'd,
+        row_2: impl Peripheral<P = impl PioPin + 'd> + 'd,
+        row_3: impl Peripheral<P = impl PioPin + 'd> + 'd,
+        column_7: impl Peripheral<P = impl PioPin + 'd> + 'd,
+        column_8: impl Peripheral<P = impl PioPin + 'd> + 'd,
+        column_9: impl Peripheral<P = impl PioPin + 'd> + 'd,
+        column_10: impl Peripheral<P = impl PioPin + 'd> + 'd,
+        column_11: impl Peripheral<P = impl PioPin + 'd> + 'd,
+        column_12: impl Peripheral<P = impl PioPin + 'd> + 'd,
+        rt_1: impl Peripheral<P = impl Pin> + 'd,
+        rt_2: impl Peripheral<P = impl Pin> + 'd,
+        rt_3: impl Peripheral<P = impl Pin> + 'd,
+        scan_rate_hz: u32,
+    ) -> RightPioScanner<'d> {
+        // Rows are driven low one at a time (active low, like the GPIO scanner), each row is
+        // given 32 cycles to settle before its 6 columns are shifted into the ISR. The 18 bit
+        // scan is compared against the previous one kept in Y and only pushed when it changed.
+        let program = pio_proc::pio_asm!(
+            "    set y, 0",
+            ".wrap_target",
+            "scan:",
+            "    set pins, 0b110 [31]",
+            "    in pins, 6",
+            "    set pins, 0b101 [31]",
+            "    in pins, 6",
+            "    set pins, 0b011 [31]",
+            "    in pins, 6",
+            "    set pins, 0b111",
+            "    mov x, isr",
+            "    mov isr, null",
+            "    jmp x!=y changed",
+            ".wrap",
+            "changed:",
+            "    mov y, x",
+            "    in y, 32",
+            "    push block",
+            "    jmp scan",
+        );
+        let program = common.load_program(&program.program);
+
+        // rows, the set pins must be consecutive: row_3 is the lowest pin
+        let row_1 = common.make_pio_pin(row_1);
+        let row_2 = common.make_pio_pin(row_2);
+        let row_3 = common.make_pio_pin(row_3);
+        let rows = [&row_3, &row_2, &row_1];
+        sm.set_pins(Level::High, &rows);
+        sm.set_pin_dirs(Direction::Out, &rows);
+
+        // columns, the in pins must be consecutive: column_12 is the lowest pin
+        let mut column_7 = common.make_pio_pin(column_7);
+        column_7.set_pull(embassy_rp::gpio::Pull::Up);
+        let mut column_8 = common.make_pio_pin(column_8);
+        column_8.set_pull(embassy_rp::gpio::Pull::Up);
+        let mut column_9 = common.make_pio_pin(column_9);
+        column_9.set_pull(embassy_rp::gpio::Pull::Up);
+        let mut column_10 = common.make_pio_pin(column_10);
+        column_10.set_pull(embassy_rp::gpio::Pull::Up);
+        let mut column_11 = common.make_pio_pin(column_11);
+        column_11.set_pull(embassy_rp::gpio::Pull::Up);
+        let mut column_12 = common.make_pio_pin(column_12);
+        column_12.set_pull(embassy_rp::gpio::Pull::Up);
+        let columns = [
+            &column_12, &column_11, &column_10, &column_9, &column_8, &column_7,
+        ];
+        sm.set_pin_dirs(Direction::In, &columns);
+
+        let mut config = Config::default();
+        config.use_program(&program, &[]);
+        config.set_set_pins(&rows);
+        config.set_in_pins(&columns);
+        config.shift_in.direction = ShiftDirection::Left;
+        config.fifo_join = FifoJoin::RxOnly;
+        config.clock_divider =
+            (embassy_rp::clocks::clk_sys_freq() / (scan_rate_hz * CYCLES_PER_SCAN)).to_fixed();
+        sm.set_config(&config);
+        sm.set_enable(true);
+
+        // right thumb cluster
+        let mut rt_1 = Input::new(rt_1, embassy_rp::gpio::Pull::Up);
+        rt_1.set_schmitt(true);
+        let mut rt_2 = Input::new(rt_2, embassy_rp::gpio::Pull::Up);
+        rt_2.set_schmitt(true);
+        let mut rt_3 = Input::new(rt_3, embassy_rp::gpio::Pull::Up);
+        rt_3.set_schmitt(true);
+
+        return RightPioScanner {
+            sm,
+            dma: dma.into_ref().map_into(),
+            rt_1,
+            rt_2,
+            rt_3,
+            scan: [NO_SCAN],
+            matrix: 0,
+        };
+    }
+
+    /// Waits until the matrix or the thumb cluster changes and returns the new readout.
+    ///
+    /// This is cancel safe: a scan that lands while the returned future is being dropped is
+    /// picked up by the next call.
+    pub async fn wait_for_change(&mut self) -> RightKeyReadout {
+        if self.scan[0] == NO_SCAN {
+            let thumb_cluster = select_array([
+                self.rt_1.wait_for_any_edge(),
+                self.rt_2.wait_for_any_edge(),
+                self.rt_3.wait_for_any_edge(),
+            ]);
+            let transfer = self.sm.rx().dma_pull(self.dma.reborrow(), &mut self.scan);
+            select(transfer, thumb_cluster).await;
+        }
+        // the transfer may have finished right before the thumb cluster won the race
+        if self.scan[0] != NO_SCAN {
+            // columns read low when pressed
+            self.matrix = !self.scan[0];
+            self.scan[0] = NO_SCAN;
+        }
+        return self.readout();
+    }
+
+    /// Returns the readout of the last scan, without waiting for a change.
+    pub fn readout(&self) -> RightKeyReadout {
+        let mut right_thumb_cluster_readout: u8 = 0;
+        right_thumb_cluster_readout =
+            self.rt_1.is_low() as u8 * 0b10000000 + right_thumb_cluster_readout;
+        right_thumb_cluster_readout =
+            self.rt_2.is_low() as u8 * 0b01000000 + right_thumb_cluster_readout;
+        right_thumb_cluster_readout =
+            self.rt_3.is_low() as u8 * 0b00100000 + right_thumb_cluster_readout;
+
+        // rows are shifted in from the lowest pin (row_3) up, columns with column_12 as the
+        // lowest bit, which sits two bits up in the readout
+        return RightKeyReadout::from_bytes([
+            ((self.matrix as u8) & 0b00111111) << 2,
+            ((self.matrix >> 6) as u8 & 0b00111111) << 2,
+            ((self.matrix >> 12) as u8 & 0b00111111) << 2,
+            right_thumb_cluster_readout,
+        ]);
+    }
+}
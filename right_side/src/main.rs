@@ -5,16 +5,52 @@
 mod io_management;
 
 use embassy_executor::Spawner;
+#[cfg(feature = "rotary-encoder")]
+use embassy_futures::select::select;
 use embassy_rp::peripherals::I2C0;
+#[cfg(feature = "pio-scan")]
+use embassy_rp::peripherals::PIO0;
+#[cfg(feature = "rotary-encoder")]
+use embassy_rp::peripherals::PIO1;
+#[cfg(any(feature = "pio-scan", feature = "rotary-encoder"))]
+use embassy_rp::pio::Pio;
 use embassy_rp::{bind_interrupts, i2c};
-use embassy_time::Timer;
-use io_management::key_manager::{RightIoKeyManager, RightKeyReadout};
+#[cfg(feature = "pio-scan")]
+use embassy_time::{with_timeout, Duration};
+use embassy_time::{Instant, Timer};
+use io_management::debounce::{DebounceAlgorithm, Debouncer};
+#[cfg(not(feature = "pio-scan"))]
+use io_management::key_manager::RightIoKeyManager;
+use io_management::key_manager::RightKeyReadout;
+#[cfg(feature = "rotary-encoder")]
+use io_management::pio_encoder::PioRotaryEncoder;
+#[cfg(feature = "pio-scan")]
+use io_management::pio_scanner::RightPioScanner;
 use {defmt_rtt as _, panic_probe as _};
 
 bind_interrupts!(struct Irqs {
     I2C0_IRQ => i2c::InterruptHandler<I2C0>;
 });
 
+#[cfg(feature = "pio-scan")]
+bind_interrupts!(struct PioIrqs {
+    PIO0_IRQ_0 => embassy_rp::pio::InterruptHandler<PIO0>;
+});
+
+#[cfg(feature = "rotary-encoder")]
+bind_interrupts!(struct EncoderIrqs {
+    PIO1_IRQ_0 => embassy_rp::pio::InterruptHandler<PIO1>;
+});
+
+const DEBOUNCE_ALGORITHM: DebounceAlgorithm = DebounceAlgorithm::DeferredPerKey;
+const DEBOUNCE_TIME_MS: u64 = 5;
+/// Time without any key down after which scanning stops until the next key press.
+#[cfg(not(feature = "pio-scan"))]
+const IDLE_TIMEOUT_MS: u64 = 500;
+/// Rate at which the PIO state machine scans the whole matrix.
+#[cfg(feature = "pio-scan")]
+const PIO_SCAN_RATE_HZ: u32 = 1000;
+
 #[embassy_executor::main]
 async fn main(spawner: Spawner) {
     const LEFT_KEYBOARD_ADDRESS: u16 = 0x0069;
@@ -26,27 +62,118 @@ async fn main(spawner: Spawner) {
     let mut config = i2c::Config::default();
     config.frequency = 1_000_000;
     let mut device = i2c::I2c::new_async(p.I2C0, left_scl, left_sda, Irqs, config);
+    #[cfg(not(feature = "pio-scan"))]
     let mut key_io_handler = RightIoKeyManager::new(
         p.PIN_15, p.PIN_14, p.PIN_13, p.PIN_12, p.PIN_11, p.PIN_10, p.PIN_9, p.PIN_8, p.PIN_7,
         p.PIN_4, p.PIN_3, p.PIN_2,
     );
+    #[cfg(feature = "pio-scan")]
+    let Pio {
+        mut common, sm0, ..
+    } = Pio::new(p.PIO0, PioIrqs);
+    #[cfg(feature = "pio-scan")]
+    let mut key_scanner = RightPioScanner::new(
+        &mut common,
+        sm0,
+        p.DMA_CH0,
+        p.PIN_15,
+        p.PIN_14,
+        p.PIN_13,
+        p.PIN_12,
+        p.PIN_11,
+        p.PIN_10,
+        p.PIN_9,
+        p.PIN_8,
+        p.PIN_7,
+        p.PIN_4,
+        p.PIN_3,
+        p.PIN_2,
+        PIO_SCAN_RATE_HZ,
+    );
+    #[cfg(feature = "rotary-encoder")]
+    let Pio {
+        common: mut encoder_common,
+        sm0: encoder_sm,
+        ..
+    } = Pio::new(p.PIO1, EncoderIrqs);
+    #[cfg(feature = "rotary-encoder")]
+    let mut right_encoder =
+        PioRotaryEncoder::new(&mut encoder_common, encoder_sm, p.PIN_18, p.PIN_19);
 
     // waiting for others to be ready
     Timer::after_millis(300).await;
 
     let mut previous_readout = RightKeyReadout::default();
+    let mut debouncer: Debouncer<4> = Debouncer::new(DEBOUNCE_ALGORITHM, DEBOUNCE_TIME_MS);
+    #[cfg(not(feature = "pio-scan"))]
+    let mut last_activity = Instant::now();
 
     loop {
-        let readout = key_io_handler.produce_readout().await;
-        if previous_readout != readout {
+        #[cfg(not(feature = "pio-scan"))]
+        let raw_readout = key_io_handler.produce_readout().await;
+        #[cfg(feature = "pio-scan")]
+        let raw_readout = if debouncer.is_settled() {
+            // an encoder turn ends the wait without any change on the matrix
+            #[cfg(feature = "rotary-encoder")]
+            select(
+                key_scanner.wait_for_change(),
+                right_encoder.wait_for_counts(),
+            )
+            .await;
+            #[cfg(not(feature = "rotary-encoder"))]
+            key_scanner.wait_for_change().await;
+            key_scanner.readout()
+        } else {
+            // still debouncing, the current readout has to be fed again even if it does not change
+            with_timeout(Duration::from_millis(1), key_scanner.wait_for_change())
+                .await
+                .unwrap_or_else(|_| key_scanner.readout())
+        };
+        let now = Instant::now();
+        #[cfg(not(feature = "pio-scan"))]
+        if !raw_readout.is_empty() {
+            last_activity = now;
+        }
+        let readout = RightKeyReadout::from_bytes(
+            debouncer.debounce(now.as_millis(), raw_readout.as_bytes()),
+        );
+        // the left half groups the counts into steps, at the resolution of its profile
+        #[cfg(feature = "rotary-encoder")]
+        let encoder_counts = right_encoder.take_counts();
+        #[cfg(not(feature = "rotary-encoder"))]
+        let encoder_counts: i8 = 0;
+        if previous_readout != readout || encoder_counts != 0 {
             previous_readout = readout;
-            let bytes = previous_readout.as_ne_bytes();
+            let readout_bytes = previous_readout.as_ne_bytes();
             // let bytes = [0b10000000, 0b01000000, 0b00100000, 0b00010000];
+            let bytes = [
+                readout_bytes[0],
+                readout_bytes[1],
+                readout_bytes[2],
+                readout_bytes[3],
+                encoder_counts as u8,
+            ];
             device
                 .write_async(LEFT_KEYBOARD_ADDRESS, bytes)
                 .await
                 .unwrap();
         }
+        // going idle, the left half keeps the last sent (all keys up) readout until we wake up
+        #[cfg(not(feature = "pio-scan"))]
+        if previous_readout.is_empty() && (now - last_activity).as_millis() >= IDLE_TIMEOUT_MS {
+            #[cfg(feature = "rotary-encoder")]
+            select(
+                key_io_handler.wait_for_key_press(),
+                right_encoder.wait_for_counts(),
+            )
+            .await;
+            #[cfg(not(feature = "rotary-encoder"))]
+            key_io_handler.wait_for_key_press().await;
+            key_io_handler.release_rows();
+            last_activity = Instant::now();
+            continue;
+        }
+        #[cfg(not(feature = "pio-scan"))]
         Timer::after_millis(1).await;
     }
 }
@@ -0,0 +1,56 @@
+use std::fmt::Write;
+
+use keyboard_left::{
+    io_management::key_position::KeyPosition,
+    profiles_management::{
+        keyboard_profile::keyboard_profile::{KeyAction, Keymap},
+        keymap_blob::{self, KeymapBlob},
+        layers::LAYER_COUNT,
+    },
+};
+
+/// Room for the blob of a keymap, the macro pool of a blob cannot grow past 64 KiB.
+const MAX_BLOB_SIZE: usize = 128 * 1024;
+
+/// Returns the blob of the keymap, see [KeymapBlob].
+pub fn encode_keymap(keymap: &Keymap) -> Result<Vec<u8>, String> {
+    let mut out = vec![0; MAX_BLOB_SIZE];
+    match keymap_blob::encode(keymap, &mut out) {
+        Some(size) => {
+            out.truncate(size);
+            return Ok(out);
+        }
+        None => return Err("the macros of the keymap do not fit in a blob".to_string()),
+    }
+}
+
+/// Reads a blob written by [encode_keymap]. The bytes are kept until the simulator exits, as
+/// they would stay in flash.
+pub fn read_blob(bytes: Vec<u8>) -> Result<KeymapBlob, String> {
+    match KeymapBlob::new(bytes.leak()) {
+        Some(blob) => return Ok(blob),
+        None => {
+            return Err(
+                "not a keymap blob, or one with actions the firmware cannot carry out".to_string(),
+            );
+        }
+    }
+}
+
+/// Lists the actions of the keymap layer by layer, one key a line, leaving out the dead keys.
+pub fn describe(keymap: &Keymap) -> String {
+    let mut text = String::new();
+    for layer in 0..LAYER_COUNT {
+        let _ = writeln!(text, "layer {}", layer);
+        for position in KeyPosition::all() {
+            match keymap.action(layer, position) {
+                KeyAction::DeadKey => {}
+                action => {
+                    let key = format!("{:?}", position.location());
+                    let _ = writeln!(text, "  {:<16} {:?}", key, action);
+                }
+            }
+        }
+    }
+    return text;
+}
@@ -0,0 +1,113 @@
+//! Replays a trace of key changes through the key processing of the left half firmware on the
+//! host, and prints the reports it sends along with the keys they press and release.
+//!
+//! Usage: `keyboard-simulator [--keymap <blob>] [trace file]`, the trace is read from stdin when
+//! no file is given. See [trace::parse_trace] for the format. With `--keymap`, the keys are
+//! looked up in the keymap blob rather than in the keymap of the profile.
+//!
+//! Keymap blobs, see [KeymapBlob](keyboard_left::profiles_management::keymap_blob::KeymapBlob),
+//! are written and read back with:
+//! - `keyboard-simulator encode-keymap <blob>`: writes the blob of the keymap of the profile,
+//! - `keyboard-simulator decode-keymap <blob>`: lists the actions of the blob.
+
+mod keymap;
+mod simulation;
+mod trace;
+
+use std::{
+    env, fs,
+    io::{self, Read},
+    process::ExitCode,
+};
+
+use keyboard_left::{
+    io_management::full_keyboard_manager::FullKeyboardManager,
+    profiles_management::{
+        keyboard_profile::keyboard_profile::Keymap, keymap_blob::KeymapBlob,
+        profiles::profile_1::profile_1::get_profile,
+    },
+    report_buffer::buffer::KeyboardRingBuffer,
+};
+
+use simulation::Simulation;
+use trace::parse_trace;
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let result = match args.as_slice() {
+        ["encode-keymap", path] => encode_keymap(path),
+        ["decode-keymap", path] => decode_keymap(path),
+        ["--keymap", blob_path, trace_path @ ..] => {
+            simulate(Some(blob_path), trace_path.first().copied())
+        }
+        trace_path => simulate(None, trace_path.first().copied()),
+    };
+    match result {
+        Ok(()) => return ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("{}", error);
+            return ExitCode::FAILURE;
+        }
+    }
+}
+
+fn encode_keymap(path: &str) -> Result<(), String> {
+    let blob = keymap::encode_keymap(&get_profile().keymap)?;
+    match fs::write(path, blob) {
+        Ok(()) => return Ok(()),
+        Err(error) => return Err(format!("cannot write the keymap blob: {}", error)),
+    }
+}
+
+fn decode_keymap(path: &str) -> Result<(), String> {
+    let blob = read_blob(path)?;
+    print!("{}", keymap::describe(&Keymap::Blob(blob)));
+    return Ok(());
+}
+
+fn read_blob(path: &str) -> Result<KeymapBlob, String> {
+    match fs::read(path) {
+        Ok(bytes) => return keymap::read_blob(bytes),
+        Err(error) => return Err(format!("cannot read the keymap blob: {}", error)),
+    }
+}
+
+fn simulate(blob_path: Option<&str>, trace_path: Option<&str>) -> Result<(), String> {
+    let text = match read_input(trace_path) {
+        Ok(text) => text,
+        Err(error) => return Err(format!("cannot read the trace: {}", error)),
+    };
+    let events = match parse_trace(&text) {
+        Ok(events) => events,
+        Err(error) => return Err(format!("invalid trace, {}", error)),
+    };
+
+    let mut profile = get_profile();
+    if let Some(path) = blob_path {
+        profile.keymap = Keymap::Blob(read_blob(path)?);
+    }
+    let mut ring_buffer = KeyboardRingBuffer::new();
+    let (report_producer, report_consumer) = ring_buffer.split();
+    let mut simulation = Simulation::new(
+        FullKeyboardManager::new(report_producer),
+        report_consumer,
+        &profile,
+        io::stdout().lock(),
+    );
+    match simulation.run(&events) {
+        Ok(()) => return Ok(()),
+        Err(error) => return Err(format!("cannot write the reports: {}", error)),
+    }
+}
+
+fn read_input(path: Option<&str>) -> io::Result<String> {
+    match path {
+        Some(path) => return fs::read_to_string(path),
+        None => {
+            let mut text = String::new();
+            io::stdin().read_to_string(&mut text)?;
+            return Ok(text);
+        }
+    }
+}
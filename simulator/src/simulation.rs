@@ -0,0 +1,140 @@
+use std::io::{self, Write};
+
+use keyboard_left::{
+    io_management::{
+        full_keyboard_manager::FullKeyboardManager, rotary_encoder::split_counts,
+        virtual_keyboard::VirtualKeyboard,
+    },
+    profiles_management::keyboard_profile::keyboard_profile::KeyboardProfile,
+    report_buffer::buffer::{QueuedReport, ReportConsumer},
+};
+use usbd_hid::descriptor::{KeyboardReport, KeyboardUsage};
+
+use crate::trace::{TimedEvent, TraceEvent};
+
+/// Usage of the left control key, the modifier bits of a report follow the usages from there.
+const FIRST_MODIFIER_USAGE: u8 = 0xE0;
+
+/// Replays a trace through a [VirtualKeyboard], which plays the part of the left half firmware
+/// on a virtual clock, and writes the reports it sends out.
+pub struct Simulation<'a, W: Write> {
+    keyboard: VirtualKeyboard<'a>,
+    profile: &'a KeyboardProfile,
+    /// Last report written to the host, to tell which keys the next one presses and releases.
+    host_report: KeyboardReport,
+    reports: usize,
+    output: W,
+}
+
+impl<'a, W: Write> Simulation<'a, W> {
+    pub fn new(
+        manager: FullKeyboardManager<'a>,
+        consumer: ReportConsumer<'a>,
+        profile: &'a KeyboardProfile,
+        output: W,
+    ) -> Self {
+        return Simulation {
+            keyboard: VirtualKeyboard::new(manager, consumer),
+            profile,
+            host_report: KeyboardReport::default(),
+            reports: 0,
+            output,
+        };
+    }
+
+    /// Feeds the events in order, then lets the macros triggered along the way play and the
+    /// timed modes expire until the end.
+    pub fn run(&mut self, events: &[TimedEvent]) -> io::Result<()> {
+        let profile = self.profile;
+        for event in events {
+            let mut sent = Vec::new();
+            self.keyboard
+                .run_until(event.time_us, profile, &mut |queued| sent.push(queued));
+            self.keyboard.change_keys(
+                profile,
+                |manager, now_us| feed(manager, profile, event, now_us),
+                &mut |queued| sent.push(queued),
+            );
+            self.write_reports(sent)?;
+        }
+        let mut sent = Vec::new();
+        self.keyboard
+            .run_until(u64::MAX, profile, &mut |queued| sent.push(queued));
+        self.write_reports(sent)?;
+        let consumer = self.keyboard.consumer();
+        writeln!(
+            self.output,
+            "# {} reports, {} coalesced, buffer high water mark {}",
+            self.reports,
+            consumer.overflows(),
+            consumer.high_water_mark()
+        )?;
+        return Ok(());
+    }
+
+    fn write_reports(&mut self, sent: Vec<QueuedReport>) -> io::Result<()> {
+        for queued in sent {
+            self.write_report(queued)?;
+        }
+        return Ok(());
+    }
+
+    /// Writes the report as `<time> ms  <modifier> <keycodes>  <keys pressed and released>`.
+    fn write_report(&mut self, queued: QueuedReport) -> io::Result<()> {
+        let report = queued.report.get_report();
+        write!(
+            self.output,
+            "{:>6}.{:03} ms  {:02x} ",
+            queued.timestamp_us / 1000,
+            queued.timestamp_us % 1000,
+            report.modifier
+        )?;
+        for keycode in report.keycodes {
+            write!(self.output, " {:02x}", keycode)?;
+        }
+        write!(self.output, " ")?;
+        let (held, previously_held) = (held_keys(&report), held_keys(&self.host_report));
+        for key in previously_held.iter().filter(|key| !held.contains(key)) {
+            write!(self.output, " -{}", key_name(*key))?;
+        }
+        for key in held.iter().filter(|key| !previously_held.contains(key)) {
+            write!(self.output, " +{}", key_name(*key))?;
+        }
+        writeln!(self.output)?;
+        self.host_report = report;
+        self.reports += 1;
+        return Ok(());
+    }
+}
+
+/// Hands the event over to the manager, at `now_us` on the virtual clock.
+fn feed(
+    manager: &mut FullKeyboardManager,
+    profile: &KeyboardProfile,
+    event: &TimedEvent,
+    now_us: u64,
+) {
+    match event.event {
+        TraceEvent::Left(readout) => manager.update_left_readout(readout, profile, now_us),
+        TraceEvent::Right(readout) => manager.update_right_readout(readout, profile, now_us),
+        TraceEvent::Encoder(location, counts) => {
+            let (direction, counts) = split_counts(counts);
+            manager.process_encoder(location, direction, counts, profile, now_us);
+        }
+    }
+}
+
+/// Returns the usages of the modifiers and keys held in the report.
+fn held_keys(report: &KeyboardReport) -> Vec<u8> {
+    let mut keys: Vec<u8> = (0..8)
+        .filter(|bit| report.modifier & (1 << bit) != 0)
+        .map(|bit| FIRST_MODIFIER_USAGE + bit)
+        .collect();
+    keys.extend(report.keycodes.iter().filter(|keycode| **keycode != 0));
+    return keys;
+}
+
+fn key_name(usage: u8) -> String {
+    let name = format!("{:?}", KeyboardUsage::from(usage));
+    return String::from(name.trim_start_matches("Keyboard"));
+}
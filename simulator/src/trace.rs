@@ -0,0 +1,120 @@
+use keyboard_left::io_management::{
+    left_half_manager::LeftReadout, right_half_manager::RightReadout,
+    rotary_encoder::EncoderLocation,
+};
+
+/// Marks the trace lines in a log of the firmware, built with `DEFMT_LOG=debug`.
+const TRACE_MARKER: &str = "trace:";
+
+/// A change seen by the left half firmware, as written in a trace.
+pub enum TraceEvent {
+    Left(LeftReadout),
+    Right(RightReadout),
+    /// Encoder turned by the given number of counts, negative counts being counter-clockwise.
+    Encoder(EncoderLocation, i8),
+}
+
+/// A line of a trace, along with the time it happened at.
+pub struct TimedEvent {
+    pub time_us: u64,
+    pub event: TraceEvent,
+}
+
+/// Parses a trace, one event per line:
+/// ```text
+/// # time  half   row_1 row_2 row_3 thumb
+/// 100     left   0 0 0 0b001
+/// 130ms   right  0b10000 0 0 0
+/// 2500us  encoder left -2
+/// ```
+/// Times are in milliseconds unless suffixed with `us`. Readout bytes can be decimal, `0x` hex or
+/// `0b` binary, and use the bit layout of [LeftReadout] and [RightReadout]. Empty lines and `#`
+/// comments are skipped. When the text holds `trace:` markers, as a log of the firmware does, only
+/// the lines holding one are read, from the marker on. The events are sorted by time.
+pub fn parse_trace(text: &str) -> Result<Vec<TimedEvent>, String> {
+    let from_log = text.contains(TRACE_MARKER);
+    let mut events = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line = match line.find(TRACE_MARKER) {
+            Some(pos) => &line[pos + TRACE_MARKER.len()..],
+            None if from_log => continue,
+            None => line,
+        };
+        let line = match line.find('#') {
+            Some(pos) => &line[..pos],
+            None => line,
+        };
+        // the firmware logs the readout bytes as an array
+        let line = line.replace(['[', ']', ','], " ");
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.is_empty() {
+            continue;
+        }
+        match parse_event(&fields) {
+            Ok(event) => events.push(event),
+            Err(error) => return Err(format!("line {}: {}", index + 1, error)),
+        }
+    }
+    // stable, events logged at the same time keep their order
+    events.sort_by_key(|event| event.time_us);
+    return Ok(events);
+}
+
+fn parse_event(fields: &[&str]) -> Result<TimedEvent, String> {
+    let time_us = parse_time_us(fields[0])?;
+    let event =
+        match fields.get(1..) {
+            Some(["left", bytes @ ..]) => {
+                let bytes = parse_readout_bytes(bytes)?;
+                TraceEvent::Left(LeftReadout::from_bytes(bytes))
+            }
+            Some(["right", bytes @ ..]) => {
+                let bytes = parse_readout_bytes(bytes)?;
+                TraceEvent::Right(RightReadout::new(bytes[0], bytes[1], bytes[2], bytes[3]))
+            }
+            Some(["encoder", location, counts]) => {
+                let location = match *location {
+                    "left" => EncoderLocation::Left,
+                    "right" => EncoderLocation::Right,
+                    _ => return Err(format!("unknown encoder `{}`", location)),
+                };
+                let counts = counts
+                    .parse()
+                    .map_err(|_| format!("invalid encoder counts `{}`", counts))?;
+                TraceEvent::Encoder(location, counts)
+            }
+            _ => return Err(String::from(
+                "expected `<time> left|right <4 bytes>` or `<time> encoder left|right <counts>`",
+            )),
+        };
+    return Ok(TimedEvent { time_us, event });
+}
+
+fn parse_time_us(field: &str) -> Result<u64, String> {
+    let (number, scale) = match field.strip_suffix("us") {
+        Some(number) => (number, 1),
+        None => (field.strip_suffix("ms").unwrap_or(field), 1000),
+    };
+    match number.parse::<u64>() {
+        Ok(time) => return Ok(time * scale),
+        Err(_) => return Err(format!("invalid time `{}`", field)),
+    }
+}
+
+fn parse_readout_bytes(fields: &[&str]) -> Result<[u8; 4], String> {
+    if fields.len() != 4 {
+        return Err(format!("expected 4 readout bytes, got {}", fields.len()));
+    }
+    let mut bytes = [0; 4];
+    for (byte, field) in bytes.iter_mut().zip(fields) {
+        let parsed = if let Some(hex) = field.strip_prefix("0x") {
+            u8::from_str_radix(hex, 16)
+        } else if let Some(binary) = field.strip_prefix("0b") {
+            u8::from_str_radix(binary, 2)
+        } else {
+            field.parse()
+        };
+        *byte = parsed.map_err(|_| format!("invalid readout byte `{}`", field))?;
+    }
+    return Ok(bytes);
+}
@@ -0,0 +1,59 @@
+//! Replays every trace of `traces/` through the simulator and compares the reports it prints
+//! with the `.expected` file next to the trace, with the keymap of the profile and with a keymap
+//! blob encoded from it.
+
+use std::{
+    ffi::OsStr,
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+const SIMULATOR: &str = env!("CARGO_BIN_EXE_keyboard-simulator");
+
+/// Returns the traces of `traces/`, in name order.
+fn traces() -> Vec<PathBuf> {
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("traces");
+    let mut traces: Vec<PathBuf> = fs::read_dir(directory)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension() == Some(OsStr::new("trace")))
+        .collect();
+    traces.sort();
+    assert!(!traces.is_empty(), "no trace to replay");
+    return traces;
+}
+
+/// Runs the simulator with the arguments and returns what it printed.
+fn simulate(args: &[&OsStr]) -> String {
+    let output = Command::new(SIMULATOR).args(args).output().unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    return String::from_utf8(output.stdout).unwrap();
+}
+
+#[track_caller]
+fn check_replay(trace: &Path, output: &str) {
+    let expected = fs::read_to_string(trace.with_extension("expected")).unwrap();
+    assert_eq!(output, expected, "{} replays differently", trace.display());
+}
+
+#[test]
+fn replays_the_traces_as_expected() {
+    for trace in traces() {
+        check_replay(&trace, &simulate(&[trace.as_os_str()]));
+    }
+}
+
+#[test]
+fn replays_the_traces_the_same_from_a_keymap_blob() {
+    let blob = Path::new(env!("CARGO_TARGET_TMPDIR")).join("keymap.bin");
+    simulate(&[OsStr::new("encode-keymap"), blob.as_os_str()]);
+    for trace in traces() {
+        let output = simulate(&[OsStr::new("--keymap"), blob.as_os_str(), trace.as_os_str()]);
+        check_replay(&trace, &output);
+    }
+}